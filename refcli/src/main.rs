@@ -1,93 +1,273 @@
 //! Reference client that demonstrates the stdin/out workload interface.
+//!
+//! Keys and values are kept as raw bytes. They are written as is by default,
+//! or percent-escaped after the runner negotiates the escaped encoding with
+//! an `ENCODING escaped` line (which gets echoed back).
+//!
+//! Calls tagged with a `#<id>` request ID get their responses held back until
+//! no more input is immediately available, then answered in reverse order,
+//! demonstrating that tagged calls may be answered out of order.
+//!
+//! Transactions between `TXN BEGIN` and `TXN COMMIT` lines are applied in
+//! one go; since calls are handled one at a time, they never conflict and
+//! never need to abort.
 
 use std::collections::BTreeMap;
 use std::error::Error;
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead, BufReader, Write};
 use std::str::SplitWhitespace;
 
 /// KV operation call type.
 enum KvCall {
-    Put { key: String, value: String },
-    Swap { key: String, value: String },
-    Get { key: String },
-    Scan { key_start: String, key_end: String },
-    Delete { key: String },
+    Put {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Swap {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Get {
+        key: Vec<u8>,
+    },
+    Scan {
+        key_start: Vec<u8>,
+        key_end: Vec<u8>,
+    },
+    Delete {
+        key: Vec<u8>,
+    },
+    Cas {
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        value: Vec<u8>,
+    },
+    Txn {
+        ops: Vec<KvCall>,
+    },
     Stop,
 }
 
 /// KV operation response type.
 enum KvResp {
     Put {
-        key: String,
+        key: Vec<u8>,
         found: bool,
     },
     Swap {
-        key: String,
-        old_value: Option<String>,
+        key: Vec<u8>,
+        old_value: Option<Vec<u8>>,
     },
     Get {
-        key: String,
-        value: Option<String>,
+        key: Vec<u8>,
+        value: Option<Vec<u8>>,
     },
     Scan {
-        key_start: String,
-        key_end: String,
-        entries: Vec<(String, String)>,
+        key_start: Vec<u8>,
+        key_end: Vec<u8>,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
     },
     Delete {
-        key: String,
+        key: Vec<u8>,
         found: bool,
     },
+    Cas {
+        key: Vec<u8>,
+        ok: bool,
+        current: Option<Vec<u8>>,
+    },
+    Txn {
+        results: Option<Vec<KvResp>>,
+    },
     Stop,
 }
 
-/// Get the next segment from an input line iterator.
-fn expect_next_seg(segs: &mut SplitWhitespace, line: &str) -> Result<String, io::Error> {
-    segs.next().map(|s| s.into()).ok_or(io::Error::new(
+/// Line negotiating the escaped encoding of keys and values.
+const ESCAPED_HANDSHAKE: &str = "ENCODING escaped";
+
+/// Decode a key or value segment, percent-escaped if `escaped`.
+fn decode_seg(seg: &str, escaped: bool, line: &str) -> Result<Vec<u8>, io::Error> {
+    if !escaped {
+        return Ok(seg.as_bytes().to_vec());
+    }
+    if seg == "%" {
+        return Ok(vec![]);
+    }
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid escaped segment in line: {}", line),
+        )
+    };
+    let mut bytes = vec![];
+    let mut iter = seg.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [
+                iter.next().ok_or_else(invalid)?,
+                iter.next().ok_or_else(invalid)?,
+            ];
+            let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Encode a key or value into a segment, percent-escaped if `escaped`.
+fn encode_seg(bytes: &[u8], escaped: bool) -> String {
+    if !escaped {
+        return String::from_utf8_lossy(bytes).into();
+    }
+    if bytes.is_empty() {
+        return "%".into();
+    }
+    if bytes == b"null" {
+        return "%6Eull".into();
+    }
+    let mut seg = String::new();
+    for &b in bytes {
+        if b.is_ascii_graphic() && b != b'%' {
+            seg.push(b as char);
+        } else {
+            seg.push_str(&format!("%{:02X}", b));
+        }
+    }
+    seg
+}
+
+/// Encode an optional value into a segment, `null` if not found.
+fn encode_value(value: Option<Vec<u8>>, escaped: bool) -> String {
+    match value {
+        Some(value) => encode_seg(&value, escaped),
+        None => "null".into(),
+    }
+}
+
+/// Get the next segment from an input line iterator as a key or value.
+fn expect_next_seg(
+    segs: &mut SplitWhitespace,
+    line: &str,
+    escaped: bool,
+) -> Result<Vec<u8>, io::Error> {
+    let seg = segs.next().ok_or(io::Error::new(
         io::ErrorKind::InvalidInput,
         format!("invalid input line: {}", line),
-    ))
+    ))?;
+    decode_seg(seg, escaped, line)
 }
 
-/// Parse an input line into a KV operation call.
-fn parse_input_call(line: &str) -> Result<KvCall, io::Error> {
+/// Get the next segment from an input line iterator as an optional value,
+/// where `null` means not found.
+fn expect_next_value(
+    segs: &mut SplitWhitespace,
+    line: &str,
+    escaped: bool,
+) -> Result<Option<Vec<u8>>, io::Error> {
+    if segs.clone().next() == Some("null") {
+        segs.next();
+        return Ok(None);
+    }
+    expect_next_seg(segs, line, escaped).map(Some)
+}
+
+/// Parse an input line into a KV operation call, along with its request ID
+/// tag if it has one.
+fn parse_input_call(line: &str, escaped: bool) -> Result<(Option<u64>, KvCall), io::Error> {
     let mut segs = line.split_whitespace();
+    let mut first = segs.next();
+    let mut tag = None;
+    if let Some(seg) = first.and_then(|seg| seg.strip_prefix('#')) {
+        tag = Some(seg.parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid request tag in line: {}", line),
+            )
+        })?);
+        first = segs.next();
+    }
 
-    match segs.next() {
+    let call = match first {
         Some("PUT") => Ok(KvCall::Put {
-            key: expect_next_seg(&mut segs, line)?,
-            value: expect_next_seg(&mut segs, line)?,
+            key: expect_next_seg(&mut segs, line, escaped)?,
+            value: expect_next_seg(&mut segs, line, escaped)?,
         }),
 
         Some("SWAP") => Ok(KvCall::Swap {
-            key: expect_next_seg(&mut segs, line)?,
-            value: expect_next_seg(&mut segs, line)?,
+            key: expect_next_seg(&mut segs, line, escaped)?,
+            value: expect_next_seg(&mut segs, line, escaped)?,
         }),
 
         Some("GET") => Ok(KvCall::Get {
-            key: expect_next_seg(&mut segs, line)?,
+            key: expect_next_seg(&mut segs, line, escaped)?,
         }),
 
         Some("SCAN") => Ok(KvCall::Scan {
-            key_start: expect_next_seg(&mut segs, line)?,
-            key_end: expect_next_seg(&mut segs, line)?,
+            key_start: expect_next_seg(&mut segs, line, escaped)?,
+            key_end: expect_next_seg(&mut segs, line, escaped)?,
         }),
 
         Some("DELETE") => Ok(KvCall::Delete {
-            key: expect_next_seg(&mut segs, line)?,
+            key: expect_next_seg(&mut segs, line, escaped)?,
         }),
 
+        Some("CAS") => Ok(KvCall::Cas {
+            key: expect_next_seg(&mut segs, line, escaped)?,
+            expected: expect_next_value(&mut segs, line, escaped)?,
+            value: expect_next_seg(&mut segs, line, escaped)?,
+        }),
+
+        // operations follow on their own lines, see `read_txn_ops()`
+        Some("TXN") if segs.next() == Some("BEGIN") => Ok(KvCall::Txn { ops: vec![] }),
+
         Some("STOP") => Ok(KvCall::Stop),
 
         _ => Err(io::Error::new(
             io::ErrorKind::InvalidInput,
             format!("invalid input line: {}", line),
         )),
+    }?;
+    Ok((tag, call))
+}
+
+/// Read the operations of a transaction from input, up to its `TXN COMMIT`
+/// line. Only untagged gets, puts, and deletes are allowed inside.
+fn read_txn_ops(stdin: &mut impl BufRead, escaped: bool) -> Result<Vec<KvCall>, io::Error> {
+    let mut ops = vec![];
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "input closed inside transaction",
+            ));
+        }
+        let segs: Vec<&str> = line.split_whitespace().collect();
+        match segs[..] {
+            [] => continue,
+            ["TXN", "COMMIT"] => return Ok(ops),
+            [tag, "TXN", "COMMIT"] if tag.starts_with('#') => return Ok(ops),
+            _ => {}
+        }
+        match parse_input_call(line.trim(), escaped)? {
+            (None, op @ (KvCall::Get { .. } | KvCall::Put { .. } | KvCall::Delete { .. })) => {
+                ops.push(op)
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid line in transaction: {}", line.trim()),
+                ))
+            }
+        }
     }
 }
 
 /// Handle a call (dummy logic).
-fn handle_kv_call(call: KvCall, state: &mut BTreeMap<String, String>) -> KvResp {
+fn handle_kv_call(call: KvCall, state: &mut BTreeMap<Vec<u8>, Vec<u8>>) -> KvResp {
     match call {
         KvCall::Put { key, value } => {
             let found = state.contains_key(&key);
@@ -107,7 +287,7 @@ fn handle_kv_call(call: KvCall, state: &mut BTreeMap<String, String>) -> KvResp
 
         KvCall::Scan { key_start, key_end } => {
             let entries = state
-                .range(key_start.clone()..key_end.clone())
+                .range(key_start.clone()..=key_end.clone())
                 .map(|(k, v)| (k.clone(), v.clone()))
                 .collect();
             KvResp::Scan {
@@ -122,88 +302,164 @@ fn handle_kv_call(call: KvCall, state: &mut BTreeMap<String, String>) -> KvResp
             KvResp::Delete { key, found }
         }
 
+        KvCall::Cas {
+            key,
+            expected,
+            value,
+        } => {
+            let current = state.get(&key).cloned();
+            let ok = current == expected;
+            if ok {
+                state.insert(key.clone(), value);
+            }
+            KvResp::Cas { key, ok, current }
+        }
+
+        KvCall::Txn { ops } => {
+            let results = ops
+                .into_iter()
+                .map(|op| handle_kv_call(op, state))
+                .collect();
+            KvResp::Txn {
+                results: Some(results),
+            }
+        }
+
         KvCall::Stop => KvResp::Stop,
     }
 }
 
-/// Produce an output KV response line, write to stdout directly.
-fn write_response(resp: KvResp, stdout: &mut io::StdoutLock) -> Result<(), io::Error> {
+/// Produce an output KV response line, prefixed by the request ID tag if
+/// given, write to stdout directly.
+fn write_response(
+    tag: Option<u64>,
+    resp: KvResp,
+    stdout: &mut io::StdoutLock,
+    escaped: bool,
+) -> Result<(), io::Error> {
+    let e = |bytes: &[u8]| encode_seg(bytes, escaped);
+    let t = match tag {
+        Some(tag) => format!("#{} ", tag),
+        None => String::new(),
+    };
+    write!(stdout, "{}", t)?;
     match resp {
         KvResp::Put { key, found } => writeln!(
             stdout,
             "PUT {} {}",
-            key,
+            e(&key),
             if found { "found" } else { "not_found" }
         ),
 
         KvResp::Swap { key, old_value } => writeln!(
             stdout,
             "SWAP {} {}",
-            key,
-            if let Some(old_value) = old_value {
-                old_value
-            } else {
-                "null".into()
-            }
+            e(&key),
+            encode_value(old_value, escaped)
         ),
 
-        KvResp::Get { key, value } => writeln!(
-            stdout,
-            "GET {} {}",
-            key,
-            if let Some(value) = value {
-                value
-            } else {
-                "null".into()
-            }
-        ),
+        KvResp::Get { key, value } => {
+            writeln!(stdout, "GET {} {}", e(&key), encode_value(value, escaped))
+        }
 
         KvResp::Scan {
             key_start,
             key_end,
             entries,
         } => {
-            writeln!(stdout, "SCAN {} {} BEGIN", key_start, key_end)?;
+            writeln!(stdout, "SCAN {} {} BEGIN", e(&key_start), e(&key_end))?;
             for (k, v) in entries {
-                writeln!(stdout, "  {} {}", k, v)?;
+                writeln!(stdout, "  {} {}", e(&k), e(&v))?;
             }
-            writeln!(stdout, "SCAN END")
+            writeln!(stdout, "{}SCAN END", t)
         }
 
         KvResp::Delete { key, found } => writeln!(
             stdout,
             "DELETE {} {}",
-            key,
+            e(&key),
             if found { "found" } else { "not_found" }
         ),
 
+        KvResp::Cas { key, ok, current } => writeln!(
+            stdout,
+            "CAS {} {} {}",
+            e(&key),
+            if ok { "ok" } else { "fail" },
+            encode_value(current, escaped)
+        ),
+
+        KvResp::Txn { results: None } => writeln!(stdout, "TXN ABORT"),
+
+        KvResp::Txn {
+            results: Some(results),
+        } => {
+            writeln!(stdout, "TXN BEGIN")?;
+            for result in results {
+                write_response(None, result, stdout, escaped)?;
+            }
+            writeln!(stdout, "{}TXN COMMIT", t)
+        }
+
         KvResp::Stop => writeln!(stdout, "STOP"),
     }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut stdin_handle = io::stdin().lock();
+    let mut stdin_handle = BufReader::new(io::stdin().lock());
     let mut stdout_handle = io::stdout().lock();
     let mut buffer = String::new();
 
     // a fake, non-server-side sorted map
     let mut state = BTreeMap::new();
+    let mut escaped = false;
+
+    // responses to tagged calls held back
+    let mut pending = vec![];
 
     loop {
         buffer.clear();
         loop {
-            stdin_handle.read_line(&mut buffer)?;
+            // answer held back calls before blocking for more input
+            if stdin_handle.buffer().is_empty() {
+                while let Some((tag, resp)) = pending.pop() {
+                    write_response(Some(tag), resp, &mut stdout_handle, escaped)?;
+                }
+            }
+
+            if stdin_handle.read_line(&mut buffer)? == 0 {
+                // stdin closed without STOP, exit as well
+                return Ok(());
+            }
             // skip empty lines
             if !buffer.trim().is_empty() {
                 break;
             }
         }
 
-        let call = parse_input_call(buffer.trim())?;
+        // switch to the escaped encoding if negotiated
+        if buffer.trim() == ESCAPED_HANDSHAKE {
+            escaped = true;
+            writeln!(stdout_handle, "{}", ESCAPED_HANDSHAKE)?;
+            continue;
+        }
+
+        let (tag, mut call) = parse_input_call(buffer.trim(), escaped)?;
+        if let KvCall::Txn { ops } = &mut call {
+            *ops = read_txn_ops(&mut stdin_handle, escaped)?;
+        }
         let resp = handle_kv_call(call, &mut state);
 
         let to_stop = matches!(resp, KvResp::Stop);
-        write_response(resp, &mut stdout_handle)?;
+        match tag {
+            Some(tag) if !to_stop => pending.push((tag, resp)),
+            _ => {
+                while let Some((tag, resp)) = pending.pop() {
+                    write_response(Some(tag), resp, &mut stdout_handle, escaped)?;
+                }
+                write_response(tag, resp, &mut stdout_handle, escaped)?;
+            }
+        }
         if to_stop {
             break;
         }
@@ -8,7 +8,9 @@ use color_print::cprintln;
 
 use clap::Parser;
 
-use runner::{ClientProc, RunnerError};
+use runner::{
+    kill_children_on_exit, ClientProc, Cluster, ClusterSpec, Encoding, KvResp, RunnerError,
+};
 
 // Hardcoded constants:
 const VALID_WORKLOADS: [char; 6] = ['a', 'b', 'c', 'd', 'e', 'f'];
@@ -30,6 +32,8 @@ struct Stats {
     lat_min: HashMap<String, f64>,
     lat_max: HashMap<String, f64>,
     lat_p99: HashMap<String, f64>,
+    num_errors: usize,  // calls answered with ERROR
+    num_unknown: usize, // calls answered with UNKNOWN
 }
 
 impl Stats {
@@ -43,6 +47,17 @@ impl Stats {
             lat_min: HashMap::new(),
             lat_max: HashMap::new(),
             lat_p99: HashMap::new(),
+            num_errors: 0,
+            num_unknown: 0,
+        }
+    }
+
+    /// Count the outcome of a call from its response.
+    fn count_resp(&mut self, resp: &KvResp) {
+        if resp.is_error() {
+            self.num_errors += 1;
+        } else if resp.is_unknown() {
+            self.num_unknown += 1;
         }
     }
 
@@ -73,6 +88,12 @@ impl Stats {
                 self.lat_p99[op]
             );
         }
+        if self.num_errors > 0 || self.num_unknown > 0 {
+            println!(
+                "    Failed:      errors {}  unknown {}",
+                self.num_errors, self.num_unknown
+            );
+        }
     }
 
     /// Merge with another stats struct, taking reasonable arithmetics on the
@@ -98,6 +119,9 @@ impl Stats {
             Self::merge_map(&mut self.lat_max, other.lat_max, f64::max);
             // for P99 latency, take the max, which should be reasonable
             Self::merge_map(&mut self.lat_p99, other.lat_p99, f64::max);
+            // take sum of failed calls count
+            self.num_errors += other.num_errors;
+            self.num_unknown += other.num_unknown;
         }
     }
 
@@ -136,6 +160,7 @@ fn ycsb_bench(
             load,
             client,
             ikeys.clone(),
+            args.pipeline,
         )?);
     }
     println!("  Launched {} YCSB drivers, now waiting...", drivers.len());
@@ -172,21 +197,67 @@ struct Args {
     /// Client `just` invocation arguments.
     #[arg(long, num_args(1..))]
     client_just_args: Vec<String>,
+
+    /// Number of tagged calls each client keeps in flight at once, answered
+    /// in any order; 1 makes calls one at a time untagged.
+    #[arg(long, default_value = "1")]
+    pipeline: usize,
+
+    /// Encoding of keys and values in the client stdin/out interface. The
+    /// escaped encoding gets negotiated with the clients, and lets YCSB
+    /// values go through verbatim, spaces included.
+    #[arg(long, value_enum, default_value = "ascii")]
+    encoding: Encoding,
+
+    /// Run against a self-managed local cluster of this topology, in the
+    /// format "<p2|p3>[:<param>=<value>,...]" with params "managers",
+    /// "partitions", "rf", and "port" (e.g., "p3:managers=3,partitions=2,rf=3").
+    /// Client `just` args default to connecting to it.
+    #[arg(long)]
+    cluster: Option<ClusterSpec>,
+
+    /// Seconds to wait for all nodes of the self-managed cluster to accept
+    /// connections.
+    #[arg(long, default_value = "60")]
+    cluster_timeout: u64,
 }
 
 fn main() -> Result<(), RunnerError> {
-    let args = Args::parse();
+    let mut args = Args::parse();
     cprintln!("<s><yellow>YCSB benchmark configuration:</></> {:#?}", args);
+    kill_children_on_exit();
     assert_ne!(args.num_clis, 0);
+    assert_ne!(args.pipeline, 0);
     assert!(VALID_WORKLOADS.contains(&args.workload));
 
+    // launch the self-managed cluster first if requested
+    let cluster = match &args.cluster {
+        Some(spec) => {
+            let cluster = Cluster::allocate(spec)?;
+            if args.client_just_args.is_empty() {
+                args.client_just_args = cluster.client_just_args.clone();
+            }
+            cprintln!("<s><yellow>Starting service nodes...</></>");
+            let servers = cluster.launch()?;
+            if let Err(err) = cluster.wait_ready(Duration::from_secs(args.cluster_timeout)) {
+                servers.stop()?;
+                return Err(err);
+            }
+            println!("  Launched:  {}", cluster.node_ids.join(" "));
+            Some((cluster, servers))
+        }
+        None => None,
+    };
+
     // YCSB benchmark load phase
     let (stats_load, ikeys_load) = {
         // run load-phase clients concurrently
         let mut clients_load = vec![];
         for _ in 0..args.num_clis {
-            let client =
-                ClientProc::new(args.client_just_args.iter().map(|s| s.as_str()).collect())?;
+            let client = ClientProc::new_with_encoding(
+                args.client_just_args.iter().map(|s| s.as_str()).collect(),
+                args.encoding,
+            )?;
             clients_load.push(client);
         }
 
@@ -204,8 +275,10 @@ fn main() -> Result<(), RunnerError> {
         // run run-phase clients concurrently
         let mut clients_run = vec![];
         for _ in 0..args.num_clis {
-            let client =
-                ClientProc::new(args.client_just_args.iter().map(|s| s.as_str()).collect())?;
+            let client = ClientProc::new_with_encoding(
+                args.client_just_args.iter().map(|s| s.as_str()).collect(),
+                args.encoding,
+            )?;
             clients_run.push(client);
         }
 
@@ -225,5 +298,10 @@ fn main() -> Result<(), RunnerError> {
     );
     stats_load.print("Load");
     stats_run.print("Run");
+
+    if let Some((cluster, servers)) = cluster {
+        servers.stop()?;
+        drop(cluster);
+    }
     Ok(())
 }
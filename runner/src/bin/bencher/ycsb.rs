@@ -12,7 +12,7 @@ use std::sync::mpsc;
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
-use runner::{ClientProc, KvCall, RunnerError};
+use runner::{ClientProc, Encoding, KvCall, RunnerError};
 
 use crate::{Stats, RESP_TIMEOUT};
 
@@ -36,6 +36,59 @@ const fn ycsb_profile(workload: char) -> &'static str {
     }
 }
 
+/// Tagged calls kept in flight to a KV client at once.
+struct Pipeline {
+    depth: usize,
+    next_tag: u64,
+    in_flight: usize,
+}
+
+impl Pipeline {
+    fn new(depth: usize) -> Self {
+        Pipeline {
+            depth,
+            next_tag: 0,
+            in_flight: 0,
+        }
+    }
+
+    /// Feed a call to the client, synchronously if the depth is 1, otherwise
+    /// tagged, waiting for any earlier one to be answered first if the
+    /// pipeline is full. Failed calls get counted into `stats`.
+    fn call(
+        &mut self,
+        client: &mut ClientProc,
+        call: KvCall,
+        stats: &mut Stats,
+    ) -> Result<(), RunnerError> {
+        // RESP_TIMEOUT should be long enough to prevent false negatives
+        if self.depth == 1 {
+            client.send_call(call)?;
+            stats.count_resp(&client.wait_resp(RESP_TIMEOUT)?);
+            return Ok(());
+        }
+        if self.in_flight == self.depth {
+            let (_, resp) = client.wait_any_resp(RESP_TIMEOUT)?;
+            stats.count_resp(&resp);
+            self.in_flight -= 1;
+        }
+        client.send_call_tagged(self.next_tag, call)?;
+        self.next_tag += 1;
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Wait for all calls in flight to be answered.
+    fn drain(&mut self, client: &mut ClientProc, stats: &mut Stats) -> Result<(), RunnerError> {
+        while self.in_flight > 0 {
+            let (_, resp) = client.wait_any_resp(RESP_TIMEOUT)?;
+            stats.count_resp(&resp);
+            self.in_flight -= 1;
+        }
+        Ok(())
+    }
+}
+
 /// Wrapper handle to a YCSB basic driver process.
 #[derive(Debug)]
 pub struct YcsbDriver {
@@ -47,13 +100,15 @@ pub struct YcsbDriver {
 impl YcsbDriver {
     /// Run a YCSB driver process that runs the specified workload for a number
     /// of operations, returning a handle to it. The driver translates YCSB
-    /// output and feeds them directly into a KV client.
+    /// output and feeds them directly into a KV client, keeping up to
+    /// `pipeline` calls in flight.
     pub(crate) fn exec(
         workload: char,
         num_ops: usize,
         load: bool, // true if 'load', false if 'run'
         client: ClientProc,
         ikeys: BTreeSet<String>,
+        pipeline: usize,
     ) -> Result<YcsbDriver, RunnerError> {
         let mut handle = Command::new(YCSB_BIN)
             .arg(if load { "load" } else { "run" })
@@ -71,7 +126,9 @@ impl YcsbDriver {
         // the basic driver, translates output lines into our KV operations,
         // and feeds them to the KV client
         let (signal_tx, signal_rx) = mpsc::channel();
-        let feeder = thread::spawn(move || Self::feeder_thread(stdout, client, ikeys, signal_tx));
+        let feeder = thread::spawn(move || {
+            Self::feeder_thread(stdout, client, ikeys, Pipeline::new(pipeline), signal_tx)
+        });
 
         Ok(YcsbDriver {
             handle,
@@ -120,6 +177,16 @@ impl YcsbDriver {
         Ok(value)
     }
 
+    /// Take the square-bracketed "value" verbatim from YCSB call line, i.e.,
+    /// all its fields as printed, for clients with the escaped encoding.
+    fn parse_ycsb_raw_value(line: &str) -> Result<String, RunnerError> {
+        let (_, fields) = line
+            .split_once(" [ ")
+            .ok_or(RunnerError::Parse("no value start bracket".into()))?;
+        let fields = fields.trim_end();
+        Ok(fields.strip_suffix(" ]").unwrap_or(fields).into())
+    }
+
     /// Parse the scan keys count from YCSB call line.
     fn parse_ycsb_scnt(segs: &mut SplitWhitespace) -> Result<usize, RunnerError> {
         let scnt = segs
@@ -139,23 +206,32 @@ impl YcsbDriver {
     }
 
     /// Parse a YCSB driver output line into a KV operation call, or `None` if
-    /// not a call line.
+    /// not a call line. Values are kept verbatim if `raw_values`, otherwise
+    /// spaces in them get substituted.
     fn interpret_ycsb_call(
         line: &str,
         ikeys: &mut BTreeSet<String>,
+        raw_values: bool,
     ) -> Result<Option<KvCall>, RunnerError> {
+        let parse_value = |segs: &mut SplitWhitespace| {
+            if raw_values {
+                Self::parse_ycsb_raw_value(line)
+            } else {
+                Self::parse_ycsb_value(segs)
+            }
+        };
         let mut segs = line.split_whitespace();
         match segs.next() {
             Some("INSERT") => {
                 let key = Self::parse_ycsb_key(&mut segs)?;
-                let value = Self::parse_ycsb_value(&mut segs)?;
+                let value = parse_value(&mut segs)?;
                 ikeys.insert(key.clone());
                 Ok(Some(KvCall::Put { key, value }))
             }
 
             Some("UPDATE") => {
                 let key = Self::parse_ycsb_key(&mut segs)?;
-                let value = Self::parse_ycsb_value(&mut segs)?;
+                let value = parse_value(&mut segs)?;
                 Ok(Some(KvCall::Swap { key, value }))
             }
 
@@ -230,6 +306,7 @@ impl YcsbDriver {
     fn feed_a_line(
         stdout: &mut BufReader<ChildStdout>,
         client: &mut ClientProc,
+        pipeline: &mut Pipeline,
         line: &mut String,
         ikeys: &mut BTreeSet<String>,
         stats: &mut Stats,
@@ -239,7 +316,8 @@ impl YcsbDriver {
 
         let size = stdout.read_line(line)?;
         if size == 0 {
-            // EOF reached, workload completed
+            // EOF reached, workload completed once all calls are answered
+            pipeline.drain(client, stats)?;
             *ended = true;
             return Ok(());
         }
@@ -248,11 +326,10 @@ impl YcsbDriver {
             return Ok(());
         }
 
-        if let Some(call) = Self::interpret_ycsb_call(line, ikeys)? {
-            // is an operation call, do it synchronously
-            // RESP_TIMEOUT should be long enough to prevent false negatives
-            client.send_call(call)?;
-            let _ = client.wait_resp(RESP_TIMEOUT)?;
+        let raw_values = client.encoding() == Encoding::Escaped;
+        if let Some(call) = Self::interpret_ycsb_call(line, ikeys, raw_values)? {
+            // is an operation call, feed it through the pipeline
+            pipeline.call(client, call, stats)?;
         } else if line.starts_with('[') {
             // might be a performance reporting line, record the number
             Self::record_ycsb_perf(line, stats)?;
@@ -270,6 +347,7 @@ impl YcsbDriver {
         stdout: ChildStdout,
         mut client: ClientProc,
         mut ikeys: BTreeSet<String>,
+        mut pipeline: Pipeline,
         signal: mpsc::Sender<()>,
     ) -> Option<(Stats, BTreeSet<String>)> {
         let mut stats = Stats::new();
@@ -283,6 +361,7 @@ impl YcsbDriver {
                 if let Err(err) = Self::feed_a_line(
                     &mut stdout,
                     &mut client,
+                    &mut pipeline,
                     line,
                     &mut ikeys,
                     &mut stats,
@@ -0,0 +1,94 @@
+//! Offline history checker utility.
+
+use color_print::cprintln;
+
+use clap::Parser;
+
+use runner::{
+    check_and_report, report_sessions, shrink_and_report, CheckOptions, CheckerKind, HistoryFile,
+    KvCall, RunnerError,
+};
+
+/// Checker utility arguments.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Path to a history file recorded by the fuzzer.
+    #[arg(long)]
+    history: String,
+
+    /// Consistency checker to run over the history.
+    #[arg(long, value_enum, default_value = "linearizable")]
+    checker: CheckerKind,
+
+    /// Require every scan to be explainable by a single point in time,
+    /// instead of each key in range independently.
+    #[arg(long, default_value = "false")]
+    scan_atomic: bool,
+
+    /// Also check which session guarantees (read-your-writes, monotonic
+    /// reads, monotonic writes, writes-follow-reads) each client got.
+    #[arg(long, default_value = "false")]
+    session_guarantees: bool,
+
+    /// Skip shrinking the history down to a minimal failing one on failure.
+    #[arg(long, default_value = "false")]
+    no_shrink: bool,
+}
+
+fn main() -> Result<(), RunnerError> {
+    let args = Args::parse();
+    cprintln!("<s><yellow>History check configuration:</></> {:#?}", args);
+
+    // load the recorded history
+    let history = HistoryFile::load(&args.history)?;
+    println!(
+        "  Loaded:  {} clients  {} ops  {} faults",
+        history.keys.len(),
+        history.records.len(),
+        history.faults.len()
+    );
+    if let Some(seed) = history.seed {
+        println!("  Fuzzing seed:  {}", seed);
+    }
+
+    let has_txns = history
+        .records
+        .iter()
+        .any(|r| matches!(r.call, KvCall::Txn { .. }));
+    if has_txns && args.checker != CheckerKind::Serializable {
+        return Err(RunnerError::Parse(
+            "history has transactions, which require '--checker serializable'".into(),
+        ));
+    }
+
+    // run the chosen checker over it
+    let opts = CheckOptions {
+        scan_atomic: args.scan_atomic,
+    };
+    cprintln!("<s><yellow>Checking history...</></>");
+    let passed = check_and_report(
+        args.checker,
+        &opts,
+        &history.keys,
+        &history.records,
+        "Check result",
+    );
+    if args.session_guarantees {
+        // per-session diagnostics, independent of the verdict
+        report_sessions(&history.records);
+    }
+    if !passed {
+        // shrink down to a minimal failing history for easier debugging
+        if !args.no_shrink {
+            shrink_and_report(
+                args.checker,
+                &opts,
+                &history.keys,
+                &history.records,
+                &history.faults,
+            );
+        }
+        std::process::exit(1);
+    }
+    Ok(())
+}
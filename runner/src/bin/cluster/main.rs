@@ -0,0 +1,75 @@
+//! Local cluster orchestrator utility.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use color_print::cprintln;
+
+use clap::Parser;
+
+use runner::{kill_children_on_exit, Cluster, ClusterSpec, RunnerError};
+
+/// Set by the signal handler upon SIGINT or SIGTERM.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Orchestrator utility arguments.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Cluster topology in the format "<p2|p3>[:<param>=<value>,...]" with
+    /// params "managers", "partitions", "rf", and "port" (base port to
+    /// allocate free ports from; picked by the OS if 0), for example,
+    /// "p3:managers=3,partitions=2,rf=3,port=3666".
+    cluster: ClusterSpec,
+
+    /// Seconds to wait for all nodes to accept connections.
+    #[arg(long, default_value = "60")]
+    startup_timeout: u64,
+}
+
+extern "C" fn on_stop_signal(_: libc::c_int) {
+    STOPPING.store(true, Ordering::Release);
+}
+
+/// Install the handler of SIGINT and SIGTERM. Nodes run in their own process
+/// groups, so they do not get a Ctrl-C themselves and must be torn down by
+/// us instead.
+fn install_stop_handler() {
+    let handler = on_stop_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+fn main() -> Result<(), RunnerError> {
+    let args = Args::parse();
+    cprintln!("<s><yellow>Cluster configuration:</></> {:#?}", args);
+    kill_children_on_exit();
+    install_stop_handler();
+
+    let cluster = Cluster::allocate(&args.cluster)?;
+    cprintln!("<s><yellow>Cluster layout:</></>");
+    for (node, addr) in cluster.node_ids.iter().zip(&cluster.node_addrs) {
+        println!("  {:<6}  {}", node, addr);
+    }
+    println!("  Backers under:  {}", cluster.tmpdir.display());
+    println!("  Service args:  {}", cluster.service_just_args.join(" "));
+    println!("  Client args:  {}", cluster.client_just_args.join(" "));
+
+    cprintln!("<s><yellow>Starting service nodes...</></>");
+    let servers = cluster.launch()?;
+    let ready = cluster.wait_ready(Duration::from_secs(args.startup_timeout));
+    if ready.is_ok() {
+        cprintln!("<s><yellow>Cluster ready, Ctrl-C to tear down...</></>");
+        while !STOPPING.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    cprintln!("<s><yellow>Tearing down cluster...</></>");
+    servers.stop()?;
+    drop(cluster);
+    ready
+}
@@ -0,0 +1,392 @@
+//! Durability audit utility over crash-restart cycles.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
+
+use color_print::cprintln;
+
+use clap::Parser;
+
+use rand::distr::{Alphanumeric, SampleString};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use runner::{
+    kill_children_on_exit, ClientProc, KvCall, KvResp, RunnerError, ServiceNodes, Signal,
+};
+
+// Hardcoded constants:
+const RESP_TIMEOUT: Duration = Duration::from_secs(60);
+const VALUE_TAG_LEN: usize = 8;
+const MAX_EXAMPLES: usize = 10;
+
+/// Durability audit utility arguments.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Number of concurrent clients.
+    #[arg(long, default_value = "4")]
+    num_clis: usize,
+
+    /// Number of keys written by each client.
+    #[arg(long, default_value = "2500")]
+    num_keys: usize,
+
+    /// Number of write operations per client in each cycle.
+    #[arg(long, default_value = "5000")]
+    num_ops: usize,
+
+    /// Number of crash-restart cycles to go through.
+    #[arg(long, default_value = "1")]
+    cycles: usize,
+
+    /// Seconds to wait for service nodes to come up after (re)starting them.
+    #[arg(long, default_value = "5")]
+    startup_wait: u64,
+
+    /// Seed of the audit's randomness; picked randomly if not given.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// IDs of service nodes to launch, crash, and restart, comma-separated
+    /// (e.g., "m,s0,s1").
+    #[arg(long, value_delimiter = ',', required = true)]
+    server_nodes: Vec<String>,
+
+    /// Service `just` invocation arguments for launching each node, with
+    /// every "{node}" replaced by the node ID. Restarted nodes are launched
+    /// with the same arguments, hence on the same backer directories.
+    #[arg(long, num_args(1..), required = true)]
+    server_just_args: Vec<String>,
+
+    /// Client `just` invocation arguments.
+    #[arg(long, num_args(1..), required = true)]
+    client_just_args: Vec<String>,
+}
+
+/// Provenance of a written value, encoded into the value itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Provenance {
+    cycle: usize,
+    cidx: usize,
+    seq: usize,
+}
+
+impl Provenance {
+    /// Tag a value with this provenance, followed by random characters.
+    fn tag_value(&self, rng: &mut StdRng) -> String {
+        format!(
+            "y{}c{}s{}r{}",
+            self.cycle,
+            self.cidx,
+            self.seq,
+            Alphanumeric.sample_string(rng, VALUE_TAG_LEN)
+        )
+    }
+
+    /// Recover the provenance of a tagged value.
+    fn of_value(value: &str) -> Option<Self> {
+        let rest = value.strip_prefix('y')?;
+        let (cycle, rest) = rest.split_once('c')?;
+        let (cidx, rest) = rest.split_once('s')?;
+        let (seq, _) = rest.split_once('r')?;
+        Some(Provenance {
+            cycle: cycle.parse().ok()?,
+            cidx: cidx.parse().ok()?,
+            seq: seq.parse().ok()?,
+        })
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cycle {} client {} write #{}",
+            self.cycle, self.cidx, self.seq
+        )
+    }
+}
+
+/// Last acknowledged write on a key.
+#[derive(Debug, Clone)]
+enum Acked {
+    /// Put or Swap of this value.
+    Value(String),
+    /// Delete, with the value it removed.
+    Deleted(String),
+}
+
+/// Key whose read-back state does not match its last acknowledged write.
+#[derive(Debug)]
+struct Anomaly {
+    key: String,
+    acked: Acked,
+    got: Option<String>,
+}
+
+impl Anomaly {
+    fn print(&self) {
+        let describe = |value: &str| match Provenance::of_value(value) {
+            Some(prov) => format!("{}  ({})", value, prov),
+            None => format!("{}  (unknown provenance)", value),
+        };
+        let expected = match &self.acked {
+            Acked::Value(value) => describe(value),
+            Acked::Deleted(value) => format!("null  (deleted {})", describe(value)),
+        };
+        let got = match &self.got {
+            Some(value) => describe(value),
+            None => "null".into(),
+        };
+        println!("    {}  acked {}", self.key, expected);
+        println!("    {:w$}  got   {}", "", got, w = self.key.len());
+    }
+}
+
+/// Key of given index owned by client.
+fn key_of(cidx: usize, idx: usize) -> String {
+    format!("dur{:02}k{:06}", cidx, idx)
+}
+
+/// Progress printing, once every 1% of total operations done.
+fn print_progress(what: &str, done: usize, total: usize) -> io::Result<()> {
+    if done.is_multiple_of((total / 100).max(1)) || done == total {
+        print!("  {}:  {} / {}\r", what, done, total);
+        io::stdout().flush()?;
+        if done == total {
+            println!();
+        }
+    }
+    Ok(())
+}
+
+/// Launch clients and wait a bit for them to get ready.
+fn launch_clients(args: &Args) -> Result<Vec<ClientProc>, RunnerError> {
+    let mut clients = vec![];
+    for _ in 0..args.num_clis {
+        let client = ClientProc::new(args.client_just_args.iter().map(|s| s.as_str()).collect())?;
+        clients.push(client);
+    }
+    thread::sleep(Duration::from_secs(
+        (0.3 * args.num_clis as f64).ceil() as u64
+    ));
+    Ok(clients)
+}
+
+/// Stop all clients.
+fn stop_clients(clients: Vec<ClientProc>) -> Result<(), RunnerError> {
+    for client in clients {
+        client.stop()?;
+    }
+    Ok(())
+}
+
+/// Issue random provenance-tagged writes on every client's own keys, each
+/// client keeping one call in flight, recording the last acknowledged write
+/// of every key into `acked`.
+fn write_phase(
+    args: &Args,
+    cycle: usize,
+    rng: &mut StdRng,
+    clients: &mut [ClientProc],
+    acked: &mut HashMap<String, Acked>,
+) -> Result<(), RunnerError> {
+    let total = args.num_ops * clients.len();
+    let mut done = 0;
+    for seq in 0..args.num_ops {
+        let mut pending = Vec::with_capacity(clients.len());
+        for (cidx, client) in clients.iter().enumerate() {
+            let key = key_of(cidx, rng.random_range(0..args.num_keys));
+            let value = Provenance { cycle, cidx, seq }.tag_value(rng);
+            let (call, if_acked) = match acked.get(&key) {
+                Some(Acked::Value(old)) if rng.random_bool(0.25) => (
+                    KvCall::Delete { key: key.clone() },
+                    Acked::Deleted(old.clone()),
+                ),
+                Some(Acked::Value(_)) if rng.random_bool(0.5) => (
+                    KvCall::Swap {
+                        key: key.clone(),
+                        value: value.clone(),
+                    },
+                    Acked::Value(value),
+                ),
+                _ => (
+                    KvCall::Put {
+                        key: key.clone(),
+                        value: value.clone(),
+                    },
+                    Acked::Value(value),
+                ),
+            };
+            client.send_call(call)?;
+            pending.push((key, if_acked));
+        }
+
+        // keys are disjoint across clients, so acknowledgements can be
+        // recorded in any order
+        for (client, (key, if_acked)) in clients.iter_mut().zip(pending) {
+            match (client.wait_resp(RESP_TIMEOUT)?, &if_acked) {
+                (KvResp::Put { .. } | KvResp::Swap { .. }, Acked::Value(_))
+                | (KvResp::Delete { .. }, Acked::Deleted(_)) => {
+                    acked.insert(key, if_acked);
+                }
+                (resp, _) => {
+                    return Err(RunnerError::Io(format!(
+                        "unexpected response to write: {:?}",
+                        resp
+                    )))
+                }
+            }
+            done += 1;
+            print_progress("Written", done, total)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read every key of every client back, returning the lost acknowledged
+/// writes and the resurrected deletes.
+fn read_phase(
+    args: &Args,
+    clients: &mut [ClientProc],
+    acked: &HashMap<String, Acked>,
+) -> Result<(Vec<Anomaly>, Vec<Anomaly>), RunnerError> {
+    let (mut lost, mut resurrected) = (vec![], vec![]);
+    let total = args.num_keys * clients.len();
+    let mut done = 0;
+    for idx in 0..args.num_keys {
+        for (cidx, client) in clients.iter().enumerate() {
+            client.send_call(KvCall::Get {
+                key: key_of(cidx, idx),
+            })?;
+        }
+
+        for (cidx, client) in clients.iter_mut().enumerate() {
+            let key = key_of(cidx, idx);
+            let got = match client.wait_resp(RESP_TIMEOUT)? {
+                KvResp::Get { value, .. } => value,
+                resp => {
+                    return Err(RunnerError::Io(format!(
+                        "unexpected response to read: {:?}",
+                        resp
+                    )))
+                }
+            };
+            match acked.get(&key) {
+                Some(Acked::Value(value)) if got.as_ref() != Some(value) => lost.push(Anomaly {
+                    key,
+                    acked: Acked::Value(value.clone()),
+                    got,
+                }),
+                Some(Acked::Deleted(removed)) if got.is_some() => resurrected.push(Anomaly {
+                    key,
+                    acked: Acked::Deleted(removed.clone()),
+                    got,
+                }),
+                _ => {}
+            }
+            done += 1;
+            print_progress("Read back", done, total)?;
+        }
+    }
+    Ok((lost, resurrected))
+}
+
+/// Print a category of anomalies with a few examples.
+fn print_anomalies(what: &str, anomalies: &[Anomaly]) {
+    if anomalies.is_empty() {
+        cprintln!("  {}:  <green>0</>", what);
+        return;
+    }
+    cprintln!("  {}:  <red>{}</>", what, anomalies.len());
+    for anomaly in anomalies.iter().take(MAX_EXAMPLES) {
+        anomaly.print();
+    }
+    if anomalies.len() > MAX_EXAMPLES {
+        println!("    ... and {} more", anomalies.len() - MAX_EXAMPLES);
+    }
+}
+
+fn main() -> Result<(), RunnerError> {
+    let args = Args::parse();
+    cprintln!(
+        "<s><yellow>Durability audit configuration:</></> {:#?}",
+        args
+    );
+    kill_children_on_exit();
+    assert_ne!(args.num_clis, 0);
+    assert_ne!(args.num_keys, 0);
+    assert!(args.num_clis < 100);
+    assert!(args.num_keys < 1000000);
+
+    let seed = args.seed.unwrap_or_else(|| rand::rng().random());
+    cprintln!("<s><yellow>Audit seed:</></> {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let startup_wait = Duration::from_secs(args.startup_wait);
+
+    cprintln!("<s><yellow>Starting service nodes...</></>");
+    let mut nodes = ServiceNodes::launch(&args.server_nodes, &args.server_just_args)?;
+    thread::sleep(startup_wait);
+
+    let mut acked = HashMap::new();
+    let mut passed = true;
+    for cycle in 0..args.cycles {
+        cprintln!(
+            "<s><yellow>Cycle {}: writing provenance-tagged keys...</></>",
+            cycle
+        );
+        let mut clients = launch_clients(&args)?;
+        write_phase(&args, cycle, &mut rng, &mut clients, &mut acked)?;
+        stop_clients(clients)?;
+
+        // crash every node at once without any chance of cleanup, then
+        // bring them back on the same backer directories
+        cprintln!(
+            "<s><yellow>Cycle {}: crashing all service nodes...</></>",
+            cycle
+        );
+        for i in 0..nodes.len() {
+            nodes.crash(i, Signal::Kill)?;
+        }
+        for i in 0..nodes.len() {
+            nodes.start(i)?;
+        }
+        println!("  Killed and restarted:  {}", args.server_nodes.join(" "));
+        thread::sleep(startup_wait);
+
+        cprintln!(
+            "<s><yellow>Cycle {}: reading everything back...</></>",
+            cycle
+        );
+        let mut clients = launch_clients(&args)?;
+        let (lost, resurrected) = read_phase(&args, &mut clients, &acked)?;
+        stop_clients(clients)?;
+
+        let num_live = acked
+            .values()
+            .filter(|a| matches!(a, Acked::Value(_)))
+            .count();
+        println!(
+            "  Audited:  {} keys acked with values  {} keys acked deleted",
+            num_live,
+            acked.len() - num_live
+        );
+        print_anomalies("Lost acknowledged writes", &lost);
+        print_anomalies("Resurrected deletes", &resurrected);
+        if !lost.is_empty() || !resurrected.is_empty() {
+            passed = false;
+            break; // later cycles would only pile up on the same anomalies
+        }
+    }
+
+    nodes.stop()?;
+    if passed {
+        cprintln!("<s><yellow>Durability audit result:</></> <green>PASSED</>");
+    } else {
+        cprintln!("<s><yellow>Durability audit result:</></> <red>FAILED</>");
+    }
+    Ok(())
+}
@@ -1,5 +1,6 @@
 //! Fuzz tester utility.
 
+use std::collections::HashMap;
 use std::io::{self, Write};
 use std::mem;
 use std::thread;
@@ -11,19 +12,27 @@ use clap::Parser;
 
 use bit_vec::BitVec;
 
-use runner::{ClientProc, KvResp, RunnerError};
+use rand::rngs::StdRng;
+
+use runner::{
+    check_and_report, kill_children_on_exit, report_inconsistent, report_remaining,
+    report_sessions, shrink_and_report, CheckOptions, CheckerKind, ClientProc, Cluster,
+    ClusterSpec, Encoding, History, HistoryWriter, KvCall, KvResp, NetemCtl, OpRecord, RunnerError,
+    ServiceNodes,
+};
 
 // Hardcoded constants:
-const KEY_LEN: usize = 8;
-const VALUE_LEN: usize = 16;
+const KEY_GEN_RETRIES: usize = 1000;
 const RESP_TIMEOUT: Duration = Duration::from_secs(60);
-const REMAIN_THRESH: usize = 1000;
 
 mod random;
 use random::*;
 
-mod history;
-use history::*;
+mod schedule;
+use schedule::*;
+
+mod nemesis;
+use nemesis::*;
 
 /// Statistics about the fuzz testing round.
 struct Stats {
@@ -32,7 +41,14 @@ struct Stats {
     cnt_get: usize,
     cnt_scan: usize,
     cnt_delete: usize,
+    cnt_cas: usize,
+    cnt_txn: usize,
+    cnt_error: usize,
+    cnt_unknown: usize,
+    cnt_aborted: usize,
     keys_freq: Vec<Vec<usize>>,
+    value_lens: (usize, usize, usize), // (min, sum, max)
+    cnt_values: usize,
 }
 
 impl Stats {
@@ -43,15 +59,42 @@ impl Stats {
             cnt_get: 0,
             cnt_scan: 0,
             cnt_delete: 0,
+            cnt_cas: 0,
+            cnt_txn: 0,
+            cnt_error: 0,
+            cnt_unknown: 0,
+            cnt_aborted: 0,
             keys_freq: keys.iter().map(|ks| vec![0; ks.len()]).collect(),
+            value_lens: (usize::MAX, 0, 0),
+            cnt_values: 0,
         }
     }
 
     fn print(&self) {
-        println!(
+        print!(
             "  Ops stats:  Put {}  Swap {}  Get {}  Scan {}  Delete {}",
             self.cnt_put, self.cnt_swap, self.cnt_get, self.cnt_scan, self.cnt_delete
         );
+        // opt-in operation types only show up if any were generated
+        if self.cnt_cas > 0 {
+            print!("  Cas {}", self.cnt_cas);
+        }
+        if self.cnt_txn > 0 {
+            print!("  Txn {}", self.cnt_txn);
+        }
+        println!();
+        if self.cnt_error > 0 || self.cnt_unknown > 0 || self.cnt_aborted > 0 {
+            println!(
+                "  Failed ops:  Error {}  Unknown {}  Aborted {}",
+                self.cnt_error, self.cnt_unknown, self.cnt_aborted
+            );
+        }
+        if let Some(avg) = self.value_lens.1.checked_div(self.cnt_values) {
+            println!(
+                "  Value lens:  min {}  avg {}  max {}",
+                self.value_lens.0, avg, self.value_lens.2
+            );
+        }
         for i in 0..self.keys_freq.len() {
             if i == 0 {
                 print!("  Keys freq:  ");
@@ -60,60 +103,158 @@ impl Stats {
             }
             println!("{:?}", self.keys_freq[i]);
         }
+
+        // realized distribution over key indices, aggregated across clients
+        let num_keys = self.keys_freq.iter().map(|f| f.len()).max().unwrap_or(0);
+        let agg: Vec<usize> = (0..num_keys)
+            .map(|k| self.keys_freq.iter().filter_map(|f| f.get(k)).sum())
+            .collect();
+        let total = agg.iter().sum::<usize>().max(1) as f64;
+        let shares: Vec<String> = agg
+            .iter()
+            .map(|&c| format!("{:.1}%", 100.0 * c as f64 / total))
+            .collect();
+        println!("  Keys dist:  [{}]", shares.join(", "));
+    }
+}
+
+/// Wait for the response to the tagged call of given tag from a client
+/// process, stashing away responses to its other calls in flight (whose tags
+/// are given) that come first.
+fn wait_tagged_resp(
+    client: &mut ClientProc,
+    arrived: &mut HashMap<u64, KvResp>,
+    tag: u64,
+    in_flight: &[u64],
+) -> Result<KvResp, RunnerError> {
+    loop {
+        if let Some(resp) = arrived.remove(&tag) {
+            return Ok(resp);
+        }
+        let (got_tag, resp) = client.wait_any_resp(RESP_TIMEOUT)?;
+        if !in_flight.contains(&got_tag) || arrived.contains_key(&got_tag) {
+            return Err(RunnerError::Io(format!(
+                "response with unexpected request tag: #{}",
+                got_tag
+            )));
+        }
+        arrived.insert(got_tag, resp);
     }
 }
 
 /// Fuzz testing logic, returning true if passed, else false. Returns the
 /// number of pending checks in the check queue upon seemingly successful
-/// test, or returns `None` if the test failed explicitly.
+/// test, or returns `None` if the test failed explicitly. Every acknowledged
+/// operation is pushed to `records`, and is also checked on the fly if using
+/// the approximate checker. If a history writer is given, every acknowledged
+/// operation is also written to it. Every call vs. wait decision is recorded
+/// into `schedule`. If a nemesis is injecting faults, it is kept up with the
+/// logical timestamps, and the faults it injects are written to the history
+/// writer as well. When pipelining, each client process hosts several
+/// sessions, which are the "clients" of the history, and their calls are
+/// tagged with their call timestamps.
+#[allow(clippy::too_many_arguments)]
 fn fuzz_test(
     args: &Args,
+    rng: &mut StdRng,
+    schedule: &mut Schedule,
     keys: &[Vec<String>],
     stats: &mut Stats,
     mut clients: Vec<ClientProc>,
+    records: &mut Vec<OpRecord>,
+    mut writer: Option<&mut HistoryWriter>,
+    mut nemesis: Option<&mut Nemesis>,
 ) -> Result<Option<usize>, RunnerError> {
     // use a bitmap to track which clients have on-the-fly requests; the fuzzer
     // randomly attempts to issue a new request or harvest a new response,
     // forcing the latter if all clients have on-the-fly requests
-    let mut flying = BitVec::from_elem(clients.len(), false);
+    let num_sessions = clients.len() * args.pipeline;
+    let mut flying = BitVec::from_elem(num_sessions, false);
 
     // use a monotonically increasing logical timestamp counter as the "physical"
     // timestamps of client requests
     let mut timestamp = 0;
-    let mut call_memo = vec![(0, None); clients.len()]; // (start_ts, put_value)
+    let mut call_memo: Vec<(u64, Option<KvCall>)> = vec![(0, None); num_sessions];
+
+    // last value each key got written with, for generating compare-and-swaps
+    let mut written = HashMap::new();
+
+    // responses to tagged calls that came before the ones being waited for
+    let mut arrived = HashMap::new();
 
     // per-key per-client update history for consistency checking
-    let mut history = History::new(clients.len(), keys);
+    let mut history = History::new(num_sessions, keys, args.scan_atomic);
+
+    // sampler of key indices following the key access distribution
+    let picker = KeyPicker::new(args.key_dist, args.num_keys);
 
     let total_ops = args.num_ops * args.num_clis;
     let mut ops_called = 0;
     let mut ops_waited = 0;
     let mut passed = true;
-    let mut fuzzer_stdout = io::stdout();
 
     while ops_waited < total_ops {
+        // log faults injected since the last operation event
+        if let Some(nemesis) = nemesis.as_mut() {
+            for fault in nemesis.poll(timestamp) {
+                if let Some(writer) = writer.as_mut() {
+                    writer.write_fault(fault)?;
+                }
+            }
+        }
         timestamp += 1;
 
-        if gen_call_vs_wait(ops_called, total_ops, &flying) {
+        if gen_call_vs_wait(rng, ops_called, total_ops, &flying) {
             // make a new call
-            let cidx = gen_rand_client(&flying, false);
-
-            let call = gen_rand_kvcall(&keys[cidx], stats, cidx);
-            call_memo[cidx] = (timestamp, call.update_info());
+            let cidx = gen_rand_client(rng, &flying, false);
+            schedule.record_call(cidx);
+
+            let call = gen_rand_kvcall(
+                rng,
+                &args.op_mix,
+                &picker,
+                &args.value_len,
+                args.encoding == Encoding::Escaped,
+                &keys[cidx],
+                &mut written,
+                stats,
+                cidx,
+            );
+            call_memo[cidx] = (timestamp, Some(call.clone()));
 
             // eprintln!("calling {:?} @ {}", call, timestamp);
-            clients[cidx].send_call(call)?;
+            let client = &clients[cidx / args.pipeline];
+            if args.pipeline > 1 {
+                client.send_call_tagged(timestamp, call)?;
+            } else {
+                client.send_call(call)?;
+            }
             // eprintln!("called");
 
             flying.set(cidx, true);
             ops_called += 1;
         } else {
             // harvest a response
-            let cidx = gen_rand_client(&flying, true);
+            let cidx = gen_rand_client(rng, &flying, true);
+            schedule.record_wait(cidx);
 
             // RESP_TIMEOUT should be long enough to prevent false negatives
             // eprintln!("waiting");
-            let resp = clients[cidx].wait_resp(RESP_TIMEOUT)?;
+            let resp = if args.pipeline > 1 {
+                let pidx = cidx / args.pipeline;
+                let in_flight: Vec<u64> = (pidx * args.pipeline..(pidx + 1) * args.pipeline)
+                    .filter(|&s| flying[s])
+                    .map(|s| call_memo[s].0)
+                    .collect();
+                wait_tagged_resp(
+                    &mut clients[pidx],
+                    &mut arrived,
+                    call_memo[cidx].0,
+                    &in_flight,
+                )?
+            } else {
+                clients[cidx].wait_resp(RESP_TIMEOUT)?
+            };
             // eprintln!("waited {:?} @ {}", resp, timestamp);
             if let KvResp::Stop = resp {
                 cprintln!(
@@ -125,53 +266,100 @@ fn fuzz_test(
                 break;
             }
 
-            // add to consistency violation check queue
-            let (ts_call, update_info) = mem::take(&mut call_memo[cidx]);
+            if resp.is_error() {
+                stats.cnt_error += 1;
+            } else if resp.is_unknown() {
+                stats.cnt_unknown += 1;
+            } else if let KvResp::Txn { results: None } = resp {
+                stats.cnt_aborted += 1;
+            }
+
+            let (ts_call, call) = mem::take(&mut call_memo[cidx]);
+            let call = call.unwrap();
             let ts_resp = timestamp;
-            history.add_to_queue(ts_call, ts_resp, resp);
-
-            // if is an update action, add to the update history, possibly
-            // triggering some pending checks
-            if let Some((update_key, update_value)) = update_info {
-                match history.apply_update(cidx, ts_call, ts_resp, update_key, update_value) {
-                    Some(Some(resp)) => {
-                        cprintln!(
-                            "<s><red>Consistency violation!</></>  Trigger:  client {}  <<{} - {}>>",
-                            cidx,
-                            ts_call,
-                            ts_resp
-                        );
-                        println!("  Resp: {:?}", resp);
-                        passed = false;
-                        break;
-                    }
-                    Some(None) => {
-                        cprintln!(
-                            "<s><red>Unexpected update key found:</></>  client {}  <<{} - {}>>",
+
+            // record the complete history for checking at the end and for
+            // shrinking upon failure
+            records.push(OpRecord {
+                cidx,
+                ts_call,
+                ts_resp,
+                call,
+                resp,
+            });
+            let record = records.last().unwrap();
+            if let Some(writer) = writer.as_mut() {
+                writer.write_record(record)?;
+            }
+
+            if args.checker == CheckerKind::Approx {
+                // the streaming check never sees the full history, so check
+                // each response against its own call as it arrives
+                if !record.is_self_consistent() {
+                    report_inconsistent(record);
+                    passed = false;
+                    break;
+                }
+
+                // add to consistency violation check queue
+                let update_info = record.update_info();
+                history.add_to_queue(ts_call, ts_resp, record.resp.clone());
+
+                // if is an update action, add to the update history, possibly
+                // triggering some pending checks; an update of unknown outcome
+                // may take effect at any later time
+                if let Some((update_key, update_value)) = update_info {
+                    let applied = if record.resp.is_unknown() {
+                        history.apply_indeterminate(
                             cidx,
                             ts_call,
-                            ts_resp
-                        );
-                        passed = false;
-                        break;
+                            ts_resp,
+                            update_key,
+                            update_value,
+                        )
+                    } else {
+                        history.apply_update(cidx, ts_call, ts_resp, update_key, update_value)
+                    };
+                    match applied {
+                        Some(Some(resp)) => {
+                            cprintln!(
+                                "<s><red>Consistency violation!</></>  Trigger:  client {}  <<{} - {}>>",
+                                cidx,
+                                ts_call,
+                                ts_resp
+                            );
+                            println!("  Resp: {:?}", resp);
+                            passed = false;
+                            break;
+                        }
+                        Some(None) => {
+                            cprintln!(
+                                "<s><red>Unexpected update key found:</></>  client {}  <<{} - {}>>",
+                                cidx,
+                                ts_call,
+                                ts_resp
+                            );
+                            passed = false;
+                            break;
+                        }
+                        None => {}
                     }
-                    None => {}
                 }
             }
 
             flying.set(cidx, false);
             ops_waited += 1;
+            print_progress(ops_called, ops_waited, total_ops)?;
+        }
+    }
 
-            // progress printing
-            if ops_waited % (total_ops / 100) == 0 || ops_waited == total_ops {
-                print!(
-                    "  Progress:  called {} / {}  waited {} / {}\r",
-                    ops_called, total_ops, ops_waited, total_ops
-                );
-                fuzzer_stdout.flush()?;
-                if ops_waited == total_ops {
-                    println!();
-                }
+    // stop injecting faults before stopping clients, so that none of them is
+    // left paused
+    if let Some(nemesis) = nemesis.as_mut() {
+        nemesis.halt()?;
+        for fault in nemesis.poll(timestamp) {
+            if let Some(writer) = writer.as_mut() {
+                writer.write_fault(fault)?;
             }
         }
     }
@@ -189,6 +377,21 @@ fn fuzz_test(
     })
 }
 
+/// Progress printing, once every 1% of total operations waited.
+fn print_progress(ops_called: usize, ops_waited: usize, total_ops: usize) -> io::Result<()> {
+    if ops_waited.is_multiple_of(total_ops / 100) || ops_waited == total_ops {
+        print!(
+            "  Progress:  called {} / {}  waited {} / {}\r",
+            ops_called, total_ops, ops_waited, total_ops
+        );
+        io::stdout().flush()?;
+        if ops_waited == total_ops {
+            println!();
+        }
+    }
+    Ok(())
+}
+
 /// Fuzzer utility arguments.
 #[derive(Parser, Debug)]
 struct Args {
@@ -196,6 +399,12 @@ struct Args {
     #[arg(long, default_value = "1")]
     num_clis: usize,
 
+    /// Number of tagged calls each client keeps in flight at once, answered
+    /// in any order. Above 1, each client process hosts that many sessions,
+    /// which count as separate clients for key pools and checking.
+    #[arg(long, default_value = "1")]
+    pipeline: usize,
+
     /// Number of keys touched by each client.
     #[arg(long, default_value = "5")]
     num_keys: usize,
@@ -208,39 +417,197 @@ struct Args {
     #[arg(long, default_value = "false")]
     conflict: bool,
 
+    /// Key length distribution in bytes: "<len>", "uniform:<min>:<max>", or
+    /// "hist:<len>=<weight>,..."; lengths may carry a "k" or "m" suffix.
+    #[arg(long, default_value = "8")]
+    key_len: SizeDist,
+
+    /// Value length distribution in bytes, in the same format as key length.
+    /// Large values are kept in the recorded history, so memory usage grows
+    /// with the average value length times the number of operations.
+    #[arg(long, default_value = "16")]
+    value_len: SizeDist,
+
+    /// Encoding of keys and values in the client stdin/out interface. The
+    /// escaped encoding gets negotiated with the clients, and makes values
+    /// mix in whitespace, control, and multi-byte characters, as well as the
+    /// empty string and the literal "null".
+    #[arg(long, value_enum, default_value = "ascii")]
+    encoding: Encoding,
+
+    /// Key access distribution over each client's keys: "uniform",
+    /// "zipfian:<theta>", or "hotspot:<frac>:<prob>".
+    #[arg(long, default_value = "uniform")]
+    key_dist: KeyDist,
+
+    /// Relative weights of operation types, as comma-separated `<op>=<weight>`
+    /// pairs; operations not listed get weight zero. Transactions ("txn") of
+    /// puts, gets, and deletes across keys can only be checked with the
+    /// serializable checker.
+    #[arg(long, default_value = "put=2,swap=2,get=3,scan=2,delete=1")]
+    op_mix: OpMix,
+
+    /// Consistency checker to run over the history.
+    #[arg(long, value_enum, default_value = "approx")]
+    checker: CheckerKind,
+
+    /// Require every scan to be explainable by a single point in time,
+    /// instead of each key in range independently.
+    #[arg(long, default_value = "false")]
+    scan_atomic: bool,
+
+    /// Also check which session guarantees (read-your-writes, monotonic
+    /// reads, monotonic writes, writes-follow-reads) each client got.
+    #[arg(long, default_value = "false")]
+    session_guarantees: bool,
+
+    /// Path to record the complete history to for offline checking.
+    #[arg(long)]
+    history_out: Option<String>,
+
+    /// Seed of all fuzzing randomness; picked randomly if not given. Reruns
+    /// with the same seed issue the identical operation sequence in the same
+    /// interleaving order.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Path to record the call vs. wait scheduling decisions to.
+    #[arg(long)]
+    schedule_out: Option<String>,
+
+    /// Skip shrinking the history down to a minimal failing one on failure.
+    #[arg(long, default_value = "false")]
+    no_shrink: bool,
+
     /// Client `just` invocation arguments.
     #[arg(long, num_args(1..))]
     client_just_args: Vec<String>,
+
+    /// IDs of service nodes for the fuzzer to launch and own, comma-separated
+    /// (e.g., "m,s0,s1"); if empty, the service is run separately.
+    #[arg(long, value_delimiter = ',')]
+    server_nodes: Vec<String>,
+
+    /// Service `just` invocation arguments for launching each owned node,
+    /// with every "{node}" replaced by the node ID.
+    #[arg(long, num_args(1..))]
+    server_just_args: Vec<String>,
+
+    /// Faults to inject while clients keep issuing operations, in the format
+    /// "<kill|term>:<node>@every=<dur>,restart-after=<dur>" to crash owned
+    /// service nodes, or "pause:<node|client>@every=<dur>,for=<dur>" to pause
+    /// owned service nodes or clients (named "c0", "c1", etc.). A name ending
+    /// in "*" picks a random eligible one with that prefix each time.
+    /// Use "<cut|cut-fwd|cut-rev>:<link>@every=<dur>,for=<dur>" to cut links
+    /// of the netem proxy at '--netem-ctl' in both directions, or only the
+    /// forward (towards the service) or reverse one.
+    /// Durations take a "ms", "s", or "m" suffix. May be given multiple times.
+    #[arg(long)]
+    nemesis: Vec<NemesisSpec>,
+
+    /// Run against a self-managed local cluster of this topology, in the
+    /// format "<p2|p3>[:<param>=<value>,...]" with params "managers",
+    /// "partitions", "rf", and "port" (e.g., "p3:managers=3,partitions=2,rf=3").
+    /// Its nodes are owned as if given through '--server-nodes', and client
+    /// `just` args default to connecting to it.
+    #[arg(long, conflicts_with_all = ["server_nodes", "server_just_args"])]
+    cluster: Option<ClusterSpec>,
+
+    /// Seconds to wait for all nodes of the self-managed cluster to accept
+    /// connections.
+    #[arg(long, default_value = "60")]
+    cluster_timeout: u64,
+
+    /// Control address of a netem proxy that clients and/or service nodes
+    /// talk through, for the nemesis to cut its links.
+    #[arg(long)]
+    netem_ctl: Option<String>,
 }
 
 fn main() -> Result<(), RunnerError> {
-    let args = Args::parse();
+    let mut args = Args::parse();
     cprintln!("<s><yellow>Fuzz testing configuration:</></> {:#?}", args);
+    kill_children_on_exit();
     assert_ne!(args.num_clis, 0);
+    assert_ne!(args.pipeline, 0);
     assert_ne!(args.num_keys, 0);
     assert!(args.num_keys < 100000);
     assert!(args.num_ops >= 1000);
 
+    // lay out the self-managed cluster if requested, owning its nodes
+    let cluster = match &args.cluster {
+        Some(spec) => Some(Cluster::allocate(spec)?),
+        None => None,
+    };
+    if let Some(cluster) = &cluster {
+        args.server_nodes = cluster.node_ids.clone();
+        args.server_just_args = cluster.service_just_args.clone();
+        if args.client_just_args.is_empty() {
+            args.client_just_args = cluster.client_just_args.clone();
+        }
+    }
+    if !args.server_nodes.is_empty() && args.server_just_args.is_empty() {
+        return Err(RunnerError::Parse(
+            "owned service nodes given without '--server-just-args'".into(),
+        ));
+    }
+    if args.op_mix.has_txns() && args.checker != CheckerKind::Serializable {
+        return Err(RunnerError::Parse(
+            "transactions in '--op-mix' require '--checker serializable'".into(),
+        ));
+    }
+    if args.nemesis.iter().any(|spec| spec.needs_nodes()) && args.server_nodes.is_empty() {
+        return Err(RunnerError::Parse(
+            "nemesis requires owned service nodes via '--server-nodes'".into(),
+        ));
+    }
+    if args.nemesis.iter().any(|spec| spec.needs_netem()) && args.netem_ctl.is_none() {
+        return Err(RunnerError::Parse(
+            "nemesis requires a netem proxy via '--netem-ctl'".into(),
+        ));
+    }
+
+    // seed all randomness for reproducibility
+    let seed = args.seed.unwrap_or_else(gen_rand_seed);
+    cprintln!("<s><yellow>Fuzzing seed:</></> {}", seed);
+    let mut rng = new_seeded_rng(seed);
+
     // generate proper pool of keys
-    let keys: Vec<Vec<String>> = (0..args.num_clis)
-        .map(|_| {
-            (0..args.num_keys)
-                .map(|i| {
-                    if args.conflict {
-                        format!("key{:0w$}", i, w = KEY_LEN - 3)
-                    } else {
-                        gen_rand_string(KEY_LEN)
-                    }
-                })
-                .collect()
-        })
-        .collect();
+    let keys = gen_keys_pool(
+        &mut rng,
+        args.num_clis * args.pipeline,
+        args.num_keys,
+        args.conflict,
+        &args.key_len,
+    )?;
     let mut stats = Stats::new(&keys);
 
+    // launch owned service nodes first if requested
+    if !args.server_nodes.is_empty() {
+        cprintln!("<s><yellow>Starting service nodes...</></>");
+    }
+    let servers = ServiceNodes::launch(&args.server_nodes, &args.server_just_args)?;
+    if let Some(cluster) = &cluster {
+        if let Err(err) = cluster.wait_ready(Duration::from_secs(args.cluster_timeout)) {
+            servers.stop()?;
+            return Err(err);
+        }
+        println!("  Launched:  {}", args.server_nodes.join(" "));
+        println!("  Backers under:  {}", cluster.tmpdir.display());
+    } else if !servers.is_empty() {
+        thread::sleep(Duration::from_secs(
+            (0.5 * servers.len() as f64).ceil() as u64
+        ));
+        println!("  Launched:  {}", args.server_nodes.join(" "));
+    }
+
     // run clients concurrently
     let mut clients = vec![];
     for _ in 0..args.num_clis {
-        let client = ClientProc::new(args.client_just_args.iter().map(|s| s.as_str()).collect())?;
+        let client = ClientProc::new_with_encoding(
+            args.client_just_args.iter().map(|s| s.as_str()).collect(),
+            args.encoding,
+        )?;
         clients.push(client);
     }
 
@@ -250,28 +617,122 @@ fn main() -> Result<(), RunnerError> {
     ));
     cprintln!("<s><yellow>Fuzzing starts...</></>");
 
+    // open history file for recording if requested
+    let mut writer = match &args.history_out {
+        Some(path) => Some(HistoryWriter::create(path, seed, &keys)?),
+        None => None,
+    };
+
+    // hand owned service nodes and clients to the nemesis if injecting
+    // faults; it draws from a separate stream of randomness so that the
+    // operation sequence stays the same as without it
+    let (mut nemesis, mut servers) = if !args.nemesis.is_empty() {
+        let nemesis_rng = new_seeded_rng(seed.wrapping_add(1));
+        let groups = clients.iter().map(|client| client.group()).collect();
+        let netem = match &args.netem_ctl {
+            Some(addr) => {
+                let mut ctl = NetemCtl::connect(addr)?;
+                let links = ctl.link_names()?;
+                Some((ctl, links))
+            }
+            None => None,
+        };
+        let nemesis = Nemesis::start(args.nemesis.clone(), servers, groups, netem, nemesis_rng);
+        (Some(nemesis), None)
+    } else {
+        (None, Some(servers))
+    };
+
     // run fuzz testing
-    let result = fuzz_test(&args, &keys, &mut stats, clients)?;
+    let mut records = vec![];
+    let mut schedule = Schedule::new(seed);
+    let result = fuzz_test(
+        &args,
+        &mut rng,
+        &mut schedule,
+        &keys,
+        &mut stats,
+        clients,
+        &mut records,
+        writer.as_mut(),
+        nemesis.as_mut(),
+    )?;
+    let mut faults = vec![];
+    if let Some(nemesis) = nemesis {
+        let num_polled = nemesis.num_faults();
+        let (owned, all_faults) = nemesis.finish()?;
+        if let Some(writer) = writer.as_mut() {
+            for fault in &all_faults[num_polled..] {
+                writer.write_fault(fault)?;
+            }
+        }
+        servers = Some(owned);
+        faults = all_faults;
+    }
+    if let Some(servers) = servers {
+        servers.stop()?;
+    }
+    drop(cluster);
     stats.print();
+    if !faults.is_empty() {
+        println!("  Faults injected:  {}", faults.len());
+    }
+    println!(
+        "  Schedule:  {} decisions  digest {:016x}",
+        schedule.len(),
+        schedule.digest()
+    );
+    if let Some(path) = &args.schedule_out {
+        schedule.write_to(path)?;
+        println!("  Schedule recorded to:  {}", path);
+    }
+    if let Some(mut writer) = writer {
+        writer.flush()?;
+        println!(
+            "  History recorded to:  {}",
+            args.history_out.as_ref().unwrap()
+        );
+    }
 
-    if let Some(remaining) = result {
-        if remaining >= REMAIN_THRESH {
-            // too many remaining checks, meaning some clients were not
-            // completing requests and were lagging too much behind others
-            cprintln!("<s><yellow>Fuzz testing result:</></> <red>UNFAIR</>");
-            println!(
-                "  Remaining checks queued:  {}  <s><red>too many!</></>",
-                remaining
-            );
-        } else {
-            // test (approximately) passed
-            cprintln!("<s><yellow>Fuzz testing result:</></> <green>PASSED</>");
-            println!("  Remaining checks queued:  {}  reasonable", remaining);
+    let opts = CheckOptions {
+        scan_atomic: args.scan_atomic,
+    };
+    let violated = match (result, args.checker) {
+        (Some(_), CheckerKind::Linearizable) => {
+            // run the full linearizability check over the recorded history
+            cprintln!("<s><yellow>Checking linearizability...</></>");
+            !check_and_report(args.checker, &opts, &keys, &records, "Fuzz testing result")
         }
-    } else {
-        // some check failed explicitly
-        cprintln!("<s><yellow>Fuzz testing result:</></> <red>FAILED</>");
-        println!("  Some check failed explicitly :-(");
+        (Some(_), CheckerKind::Classify) => {
+            // classify the strongest consistency level of the recorded history
+            cprintln!("<s><yellow>Classifying consistency level...</></>");
+            !check_and_report(args.checker, &opts, &keys, &records, "Fuzz testing result")
+        }
+        (Some(_), CheckerKind::Serializable) => {
+            // look for dependency cycles among the recorded transactions
+            cprintln!("<s><yellow>Checking serializability...</></>");
+            !check_and_report(args.checker, &opts, &keys, &records, "Fuzz testing result")
+        }
+        (Some(remaining), CheckerKind::Approx) => {
+            // an unfair run is not a violation, nothing to shrink
+            report_remaining(remaining, "Fuzz testing result");
+            false
+        }
+        (None, _) => {
+            // some check failed explicitly
+            cprintln!("<s><yellow>Fuzz testing result:</></> <red>FAILED</>");
+            println!("  Some check failed explicitly :-(");
+            true
+        }
+    };
+
+    if args.session_guarantees && !records.is_empty() {
+        // per-session diagnostics, independent of the verdict
+        report_sessions(&records);
+    }
+
+    if violated && !args.no_shrink {
+        shrink_and_report(args.checker, &opts, &keys, &records, &faults);
     }
     Ok(())
 }
@@ -0,0 +1,406 @@
+//! Crash/restart and pause fault injection into fuzzer-owned service nodes
+//! and clients, and link cuts through a netem proxy.
+
+use std::mem;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use runner::{
+    Direction, FaultAction, FaultRecord, NetemCmd, NetemCtl, ProcGroup, RunnerError, ServiceNodes,
+    Signal,
+};
+
+/// Sleep granularity of the nemesis thread between checking for due events.
+const NEMESIS_TICK: Duration = Duration::from_millis(20);
+
+/// Parse a duration with a "ms", "s", or "m" suffix.
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, RunnerError> {
+    let invalid = || RunnerError::Parse(format!("invalid duration '{}'", s));
+    let (num, unit_ms) = if let Some(num) = s.strip_suffix("ms") {
+        (num, 1)
+    } else if let Some(num) = s.strip_suffix('s') {
+        (num, 1000)
+    } else if let Some(num) = s.strip_suffix('m') {
+        (num, 60 * 1000)
+    } else {
+        return Err(invalid());
+    };
+    let num: u64 = num.parse().map_err(|_| invalid())?;
+    Ok(Duration::from_millis(num * unit_ms))
+}
+
+/// Which service node, client, or netem link a fault hits, where clients are
+/// named "c0", "c1", etc.
+#[derive(Debug, Clone)]
+pub(crate) enum Victim {
+    /// The node, client, or link of exactly this name.
+    Exact(String),
+    /// A randomly chosen eligible node, client, or link whose name starts
+    /// with this prefix.
+    Random(String),
+}
+
+impl Victim {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            Victim::Exact(exact) => name == exact,
+            Victim::Random(prefix) => name.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+/// Kind of fault injected by a nemesis spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FaultKind {
+    /// Crash a service node with the signal, restarting it later.
+    Crash(Signal),
+    /// Pause a service node or client, resuming it later.
+    Pause,
+    /// Cut a netem link in the direction, healing it later.
+    Cut(Direction),
+}
+
+/// Periodic fault specification, in one of the formats
+///   - "<kill|term>:<victim>@every=<dur>,restart-after=<dur>"
+///   - "pause:<victim>@every=<dur>,for=<dur>"
+///   - "<cut|cut-fwd|cut-rev>:<victim>@every=<dur>,for=<dur>"
+///
+/// where victim is a node ID, client name, or netem link name, or a prefix
+/// followed by "*" to pick a random eligible one among the matching ones each
+/// time. Only service nodes can be crashed, both nodes and clients can be
+/// paused, and only links can be cut.
+#[derive(Debug, Clone)]
+pub(crate) struct NemesisSpec {
+    kind: FaultKind,
+    victim: Victim,
+    every: Duration,
+    downtime: Duration,
+}
+
+impl NemesisSpec {
+    /// Returns true if the spec may hit service nodes, thus needs them owned.
+    pub(crate) fn needs_nodes(&self) -> bool {
+        match (&self.victim, self.kind) {
+            (_, FaultKind::Cut(_)) => false,
+            (Victim::Exact(name) | Victim::Random(name), kind) => {
+                kind != FaultKind::Pause || !name.starts_with('c')
+            }
+        }
+    }
+
+    /// Returns true if the spec cuts links, thus needs a netem proxy.
+    pub(crate) fn needs_netem(&self) -> bool {
+        matches!(self.kind, FaultKind::Cut(_))
+    }
+}
+
+impl FromStr for NemesisSpec {
+    type Err = RunnerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| RunnerError::Parse(format!("invalid nemesis '{}': {}", s, why));
+        let (fault, params) = s.split_once('@').ok_or_else(|| invalid("missing '@'"))?;
+        let (kind, victim) = fault
+            .split_once(':')
+            .ok_or_else(|| invalid("missing ':'"))?;
+
+        let (kind, downtime_param) = match kind {
+            "kill" => (FaultKind::Crash(Signal::Kill), "restart-after"),
+            "term" => (FaultKind::Crash(Signal::Term), "restart-after"),
+            "pause" => (FaultKind::Pause, "for"),
+            "cut" => (FaultKind::Cut(Direction::Both), "for"),
+            "cut-fwd" => (FaultKind::Cut(Direction::Fwd), "for"),
+            "cut-rev" => (FaultKind::Cut(Direction::Rev), "for"),
+            _ => return Err(invalid("fault must be 'kill', 'term', 'pause', or 'cut'")),
+        };
+        let victim = match victim.strip_suffix('*') {
+            Some(prefix) => Victim::Random(prefix.into()),
+            None if !victim.is_empty() => Victim::Exact(victim.into()),
+            None => return Err(invalid("empty victim")),
+        };
+
+        let (mut every, mut downtime) = (None, None);
+        for param in params.split(',') {
+            match param.split_once('=') {
+                Some(("every", dur)) => every = Some(parse_duration(dur)?),
+                Some((name, dur)) if name == downtime_param => {
+                    downtime = Some(parse_duration(dur)?)
+                }
+                _ => return Err(invalid(&format!("unknown param '{}'", param))),
+            }
+        }
+        let every = every.ok_or_else(|| invalid("missing 'every'"))?;
+        let downtime = downtime.ok_or_else(|| invalid(&format!("missing '{}'", downtime_param)))?;
+        if every.is_zero() {
+            return Err(invalid("'every' must be positive"));
+        }
+
+        Ok(NemesisSpec {
+            kind,
+            victim,
+            every,
+            downtime,
+        })
+    }
+}
+
+/// Service node, client, or netem link (by index) hit by a fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Node(usize),
+    Client(usize),
+    Link(usize),
+}
+
+/// Service nodes, clients, and netem links that faults get injected into.
+struct Targets {
+    servers: ServiceNodes,
+    clients: Vec<ProcGroup>,
+    netem: Option<(NetemCtl, Vec<String>)>,
+    paused: Vec<Target>,
+    cut: Vec<Target>,
+}
+
+impl Targets {
+    fn name(&self, target: Target) -> String {
+        match target {
+            Target::Node(i) => self.servers.node_id(i).into(),
+            Target::Client(c) => format!("c{}", c),
+            Target::Link(l) => self.netem.as_ref().unwrap().1[l].clone(),
+        }
+    }
+
+    fn group(&self, target: Target) -> Option<ProcGroup> {
+        match target {
+            Target::Node(i) => self.servers.group(i),
+            Target::Client(c) => Some(self.clients[c]),
+            Target::Link(_) => None,
+        }
+    }
+
+    /// Targets the spec may currently hit: nodes that are up for crashing,
+    /// nodes that are up or clients for pausing, and links for cutting, but
+    /// never ones that are paused or cut already.
+    fn candidates(&self, spec: &NemesisSpec) -> Vec<Target> {
+        let cands: Vec<Target> = match spec.kind {
+            FaultKind::Cut(_) => {
+                let num_links = self.netem.as_ref().map_or(0, |(_, links)| links.len());
+                (0..num_links).map(Target::Link).collect()
+            }
+            kind => {
+                let nodes = (0..self.servers.len())
+                    .filter(|&i| self.servers.is_up(i))
+                    .map(Target::Node);
+                let clients = (0..self.clients.len())
+                    .filter(|_| kind == FaultKind::Pause)
+                    .map(Target::Client);
+                nodes.chain(clients).collect()
+            }
+        };
+        cands
+            .into_iter()
+            .filter(|t| !self.paused.contains(t) && !self.cut.contains(t))
+            .filter(|&t| spec.victim.matches(&self.name(t)))
+            .collect()
+    }
+
+    /// Send a command to the netem proxy.
+    fn netem_send(&mut self, cmd: NetemCmd) -> Result<(), RunnerError> {
+        let (ctl, _) = self.netem.as_mut().unwrap();
+        ctl.send(&cmd)?;
+        Ok(())
+    }
+}
+
+/// Background thread injecting faults into owned service nodes, clients, and
+/// netem links following the nemesis specs, while clients keep issuing
+/// operations.
+pub(crate) struct Nemesis {
+    handle: Option<JoinHandle<Result<ServiceNodes, RunnerError>>>,
+    servers: Option<ServiceNodes>,
+    stop: Arc<AtomicBool>,
+    clock: Arc<AtomicU64>,
+    fault_rx: mpsc::Receiver<FaultRecord>,
+    faults: Vec<FaultRecord>,
+}
+
+impl Nemesis {
+    /// Start the nemesis thread, handing it ownership of the service nodes,
+    /// the process groups of the clients, and the netem proxy control
+    /// connection along with its link names.
+    pub(crate) fn start(
+        specs: Vec<NemesisSpec>,
+        servers: ServiceNodes,
+        clients: Vec<ProcGroup>,
+        netem: Option<(NetemCtl, Vec<String>)>,
+        rng: StdRng,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let clock = Arc::new(AtomicU64::new(0));
+        let (fault_tx, fault_rx) = mpsc::channel();
+
+        let targets = Targets {
+            servers,
+            clients,
+            netem,
+            paused: vec![],
+            cut: vec![],
+        };
+        let (stop_ref, clock_ref) = (stop.clone(), clock.clone());
+        let handle = thread::spawn(move || {
+            Self::nemesis_thread(specs, targets, rng, stop_ref, clock_ref, fault_tx)
+        });
+
+        Nemesis {
+            handle: Some(handle),
+            servers: None,
+            stop,
+            clock,
+            fault_rx,
+            faults: vec![],
+        }
+    }
+
+    /// Advance the logical clock that injected faults are stamped with to
+    /// the timestamp of the latest completed operation event, returning the
+    /// faults injected since the last poll.
+    pub(crate) fn poll(&mut self, timestamp: u64) -> &[FaultRecord] {
+        self.clock.store(timestamp, Ordering::Release);
+        let num_old = self.faults.len();
+        self.faults.extend(self.fault_rx.try_iter());
+        &self.faults[num_old..]
+    }
+
+    /// Number of faults injected so far.
+    pub(crate) fn num_faults(&self) -> usize {
+        self.faults.len()
+    }
+
+    /// Stop injecting faults, resuming everything paused and healing every
+    /// link cut. Crashed nodes are left down.
+    pub(crate) fn halt(&mut self) -> Result<(), RunnerError> {
+        if let Some(handle) = self.handle.take() {
+            self.stop.store(true, Ordering::Release);
+            self.servers = Some(handle.join().map_err(|_| RunnerError::Join)??);
+        }
+        Ok(())
+    }
+
+    /// Halt, returning ownership of the service nodes and all faults
+    /// injected.
+    pub(crate) fn finish(mut self) -> Result<(ServiceNodes, Vec<FaultRecord>), RunnerError> {
+        self.halt()?;
+        self.faults.extend(self.fault_rx.try_iter());
+        Ok((self.servers.take().unwrap(), mem::take(&mut self.faults)))
+    }
+
+    /// Nemesis thread function.
+    fn nemesis_thread(
+        specs: Vec<NemesisSpec>,
+        mut targets: Targets,
+        mut rng: StdRng,
+        stop: Arc<AtomicBool>,
+        clock: Arc<AtomicU64>,
+        fault_tx: mpsc::Sender<FaultRecord>,
+    ) -> Result<ServiceNodes, RunnerError> {
+        let start = Instant::now();
+        let mut next_fault: Vec<Instant> = specs.iter().map(|spec| start + spec.every).collect();
+        let mut recoveries: Vec<(Instant, Target)> = vec![];
+        let log_fault = |action, target: Target, targets: &Targets| {
+            let _ = fault_tx.send(FaultRecord {
+                ts: clock.load(Ordering::Acquire),
+                action,
+                target: targets.name(target),
+            });
+        };
+
+        while !stop.load(Ordering::Acquire) {
+            let now = Instant::now();
+
+            // bring back targets whose downtime has passed
+            let (due, pending) = recoveries.into_iter().partition(|&(at, _)| at <= now);
+            recoveries = pending;
+            for (_, target) in due {
+                Self::recover(&mut targets, target, &log_fault)?;
+            }
+
+            // hit a victim for every spec that is due
+            for (spec, next) in specs.iter().zip(next_fault.iter_mut()) {
+                if *next > now {
+                    continue;
+                }
+                *next = now + spec.every;
+                let cands = targets.candidates(spec);
+                if cands.is_empty() {
+                    continue; // all matching targets are down or paused already
+                }
+                let target = cands[rng.random_range(0..cands.len())];
+                match (spec.kind, target) {
+                    (FaultKind::Crash(sig), Target::Node(i)) => {
+                        targets.servers.crash(i, sig)?;
+                        let action = match sig {
+                            Signal::Term => FaultAction::Term,
+                            _ => FaultAction::Kill,
+                        };
+                        log_fault(action, target, &targets);
+                    }
+                    (FaultKind::Pause, _) => {
+                        if let Some(group) = targets.group(target) {
+                            group.pause()?;
+                            targets.paused.push(target);
+                            log_fault(FaultAction::Pause, target, &targets);
+                        }
+                    }
+                    (FaultKind::Cut(dir), Target::Link(_)) => {
+                        let link = targets.name(target);
+                        targets.netem_send(NetemCmd::Cut { link, dir })?;
+                        targets.cut.push(target);
+                        log_fault(FaultAction::Cut(dir), target, &targets);
+                    }
+                    _ => unreachable!(),
+                }
+                recoveries.push((now + spec.downtime, target));
+            }
+
+            thread::sleep(NEMESIS_TICK);
+        }
+
+        // never leave anything paused or cut behind
+        for (_, target) in recoveries {
+            if targets.paused.contains(&target) || targets.cut.contains(&target) {
+                Self::recover(&mut targets, target, &log_fault)?;
+            }
+        }
+        Ok(targets.servers)
+    }
+
+    /// Resume a paused target, heal a cut link, or restart a crashed node.
+    fn recover(
+        targets: &mut Targets,
+        target: Target,
+        log_fault: &impl Fn(FaultAction, Target, &Targets),
+    ) -> Result<(), RunnerError> {
+        if let Some(pos) = targets.paused.iter().position(|&t| t == target) {
+            targets.paused.swap_remove(pos);
+            if let Some(group) = targets.group(target) {
+                group.resume()?;
+                log_fault(FaultAction::Resume, target, targets);
+            }
+        } else if let Some(pos) = targets.cut.iter().position(|&t| t == target) {
+            targets.cut.swap_remove(pos);
+            let link = targets.name(target);
+            targets.netem_send(NetemCmd::Heal { link })?;
+            log_fault(FaultAction::Heal, target, targets);
+        } else if let Target::Node(i) = target {
+            targets.servers.start(i)?;
+            log_fault(FaultAction::Restart, target, targets);
+        }
+        Ok(())
+    }
+}
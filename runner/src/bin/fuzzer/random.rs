@@ -1,78 +1,531 @@
 //! Randomization related helpers.
 
+use std::collections::{HashMap, HashSet};
 use std::mem;
+use std::str::FromStr;
 
 use rand::distr::{Alphanumeric, SampleString};
-use rand::{rng, Rng};
+use rand::rngs::StdRng;
+use rand::{rng, Rng, SeedableRng};
 
 use bit_vec::BitVec;
 
-use super::{Stats, VALUE_LEN};
-use runner::KvCall;
+use super::{Stats, KEY_GEN_RETRIES};
+use runner::{KvCall, RunnerError};
+
+/// Relative weights of operation types in randomly generated calls.
+#[derive(Debug, Clone)]
+pub(crate) struct OpMix {
+    put: usize,
+    swap: usize,
+    get: usize,
+    scan: usize,
+    delete: usize,
+    cas: usize,
+    txn: usize,
+}
+
+impl OpMix {
+    /// Weights in the order of Put, Swap, Get, Scan, Delete, Cas, Txn.
+    fn weights(&self) -> [usize; 7] {
+        [
+            self.put,
+            self.swap,
+            self.get,
+            self.scan,
+            self.delete,
+            self.cas,
+            self.txn,
+        ]
+    }
+
+    /// True if transactions are in the mix.
+    pub(crate) fn has_txns(&self) -> bool {
+        self.txn > 0
+    }
+
+    /// Choose an operation type index for inside a transaction, among Put,
+    /// Get, and Delete following their weights, or evenly between Put and Get
+    /// if those are all zero.
+    fn choose_in_txn(&self, rng: &mut StdRng) -> usize {
+        let weights = match [self.put, self.get, self.delete] {
+            [0, 0, 0] => [1, 1, 0],
+            weights => weights,
+        };
+        let mut pick = gen_rand_index(rng, weights.iter().sum());
+        for (i, weight) in [0, 2, 4].into_iter().zip(weights) {
+            if pick < weight {
+                return i;
+            }
+            pick -= weight;
+        }
+        unreachable!()
+    }
+
+    /// Choose an operation type index following the weights.
+    fn choose(&self, rng: &mut StdRng) -> usize {
+        let weights = self.weights();
+        let mut pick = gen_rand_index(rng, weights.iter().sum());
+        for (i, weight) in weights.into_iter().enumerate() {
+            if pick < weight {
+                return i;
+            }
+            pick -= weight;
+        }
+        unreachable!()
+    }
+}
+
+impl FromStr for OpMix {
+    type Err = RunnerError;
+
+    /// Parse from a comma-separated list of `<op>=<weight>` pairs, e.g.,
+    /// "put=2,swap=2,get=3,scan=2,delete=1,cas=1,txn=1". Operations not listed
+    /// get weight zero.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mix = OpMix {
+            put: 0,
+            swap: 0,
+            get: 0,
+            scan: 0,
+            delete: 0,
+            cas: 0,
+            txn: 0,
+        };
+        for pair in s.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let (op, weight) = pair
+                .split_once('=')
+                .ok_or_else(|| RunnerError::Parse(format!("invalid op-mix entry '{}'", pair)))?;
+            let weight = weight.trim().parse()?;
+            match op.trim().to_lowercase().as_str() {
+                "put" => mix.put = weight,
+                "swap" => mix.swap = weight,
+                "get" => mix.get = weight,
+                "scan" => mix.scan = weight,
+                "delete" => mix.delete = weight,
+                "cas" => mix.cas = weight,
+                "txn" => mix.txn = weight,
+                _ => {
+                    return Err(RunnerError::Parse(format!(
+                        "unknown op-mix operation '{}'",
+                        op
+                    )))
+                }
+            }
+        }
+        if mix.weights().iter().sum::<usize>() == 0 {
+            return Err(RunnerError::Parse(
+                "op-mix weights must not be all zero".into(),
+            ));
+        }
+        Ok(mix)
+    }
+}
+
+/// Key access distribution over each client's pool of keys, in the order of
+/// key indices (lower indices are hotter under skewed distributions).
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum KeyDist {
+    /// Every key is equally likely.
+    Uniform,
+    /// Key of index i is accessed with probability proportional to
+    /// 1 / (i+1)^theta.
+    Zipfian(f64),
+    /// The first `frac` fraction of keys is accessed with total probability
+    /// `prob`, the rest of keys share the remaining probability.
+    Hotspot(f64, f64),
+}
+
+impl FromStr for KeyDist {
+    type Err = RunnerError;
+
+    /// Parse from "uniform", "zipfian:<theta>", or "hotspot:<frac>:<prob>".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let segs: Vec<&str> = s.trim().split(':').collect();
+        let dist = match (segs[0].to_lowercase().as_str(), &segs[1..]) {
+            ("uniform", []) => KeyDist::Uniform,
+            ("zipfian", [theta]) => KeyDist::Zipfian(theta.parse()?),
+            ("hotspot", [frac, prob]) => KeyDist::Hotspot(frac.parse()?, prob.parse()?),
+            _ => {
+                return Err(RunnerError::Parse(format!(
+                    "invalid key distribution '{}'",
+                    s
+                )))
+            }
+        };
+        match dist {
+            KeyDist::Zipfian(theta) if theta.is_nan() || theta < 0.0 => Err(RunnerError::Parse(
+                format!("zipfian theta must be non-negative: {}", theta),
+            )),
+            KeyDist::Hotspot(frac, prob)
+                if !(0.0..=1.0).contains(&frac) || !(0.0..=1.0).contains(&prob) =>
+            {
+                Err(RunnerError::Parse(format!(
+                    "hotspot fraction and probability must be in [0, 1]: {}",
+                    s
+                )))
+            }
+            _ => Ok(dist),
+        }
+    }
+}
+
+/// Sampler of key indices following a key access distribution.
+pub(crate) struct KeyPicker {
+    dist: KeyDist,
+    num_keys: usize,
+    /// Cumulative probabilities of key indices, for Zipfian only.
+    cdf: Vec<f64>,
+}
+
+impl KeyPicker {
+    pub(crate) fn new(dist: KeyDist, num_keys: usize) -> Self {
+        debug_assert_ne!(num_keys, 0);
+        let cdf = if let KeyDist::Zipfian(theta) = dist {
+            let mut acc = 0.0;
+            let mut cdf: Vec<f64> = (0..num_keys)
+                .map(|i| {
+                    acc += 1.0 / ((i + 1) as f64).powf(theta);
+                    acc
+                })
+                .collect();
+            cdf.iter_mut().for_each(|c| *c /= acc);
+            cdf
+        } else {
+            vec![]
+        };
+        KeyPicker {
+            dist,
+            num_keys,
+            cdf,
+        }
+    }
+
+    /// Pick a random key index following the distribution.
+    pub(crate) fn pick(&self, rng: &mut StdRng) -> usize {
+        match self.dist {
+            KeyDist::Uniform => gen_rand_index(rng, self.num_keys),
+            KeyDist::Zipfian(_) => {
+                let p: f64 = rng.random();
+                self.cdf.partition_point(|&c| c <= p).min(self.num_keys - 1)
+            }
+            KeyDist::Hotspot(frac, prob) => {
+                let num_hot =
+                    ((frac * self.num_keys as f64).ceil() as usize).clamp(1, self.num_keys);
+                if num_hot == self.num_keys || rng.random_bool(prob) {
+                    gen_rand_index(rng, num_hot)
+                } else {
+                    num_hot + gen_rand_index(rng, self.num_keys - num_hot)
+                }
+            }
+        }
+    }
+}
+
+/// Distribution of key or value lengths in bytes.
+#[derive(Debug, Clone)]
+pub(crate) enum SizeDist {
+    /// Always the same length.
+    Fixed(usize),
+    /// Uniformly random length in the inclusive range.
+    Uniform(usize, usize),
+    /// Length drawn from a histogram of `(length, weight)` buckets.
+    Histogram(Vec<(usize, usize)>),
+}
+
+/// Parse a size with an optional "k" or "m" (binary) unit suffix.
+fn parse_size(s: &str) -> Result<usize, RunnerError> {
+    let s = s.trim().to_lowercase();
+    let (num, unit) = if let Some(num) = s.strip_suffix('k') {
+        (num, 1 << 10)
+    } else if let Some(num) = s.strip_suffix('m') {
+        (num, 1 << 20)
+    } else {
+        (s.as_str(), 1)
+    };
+    Ok(num.parse::<usize>()? * unit)
+}
+
+impl FromStr for SizeDist {
+    type Err = RunnerError;
+
+    /// Parse from "<len>", "uniform:<min>:<max>", or
+    /// "hist:<len>=<weight>,<len>=<weight>,...", where lengths may carry a
+    /// "k" or "m" unit suffix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RunnerError::Parse(format!("invalid size distribution '{}'", s));
+        let dist = match s.trim().split_once(':') {
+            None => SizeDist::Fixed(parse_size(s)?),
+            Some(("uniform", range)) => {
+                let (min, max) = range.split_once(':').ok_or_else(invalid)?;
+                SizeDist::Uniform(parse_size(min)?, parse_size(max)?)
+            }
+            Some(("hist", buckets)) => SizeDist::Histogram(
+                buckets
+                    .split(',')
+                    .map(|bucket| {
+                        let (len, weight) = bucket.split_once('=').ok_or_else(invalid)?;
+                        Ok((parse_size(len)?, weight.trim().parse()?))
+                    })
+                    .collect::<Result<_, RunnerError>>()?,
+            ),
+            _ => return Err(invalid()),
+        };
+        let valid = match &dist {
+            SizeDist::Fixed(len) => *len > 0,
+            SizeDist::Uniform(min, max) => *min > 0 && min <= max,
+            SizeDist::Histogram(buckets) => {
+                buckets.iter().all(|(len, _)| *len > 0)
+                    && buckets.iter().map(|(_, weight)| weight).sum::<usize>() > 0
+            }
+        };
+        if valid {
+            Ok(dist)
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+/// Generate a random length following the size distribution.
+pub(crate) fn gen_rand_size(rng: &mut StdRng, dist: &SizeDist) -> usize {
+    match dist {
+        SizeDist::Fixed(len) => *len,
+        SizeDist::Uniform(min, max) => rng.random_range(*min..=*max),
+        SizeDist::Histogram(buckets) => {
+            let mut pick = gen_rand_index(rng, buckets.iter().map(|(_, w)| w).sum());
+            for (len, weight) in buckets {
+                if pick < *weight {
+                    return *len;
+                }
+                pick -= weight;
+            }
+            unreachable!()
+        }
+    }
+}
+
+/// Generate the pool of keys of each client, with lengths following the size
+/// distribution. With `conflict`, all clients share the same keys, named
+/// "key" followed by the zero-padded key index (longer than the drawn length
+/// if needed for uniqueness); otherwise, keys are random strings unique
+/// across all clients.
+pub(crate) fn gen_keys_pool(
+    rng: &mut StdRng,
+    num_clis: usize,
+    num_keys: usize,
+    conflict: bool,
+    key_len: &SizeDist,
+) -> Result<Vec<Vec<String>>, RunnerError> {
+    if conflict {
+        let keys: Vec<String> = (0..num_keys)
+            .map(|i| {
+                let len = gen_rand_size(rng, key_len);
+                format!("key{:0w$}", i, w = len.saturating_sub(3))
+            })
+            .collect();
+        return Ok(vec![keys; num_clis]);
+    }
+
+    let mut seen = HashSet::new();
+    (0..num_clis)
+        .map(|_| {
+            (0..num_keys)
+                .map(|_| {
+                    for _ in 0..KEY_GEN_RETRIES {
+                        let len = gen_rand_size(rng, key_len);
+                        let key = gen_rand_string(rng, len);
+                        if seen.insert(key.clone()) {
+                            return Ok(key);
+                        }
+                    }
+                    Err(RunnerError::Parse(format!(
+                        "key length distribution {:?} too narrow for unique keys",
+                        key_len
+                    )))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Pick a fresh random seed from the thread-local generator.
+pub(crate) fn gen_rand_seed() -> u64 {
+    rng().random()
+}
+
+/// Create the deterministic generator that all fuzzing randomness draws
+/// from, so that runs with the same seed are reproducible.
+pub(crate) fn new_seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
 
 /// Generate a random index in the range [0, max).
-pub(crate) fn gen_rand_index(max: usize) -> usize {
+pub(crate) fn gen_rand_index(rng: &mut StdRng, max: usize) -> usize {
     debug_assert_ne!(max, 0);
-    rng().random_range(0..max)
+    rng.random_range(0..max)
 }
 
 /// Generate a random alphanumeric string.
-pub(crate) fn gen_rand_string(len: usize) -> String {
+pub(crate) fn gen_rand_string(rng: &mut StdRng, len: usize) -> String {
     debug_assert_ne!(len, 0);
-    Alphanumeric.sample_string(&mut rng(), len)
+    Alphanumeric.sample_string(rng, len)
+}
+
+/// Maximum number of operations in a generated transaction.
+const TXN_MAX_OPS: usize = 4;
+
+/// Characters that only the escaped encoding can carry, beyond alphanumerics;
+/// all valid UTF-8, as the encoding carries text rather than raw bytes.
+const TEXT_CHARS: [char; 12] = [
+    ' ', '\n', '\t', '\r', '%', '\0', '\u{7f}', '[', ']', '"', '\u{e9}', '\u{4e2d}',
+];
+
+/// Generate a random UTF-8 string of given length in bytes, mixing
+/// alphanumerics with whitespace, control, and multi-byte characters. Once
+/// in a while, the string is instead empty or the literal "null".
+pub(crate) fn gen_rand_text_string(rng: &mut StdRng, len: usize) -> String {
+    match rng.random_range(0..32) {
+        0 => return String::new(),
+        1 => return "null".into(),
+        _ => {}
+    }
+    let mut s = String::with_capacity(len);
+    while s.len() < len {
+        let c = if rng.random_bool(0.5) {
+            Alphanumeric.sample_string(rng, 1).pop().unwrap()
+        } else {
+            TEXT_CHARS[rng.random_range(0..TEXT_CHARS.len())]
+        };
+        // never overshoot the length with a multi-byte character
+        if s.len() + c.len_utf8() <= len {
+            s.push(c);
+        }
+    }
+    s
 }
 
 /// Generate a random decision on call vs. wait resp.
-pub(crate) fn gen_call_vs_wait(ops_called: usize, num_ops: usize, flying: &BitVec) -> bool {
+pub(crate) fn gen_call_vs_wait(
+    rng: &mut StdRng,
+    ops_called: usize,
+    num_ops: usize,
+    flying: &BitVec,
+) -> bool {
     if ops_called == num_ops || flying.all() {
         false
     } else if flying.none() {
         true
     } else {
-        rng().random()
+        rng.random()
     }
 }
 
 /// Generate a random client index, retry until one with expected on-the-fly
 /// status is found.
-pub(crate) fn gen_rand_client(flying: &BitVec, want_flying: bool) -> usize {
+pub(crate) fn gen_rand_client(rng: &mut StdRng, flying: &BitVec, want_flying: bool) -> usize {
     debug_assert!(want_flying || !flying.all());
     debug_assert!(!want_flying || !flying.none());
     loop {
-        let cidx = gen_rand_index(flying.len());
+        let cidx = gen_rand_index(rng, flying.len());
         if flying.get(cidx).unwrap() == want_flying {
             return cidx;
         }
     }
 }
 
-/// Generate a random `KvCall` operation, updating statistics accordingly.
-pub(crate) fn gen_rand_kvcall(keys: &[String], stats: &mut Stats, cidx: usize) -> KvCall {
-    match gen_rand_index(10) {
-        0..=1 => {
-            let kidx = gen_rand_index(keys.len());
+/// Generate a random value with length following the size distribution,
+/// alphanumeric or with arbitrary UTF-8 text if `escaped`.
+fn gen_rand_value(rng: &mut StdRng, value_len: &SizeDist, escaped: bool) -> String {
+    let len = gen_rand_size(rng, value_len);
+    if escaped {
+        gen_rand_text_string(rng, len)
+    } else {
+        gen_rand_string(rng, len)
+    }
+}
+
+/// Generate a random value to be written, like `gen_rand_value()`, updating
+/// statistics accordingly.
+fn gen_value(rng: &mut StdRng, value_len: &SizeDist, escaped: bool, stats: &mut Stats) -> String {
+    let value = gen_rand_value(rng, value_len, escaped);
+    stats.value_lens.0 = stats.value_lens.0.min(value.len());
+    stats.value_lens.1 += value.len();
+    stats.value_lens.2 = stats.value_lens.2.max(value.len());
+    stats.cnt_values += 1;
+    value
+}
+
+/// Generate a random `KvCall` operation following the operation mix, key
+/// access distribution, and value size distribution, updating statistics
+/// accordingly. Values contain arbitrary UTF-8 text if `escaped`. The last
+/// value each key got written with by a generated call is kept in `written`,
+/// so that compare-and-swaps mostly expect a value the key may still hold.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn gen_rand_kvcall(
+    rng: &mut StdRng,
+    mix: &OpMix,
+    picker: &KeyPicker,
+    value_len: &SizeDist,
+    escaped: bool,
+    keys: &[String],
+    written: &mut HashMap<String, Option<String>>,
+    stats: &mut Stats,
+    cidx: usize,
+) -> KvCall {
+    let op = mix.choose(rng);
+    gen_kvcall_of(
+        rng, op, mix, picker, value_len, escaped, keys, written, stats, cidx,
+    )
+}
+
+/// Generate a random `KvCall` operation of the type at given index in the
+/// order of `OpMix::weights()`. A transaction gets between 2 and
+/// `TXN_MAX_OPS` operations on possibly different keys, whose types follow
+/// `OpMix::choose_in_txn()`; they count towards the statistics of their own
+/// types as well.
+#[allow(clippy::too_many_arguments)]
+fn gen_kvcall_of(
+    rng: &mut StdRng,
+    op: usize,
+    mix: &OpMix,
+    picker: &KeyPicker,
+    value_len: &SizeDist,
+    escaped: bool,
+    keys: &[String],
+    written: &mut HashMap<String, Option<String>>,
+    stats: &mut Stats,
+    cidx: usize,
+) -> KvCall {
+    match op {
+        0 => {
+            let kidx = picker.pick(rng);
             stats.cnt_put += 1;
             stats.keys_freq[cidx][kidx] += 1;
 
+            let value = gen_value(rng, value_len, escaped, stats);
+            written.insert(keys[kidx].clone(), Some(value.clone()));
             KvCall::Put {
                 key: keys[kidx].clone(),
-                value: gen_rand_string(VALUE_LEN),
+                value,
             }
         }
 
-        2..=3 => {
-            let kidx = gen_rand_index(keys.len());
+        1 => {
+            let kidx = picker.pick(rng);
             stats.cnt_swap += 1;
             stats.keys_freq[cidx][kidx] += 1;
 
+            let value = gen_value(rng, value_len, escaped, stats);
+            written.insert(keys[kidx].clone(), Some(value.clone()));
             KvCall::Swap {
                 key: keys[kidx].clone(),
-                value: gen_rand_string(VALUE_LEN),
+                value,
             }
         }
 
-        4..=6 => {
-            let kidx = gen_rand_index(keys.len());
+        2 => {
+            let kidx = picker.pick(rng);
             stats.cnt_get += 1;
             stats.keys_freq[cidx][kidx] += 1;
 
@@ -81,9 +534,9 @@ pub(crate) fn gen_rand_kvcall(keys: &[String], stats: &mut Stats, cidx: usize) -
             }
         }
 
-        7..=8 => {
-            let ksidx = gen_rand_index(keys.len());
-            let keidx = gen_rand_index(keys.len());
+        3 => {
+            let ksidx = picker.pick(rng);
+            let keidx = picker.pick(rng);
             let mut key_start = keys[ksidx].clone();
             let mut key_end = keys[keidx].clone();
             if key_end < key_start {
@@ -96,16 +549,52 @@ pub(crate) fn gen_rand_kvcall(keys: &[String], stats: &mut Stats, cidx: usize) -
             KvCall::Scan { key_start, key_end }
         }
 
-        9 => {
-            let kidx = gen_rand_index(keys.len());
+        4 => {
+            let kidx = picker.pick(rng);
             stats.cnt_delete += 1;
             stats.keys_freq[cidx][kidx] += 1;
 
+            written.insert(keys[kidx].clone(), None);
             KvCall::Delete {
                 key: keys[kidx].clone(),
             }
         }
 
+        5 => {
+            let kidx = picker.pick(rng);
+            stats.cnt_cas += 1;
+            stats.keys_freq[cidx][kidx] += 1;
+
+            // expect the last written value most of the time, or else some
+            // fresh value that is almost surely not there
+            let expected = if rng.random_bool(0.8) {
+                written.get(&keys[kidx]).cloned().flatten()
+            } else {
+                Some(gen_rand_value(rng, value_len, escaped))
+            };
+            let value = gen_value(rng, value_len, escaped, stats);
+            written.insert(keys[kidx].clone(), Some(value.clone()));
+            KvCall::Cas {
+                key: keys[kidx].clone(),
+                expected,
+                value,
+            }
+        }
+
+        6 => {
+            stats.cnt_txn += 1;
+            let num_ops = rng.random_range(2..=TXN_MAX_OPS);
+            let ops = (0..num_ops)
+                .map(|_| {
+                    let op = mix.choose_in_txn(rng);
+                    gen_kvcall_of(
+                        rng, op, mix, picker, value_len, escaped, keys, written, stats, cidx,
+                    )
+                })
+                .collect();
+            KvCall::Txn { ops }
+        }
+
         _ => panic!("random KvCall variant out of range"),
     }
 }
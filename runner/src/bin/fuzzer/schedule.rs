@@ -0,0 +1,76 @@
+//! Recording of call vs. wait scheduling decisions.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use runner::RunnerError;
+
+/// One scheduling decision made by the fuzzer at a logical timestamp.
+#[derive(Debug, Clone, Copy)]
+enum Decision {
+    Call(usize),
+    Wait(usize),
+}
+
+/// Sequence of scheduling decisions of a fuzz testing round. Given the same
+/// seed, a rerun makes the identical sequence of decisions, which can be
+/// verified by comparing digests.
+pub(crate) struct Schedule {
+    seed: u64,
+    decisions: Vec<Decision>,
+}
+
+impl Schedule {
+    pub(crate) fn new(seed: u64) -> Self {
+        Schedule {
+            seed,
+            decisions: vec![],
+        }
+    }
+
+    /// Record a decision of issuing a new call on client.
+    pub(crate) fn record_call(&mut self, cidx: usize) {
+        self.decisions.push(Decision::Call(cidx));
+    }
+
+    /// Record a decision of harvesting a response from client.
+    pub(crate) fn record_wait(&mut self, cidx: usize) {
+        self.decisions.push(Decision::Wait(cidx));
+    }
+
+    /// Number of decisions recorded.
+    pub(crate) fn len(&self) -> usize {
+        self.decisions.len()
+    }
+
+    /// FNV-1a digest over the sequence of decisions.
+    pub(crate) fn digest(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for decision in &self.decisions {
+            let (tag, cidx) = match decision {
+                Decision::Call(cidx) => (b'C', *cidx),
+                Decision::Wait(cidx) => (b'W', *cidx),
+            };
+            for byte in [tag].into_iter().chain((cidx as u64).to_le_bytes()) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    /// Write the schedule to a file, one decision per line in timestamp
+    /// order, after a line with the seed.
+    pub(crate) fn write_to(&self, path: &str) -> Result<(), RunnerError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "SEED {}", self.seed)?;
+        for decision in &self.decisions {
+            match decision {
+                Decision::Call(cidx) => writeln!(writer, "CALL {}", cidx)?,
+                Decision::Wait(cidx) => writeln!(writer, "WAIT {}", cidx)?,
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
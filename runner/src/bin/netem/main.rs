@@ -0,0 +1,60 @@
+//! Local fault-injecting TCP proxy utility.
+
+use std::io;
+use std::thread;
+
+use color_print::cprintln;
+
+use clap::Parser;
+
+use runner::{Netem, RunnerError};
+
+/// Proxy utility arguments.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Links to proxy, each in the format "<name>=<listen_addr>-><target_addr>",
+    /// for example, "s0=127.0.0.1:4700->127.0.0.1:3700". Clients (or peer
+    /// nodes) connecting to the listen address get forwarded to the target
+    /// address.
+    #[arg(required = true)]
+    links: Vec<String>,
+
+    /// Address to serve control commands on, one command per line.
+    #[arg(short, long, default_value = "127.0.0.1:3699")]
+    ctl_addr: String,
+}
+
+/// Parse a link argument into its name, listen address, and target address.
+fn parse_link(arg: &str) -> Result<(&str, &str, &str), RunnerError> {
+    let invalid = || RunnerError::Parse(format!("invalid link argument: {}", arg));
+    let (name, addrs) = arg.split_once('=').ok_or_else(invalid)?;
+    let (listen, target) = addrs.split_once("->").ok_or_else(invalid)?;
+    if name.is_empty() || listen.is_empty() || target.is_empty() {
+        return Err(invalid());
+    }
+    Ok((name, listen, target))
+}
+
+fn main() -> Result<(), RunnerError> {
+    let args = Args::parse();
+    cprintln!("<s><yellow>Netem proxy configuration:</></> {:#?}", args);
+
+    let netem = Netem::new();
+    for link in &args.links {
+        let (name, listen, target) = parse_link(link)?;
+        netem.add_link(name, listen, target)?;
+        println!("  Link {}: {} -> {}", name, listen, target);
+    }
+    netem.serve_control(&args.ctl_addr)?;
+    cprintln!(
+        "<s><yellow>Serving control commands on {} and stdin...</></>",
+        args.ctl_addr
+    );
+
+    // commands can also be typed in directly; keep proxying after stdin
+    // gets closed, until killed
+    netem.control_loop(io::stdin().lock(), &mut io::stdout())?;
+    loop {
+        thread::park();
+    }
+}
@@ -0,0 +1,74 @@
+//! Unix-domain control socket of the launcher.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use runner::RunnerError;
+
+use crate::supervise::Supervisor;
+
+/// Control socket being served, removed from the filesystem when dropped.
+pub(crate) struct ControlSocket {
+    path: String,
+}
+
+impl ControlSocket {
+    /// Serve control commands to the supervisor on a socket at given path,
+    /// replacing a stale socket file left there.
+    pub(crate) fn serve(path: &str, sup: Arc<Supervisor>) -> Result<Self, RunnerError> {
+        if Path::new(path).exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(RunnerError::Io(format!(
+                    "control socket {} is in use by another launcher",
+                    path
+                )));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sup = sup.clone();
+                thread::spawn(move || {
+                    if let Err(err) = control_session(&sup, stream) {
+                        eprintln!("Error in service control: {}", err);
+                    }
+                });
+            }
+        });
+        Ok(ControlSocket { path: path.into() })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// One control connection session, handling commands until EOF.
+fn control_session(sup: &Supervisor, stream: UnixStream) -> Result<(), RunnerError> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse().and_then(|cmd| sup.command(cmd)) {
+            Ok(infos) => {
+                for info in infos {
+                    writeln!(writer, "{}", info)?;
+                }
+                writeln!(writer, "OK")?;
+            }
+            Err(err) => writeln!(writer, "ERR {}", err)?,
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
@@ -1,14 +1,26 @@
 //! Service launcher utility.
 
+use std::time::Duration;
+
 use color_print::cprintln;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use runner::{ClusterConfig, NodeId, NodeRole, RunnerError, ServiceCmd, ServiceCtl, Topology};
+
+mod control;
+use control::ControlSocket;
 
-use runner::{RunnerError, ServerProc};
+mod supervise;
+use supervise::{EventLog, RestartPolicy, SuperviseOptions, Supervisor};
 
 /// Launcher utility arguments.
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Node ID I'm running on in the cluster. The format is as follows:
     ///   - Manager node is named "m". If there is replication, then the
     ///     replica ID follows after a '.', for example, "m.0", "m.1", etc.
@@ -17,42 +29,253 @@ struct Args {
     ///     replica ID follows after a '.', for example, "s0.0", "s0.1",
     ///     "s0.2", "s1.0", etc.
     #[arg(short, long, default_value = "s0")]
-    node_id: String,
+    node_id: NodeId,
 
-    /// Server `just` invocation arguments, if not "none".
+    /// Server `just` invocation arguments, if not "none". Placeholders get
+    /// replaced as follows, deriving from the node ID and the topology:
+    ///   - "{part}": partition ID
+    ///   - "{rep}": replica ID (0 if the node ID has none)
+    ///   - "{api_port}": port of the node's public listen address
+    ///   - "{p2p_port}": port of the node's internal replication address
+    ///   - "{peers}": internal replication addresses of the other replicas
+    ///     of the node's partition, comma-separated ("none" if there are no
+    ///     other replicas)
+    ///   - "{managers}", "{servers}": all managers' or servers' public listen
+    ///     addresses, comma-separated
+    ///   - "{server_rf}": server replication factor
+    ///   - "{backer}": the node's durable storage path
     #[arg(short, long, num_args(1..))]
     server_just_args: Vec<String>,
 
-    /// Manager `just` invocation arguments, if not "none".
+    /// Manager `just` invocation arguments, if not "none". Placeholders get
+    /// replaced the same way as for server args, among manager replicas.
     #[arg(short, long, num_args(1..))]
     manager_just_args: Vec<String>,
+
+    /// TOML cluster description file to take the topology, backer paths,
+    /// and default `just` args from, instead of the address list arguments.
+    #[arg(long, conflicts_with_all = [
+        "managers", "manager_p2ps", "servers", "server_p2ps", "server_rf", "backer_prefix"
+    ])]
+    config: Option<String>,
+
+    /// Manager replicas' public listen addresses, comma-separated.
+    #[arg(long, default_value = "")]
+    managers: String,
+
+    /// Manager replicas' internal replication addresses, comma-separated.
+    #[arg(long, default_value = "")]
+    manager_p2ps: String,
+
+    /// Server nodes' public listen addresses, comma-separated, indexed first
+    /// by partition then by replica.
+    #[arg(long, default_value = "")]
+    servers: String,
+
+    /// Server nodes' internal replication addresses, comma-separated, indexed
+    /// the same way as servers.
+    #[arg(long, default_value = "")]
+    server_p2ps: String,
+
+    /// Server replication factor of a partition.
+    #[arg(long, default_value = "1")]
+    server_rf: usize,
+
+    /// Prefix of durable storage paths, each followed by "." and the node ID.
+    #[arg(long)]
+    backer_prefix: Option<String>,
+
+    /// When to restart the process after it exits.
+    #[arg(long, value_enum, default_value = "never")]
+    restart: RestartPolicy,
+
+    /// Milliseconds to wait before restarting, doubled for each consecutive
+    /// restart of a process that exited quickly.
+    #[arg(long, default_value = "1000")]
+    restart_backoff_ms: u64,
+
+    /// Maximum milliseconds to wait before restarting. A process that stayed
+    /// up for at least this long starts over at the initial backoff.
+    #[arg(long, default_value = "30000")]
+    max_backoff_ms: u64,
+
+    /// Give up after this many restarts in total; unlimited if not given.
+    #[arg(long)]
+    max_restarts: Option<u32>,
+
+    /// File to append timestamped lifecycle events to, in addition to
+    /// printing them.
+    #[arg(long)]
+    event_log: Option<String>,
+
+    /// Directory to write the process's output to, as "<node>.stdout.log"
+    /// and "<node>.stderr.log". Output goes to the terminal if not given.
+    #[arg(long)]
+    log_dir: Option<String>,
+
+    /// Size in bytes beyond which an output log file gets rotated.
+    #[arg(long, default_value = "16777216")]
+    log_max_bytes: u64,
+
+    /// Number of rotated output log files to keep, as "<file>.1" (newest)
+    /// and onwards.
+    #[arg(long, default_value = "5")]
+    log_keep: usize,
+
+    /// Unix-domain socket path to accept control commands on, for crashing,
+    /// pausing, or restarting the process from a test driver or the shell.
+    #[arg(long)]
+    ctl_socket: Option<String>,
+}
+
+/// Launcher utility subcommands.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send a control command to a running launcher instead of launching.
+    Ctl {
+        /// Control socket path of the launcher.
+        socket: String,
+
+        /// One of "status", "kill", "term", "pause", "resume", or "restart".
+        command: ServiceCmd,
+    },
+}
+
+/// Send a control command to a launcher, printing the info lines replied.
+fn send_ctl(socket: &str, cmd: ServiceCmd) -> Result<(), RunnerError> {
+    let mut ctl = ServiceCtl::connect(socket)?;
+    for info in ctl.send(cmd)? {
+        println!("{}", info);
+    }
+    Ok(())
+}
+
+/// Replace the placeholders in `just` args with what they derive to for the
+/// node, failing if the topology lacks what a placeholder needs.
+fn derive_just_args(
+    just_args: &[String],
+    node: &NodeId,
+    topo: &Topology,
+    backer: Option<&str>,
+) -> Result<Vec<String>, RunnerError> {
+    let port_of = |addr: &str| addr.rsplit_once(':').map_or("", |(_, p)| p).to_string();
+    let mut derived = vec![];
+    for arg in just_args {
+        let mut arg = arg.clone();
+        if arg.contains("{part}") {
+            arg = arg.replace("{part}", &node.partition.to_string());
+        }
+        if arg.contains("{rep}") {
+            arg = arg.replace("{rep}", &node.rep_id().to_string());
+        }
+        if arg.contains("{api_port}") {
+            arg = arg.replace("{api_port}", &port_of(topo.api_addr(node)?));
+        }
+        if arg.contains("{p2p_port}") {
+            arg = arg.replace("{p2p_port}", &port_of(topo.p2p_addr(node)?));
+        }
+        if arg.contains("{peers}") {
+            let peers = topo.peers(node)?;
+            let peers = if peers.is_empty() {
+                "none".into()
+            } else {
+                peers.join(",")
+            };
+            arg = arg.replace("{peers}", &peers);
+        }
+        if arg.contains("{managers}") {
+            arg = arg.replace("{managers}", &topo.managers.join(","));
+        }
+        if arg.contains("{servers}") {
+            arg = arg.replace("{servers}", &topo.servers.join(","));
+        }
+        if arg.contains("{server_rf}") {
+            arg = arg.replace("{server_rf}", &topo.server_rf.to_string());
+        }
+        if arg.contains("{backer}") {
+            let backer = backer.ok_or_else(|| {
+                RunnerError::Parse(format!("no backer path configured for node {}", node))
+            })?;
+            arg = arg.replace("{backer}", backer);
+        }
+        derived.push(arg);
+    }
+    Ok(derived)
 }
 
 fn main() -> Result<(), RunnerError> {
     let args = Args::parse();
+    if let Some(Command::Ctl { socket, command }) = &args.command {
+        return send_ctl(socket, *command);
+    }
     cprintln!("<s><yellow>Service launch configuration:</></> {:#?}", args);
 
-    if args.node_id.is_empty() || (!args.node_id.starts_with('s') && !args.node_id.starts_with('m'))
-    {
-        return Err(RunnerError::Parse(format!(
-            "invalid 'node_id' argument: {}",
-            args.node_id
-        )));
+    let node = args.node_id;
+    let (role_name, mut just_args) = match node.role {
+        NodeRole::Manager => ("manager", args.manager_just_args),
+        NodeRole::Server => ("server", args.server_just_args),
+    };
+
+    // take everything from the cluster description file if given, with
+    // `just` args on the command line taking precedence
+    let (topo, backer) = match &args.config {
+        Some(path) => {
+            let config = ClusterConfig::load(path)?;
+            if just_args.is_empty() {
+                just_args = match node.role {
+                    NodeRole::Manager => config.manager_just_args.clone(),
+                    NodeRole::Server => config.server_just_args.clone(),
+                };
+            }
+            (config.topology()?, config.backer_of(&node)?)
+        }
+        None => (
+            Topology::new(
+                &args.managers,
+                &args.manager_p2ps,
+                &args.servers,
+                &args.server_p2ps,
+                args.server_rf,
+            )?,
+            args.backer_prefix
+                .map(|prefix| format!("{}.{}", prefix, node)),
+        ),
+    };
+    if just_args.is_empty() || just_args[0].to_lowercase() == "none" {
+        return Ok(());
     }
 
-    if args.node_id.starts_with('m') && args.manager_just_args[0].to_lowercase() != "none" {
-        // we are launching a manager
-        cprintln!("<s><yellow>Starting manager {}...</></>", args.node_id);
-        let manager = ServerProc::new(args.manager_just_args.iter().map(|s| s.as_str()).collect())?;
-        println!("  Launched...");
-        manager.wait()?;
-    } else if args.node_id.starts_with('s') && args.server_just_args[0].to_lowercase() != "none" {
-        // we are launching a server
-        cprintln!("<s><yellow>Starting server {}...</></>", args.node_id);
-        let server = ServerProc::new(args.server_just_args.iter().map(|s| s.as_str()).collect())?;
-        println!("  Launched...");
-        server.wait()?;
+    // derive the actual invocation, validating the node against the
+    // topology if its role's addresses are given
+    let listed = match node.role {
+        NodeRole::Manager => &topo.managers,
+        NodeRole::Server => &topo.servers,
+    };
+    if !listed.is_empty() {
+        topo.check_node(&node)?;
     }
+    let just_args = derive_just_args(&just_args, &node, &topo, backer.as_deref())?;
 
-    Ok(())
+    let opts = SuperviseOptions {
+        policy: args.restart,
+        backoff: Duration::from_millis(args.restart_backoff_ms),
+        max_backoff: Duration::from_millis(args.max_backoff_ms),
+        max_restarts: args.max_restarts,
+        log_dir: args.log_dir,
+        log_max_bytes: args.log_max_bytes,
+        log_keep: args.log_keep,
+    };
+    let events = EventLog::new(&node.to_string(), args.event_log.as_deref())?;
+    let sup = Supervisor::new(opts, events);
+    let _ctl = match &args.ctl_socket {
+        Some(path) => {
+            println!("  Control socket:  {}", path);
+            Some(ControlSocket::serve(path, sup.clone())?)
+        }
+        None => None,
+    };
+
+    cprintln!("<s><yellow>Starting {} {}...</></>", role_name, node);
+    println!("  Invocation:  just {}", just_args.join(" "));
+    sup.run(&just_args)
 }
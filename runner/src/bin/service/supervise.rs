@@ -0,0 +1,423 @@
+//! Supervision of the launched server or manager process: restart policy,
+//! control commands, lifecycle event log, and rotated per-process output
+//! logs.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::process::ExitStatus;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+
+use runner::{ProcTree, RunnerError, ServerProc, ServiceCmd, Signal};
+
+/// When to restart the process after it exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum RestartPolicy {
+    /// Never restart; the launcher exits along with the process.
+    Never,
+    /// Restart only if the process exited unsuccessfully or by a signal.
+    OnFailure,
+    /// Restart whenever the process exits.
+    Always,
+}
+
+/// Format a system time as an RFC 3339 UTC timestamp with milliseconds.
+fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+
+    // civil date from days since epoch, after Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Timestamped lifecycle events, printed and optionally appended to a file.
+pub(crate) struct EventLog {
+    node: String,
+    file: Option<File>,
+}
+
+impl EventLog {
+    pub(crate) fn new(node: &str, path: Option<&str>) -> Result<Self, RunnerError> {
+        let file = match path {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        Ok(EventLog {
+            node: node.into(),
+            file,
+        })
+    }
+
+    /// Record an event happening now.
+    pub(crate) fn event(&mut self, msg: &str) -> Result<(), RunnerError> {
+        let line = format!("{}  {}  {}", format_utc(SystemTime::now()), self.node, msg);
+        println!("  Event:  {}", line);
+        if let Some(file) = self.file.as_mut() {
+            writeln!(file, "{}", line)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Append-only log file rotated once it would grow beyond a size limit,
+/// keeping a number of older files as "<path>.1" (newest) to "<path>.<keep>".
+pub(crate) struct RotatingLog {
+    path: String,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl RotatingLog {
+    pub(crate) fn open(path: String, max_bytes: u64, keep: usize) -> Result<Self, RunnerError> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingLog {
+            path,
+            file,
+            size,
+            max_bytes,
+            keep,
+        })
+    }
+
+    fn rotate(&mut self) -> Result<(), RunnerError> {
+        if self.keep > 0 {
+            let _ = fs::remove_file(format!("{}.{}", self.path, self.keep));
+            for i in (1..self.keep).rev() {
+                let _ = fs::rename(
+                    format!("{}.{}", self.path, i),
+                    format!("{}.{}", self.path, i + 1),
+                );
+            }
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<(), RunnerError> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.file.flush()?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+}
+
+/// Copy everything from a process output pipe into a log until EOF.
+fn spawn_log_pump(
+    mut src: impl Read + Send + 'static,
+    log: Arc<Mutex<RotatingLog>>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = vec![0; 8192];
+        while let Ok(n) = src.read(&mut buf) {
+            if n == 0 || log.lock().unwrap().write(&buf[..n]).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Restart and logging configuration of a supervisor.
+#[derive(Debug, Clone)]
+pub(crate) struct SuperviseOptions {
+    pub(crate) policy: RestartPolicy,
+    /// Delay before the first of consecutive restarts, doubled for each
+    /// further one up to `max_backoff`.
+    pub(crate) backoff: Duration,
+    pub(crate) max_backoff: Duration,
+    /// Give up after this many restarts in total, if set.
+    pub(crate) max_restarts: Option<u32>,
+    /// Directory to write "<node>.stdout.log" and "<node>.stderr.log" to;
+    /// output is inherited if not set.
+    pub(crate) log_dir: Option<String>,
+    pub(crate) log_max_bytes: u64,
+    pub(crate) log_keep: usize,
+}
+
+/// State of the supervised process, shared with control commands.
+#[derive(Debug, Default)]
+struct State {
+    /// Process tree of the running process, if any.
+    tree: Option<ProcTree>,
+    started: Option<Instant>,
+    paused: bool,
+    restarts: u32,
+    /// Set by a kill or term command: the exit it causes does not follow the
+    /// restart policy, and the process stays down until a restart command.
+    held: bool,
+    /// Set by a restart command: the process gets started again right after
+    /// it exits, or right away if it is down.
+    restart_now: bool,
+}
+
+/// Supervisor of the launched server or manager process.
+pub(crate) struct Supervisor {
+    opts: SuperviseOptions,
+    state: Mutex<State>,
+    /// Notified upon restart and kill commands while the process is down.
+    wakeup: Condvar,
+    events: Mutex<EventLog>,
+}
+
+impl Supervisor {
+    pub(crate) fn new(opts: SuperviseOptions, events: EventLog) -> Arc<Self> {
+        Arc::new(Supervisor {
+            opts,
+            state: Mutex::new(State::default()),
+            wakeup: Condvar::new(),
+            events: Mutex::new(events),
+        })
+    }
+
+    fn event(&self, msg: &str) -> Result<(), RunnerError> {
+        self.events.lock().unwrap().event(msg)
+    }
+
+    /// Run the process of given `just` args, restarting it following the
+    /// policy or control commands. Returns `Ok` only if the last run exited
+    /// successfully.
+    pub(crate) fn run(&self, just_args: &[String]) -> Result<(), RunnerError> {
+        let opts = &self.opts;
+        let logs = match &opts.log_dir {
+            Some(dir) => {
+                fs::create_dir_all(dir)?;
+                let node = self.events.lock().unwrap().node.clone();
+                let open = |stream: &str| -> Result<_, RunnerError> {
+                    let path = format!("{}/{}.{}.log", dir, node, stream);
+                    let log = RotatingLog::open(path, opts.log_max_bytes, opts.log_keep)?;
+                    Ok(Arc::new(Mutex::new(log)))
+                };
+                Some((open("stdout")?, open("stderr")?))
+            }
+            None => None,
+        };
+
+        let mut consecutive = 0;
+        loop {
+            let just_args: Vec<&str> = just_args.iter().map(|s| s.as_str()).collect();
+            let (proc, pumps) = match &logs {
+                Some((out_log, err_log)) => {
+                    let (proc, stdout, stderr) = ServerProc::new_in_caller_group_piped(just_args)?;
+                    let pumps = vec![
+                        spawn_log_pump(stdout, out_log.clone()),
+                        spawn_log_pump(stderr, err_log.clone()),
+                    ];
+                    (proc, pumps)
+                }
+                None => (ServerProc::new_in_caller_group(just_args)?, vec![]),
+            };
+            let started = Instant::now();
+            {
+                let mut state = self.state.lock().unwrap();
+                state.tree = Some(proc.tree());
+                state.started = Some(started);
+                state.paused = false;
+                self.event(&format!("STARTED pid {}", proc.pid()))?;
+            }
+
+            let status = proc.wait_status()?;
+            for pump in pumps {
+                pump.join().map_err(|_| RunnerError::Join)?;
+            }
+            let uptime = started.elapsed();
+            let (held, restart_now, restarts) = {
+                let mut state = self.state.lock().unwrap();
+                state.tree = None;
+                state.started = None;
+                state.paused = false;
+                (state.held, state.restart_now, state.restarts)
+            };
+            self.event(&format!(
+                "EXITED {} after {:.3}s",
+                describe_status(status),
+                uptime.as_secs_f64()
+            ))?;
+
+            if !held && !restart_now {
+                let restart = match opts.policy {
+                    RestartPolicy::Never => false,
+                    RestartPolicy::OnFailure => !status.success(),
+                    RestartPolicy::Always => true,
+                };
+                if !restart {
+                    return exit_result(status);
+                }
+                if opts.max_restarts.is_some_and(|max| restarts >= max) {
+                    self.event(&format!("GAVE UP after {} restarts", restarts))?;
+                    return exit_result(status);
+                }
+            }
+
+            // back off exponentially over consecutive quick exits, starting
+            // over once the process stayed up for longer than the longest
+            // backoff; commanded restarts happen right away
+            if uptime >= opts.max_backoff {
+                consecutive = 0;
+            }
+            let delay = if restart_now {
+                Duration::ZERO
+            } else {
+                let delay = opts
+                    .backoff
+                    .saturating_mul(1 << consecutive.min(16))
+                    .min(opts.max_backoff);
+                consecutive += 1;
+                delay
+            };
+            self.wait_restart(delay)?;
+        }
+    }
+
+    /// Wait out the delay before restarting, or until a restart command if
+    /// the process is held down, then count the restart.
+    fn wait_restart(&self, delay: Duration) -> Result<(), RunnerError> {
+        let deadline = Instant::now() + delay;
+        let mut state = self.state.lock().unwrap();
+        let mut announced = false;
+        loop {
+            if state.restart_now {
+                break;
+            }
+            let now = Instant::now();
+            if state.held {
+                if !announced {
+                    self.event("HELD DOWN until restart command")?;
+                    announced = true;
+                }
+                state = self.wakeup.wait(state).unwrap();
+            } else if now < deadline {
+                if !announced {
+                    self.event(&format!(
+                        "RESTARTING #{} in {:.3}s",
+                        state.restarts + 1,
+                        delay.as_secs_f64()
+                    ))?;
+                    announced = true;
+                }
+                state = self.wakeup.wait_timeout(state, deadline - now).unwrap().0;
+            } else {
+                break;
+            }
+        }
+        if state.restart_now {
+            self.event(&format!("RESTARTING #{} on command", state.restarts + 1))?;
+        }
+        state.restarts += 1;
+        state.held = false;
+        state.restart_now = false;
+        Ok(())
+    }
+
+    /// Apply a control command, returning the info lines to reply.
+    pub(crate) fn command(&self, cmd: ServiceCmd) -> Result<Vec<String>, RunnerError> {
+        let mut state = self.state.lock().unwrap();
+        if cmd != ServiceCmd::Status {
+            self.event(&format!("CONTROL {}", cmd))?;
+        }
+        let not_running = || RunnerError::Io("process is not running".into());
+        match cmd {
+            ServiceCmd::Status => {
+                let desc = match (state.tree, state.started) {
+                    (Some(tree), Some(started)) => format!(
+                        "{} pid {} uptime {:.3}s",
+                        if state.paused { "paused" } else { "running" },
+                        tree.pid(),
+                        started.elapsed().as_secs_f64()
+                    ),
+                    _ if state.held => "held".into(),
+                    _ => "restarting".into(),
+                };
+                return Ok(vec![format!("{} restarts {}", desc, state.restarts)]);
+            }
+            ServiceCmd::Kill | ServiceCmd::Term => {
+                // also holds down a process waiting to be restarted
+                state.held = true;
+                state.restart_now = false;
+                if let Some(tree) = state.tree {
+                    if cmd == ServiceCmd::Kill {
+                        tree.signal(Signal::Kill)?;
+                    } else {
+                        tree.signal(Signal::Term)?;
+                        // a paused process would never act on the termination
+                        tree.resume()?;
+                    }
+                }
+                self.wakeup.notify_all();
+            }
+            ServiceCmd::Pause => {
+                state.tree.ok_or_else(not_running)?.pause()?;
+                state.paused = true;
+            }
+            ServiceCmd::Resume => {
+                state.tree.ok_or_else(not_running)?.resume()?;
+                state.paused = false;
+            }
+            ServiceCmd::Restart => {
+                state.restart_now = true;
+                state.held = false;
+                if let Some(tree) = state.tree {
+                    tree.signal(Signal::Kill)?;
+                }
+                self.wakeup.notify_all();
+            }
+        }
+        Ok(vec![])
+    }
+}
+
+/// Human-readable exit status, telling apart exits from signals.
+fn describe_status(status: ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("with code {}", code),
+        (None, Some(sig)) => format!("by signal {}", sig),
+        _ => format!("with {}", status),
+    }
+}
+
+fn exit_result(status: ExitStatus) -> Result<(), RunnerError> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(RunnerError::Io(format!(
+            "server exited with status: {}",
+            status
+        )))
+    }
+}
@@ -2,7 +2,9 @@
 
 use std::collections::{HashMap, VecDeque};
 
-use runner::KvResp;
+use super::scan::{current_windows, has_common_point, WriteSpan};
+use super::OpRecord;
+use crate::KvResp;
 
 /// Per-key, non-read-only operation record with timestamp span.
 #[derive(Debug, Clone)]
@@ -20,38 +22,56 @@ struct QueuedSpan {
     resp: KvResp,
 }
 
+/// Outcome of replaying a complete history through the approximate checker.
+#[derive(Debug, Clone)]
+pub enum ApproxOutcome {
+    /// No violation found, with the number of checks left in the queue.
+    Passed(usize),
+    /// Check of the `failed` operation failed when the `trigger` update got
+    /// applied.
+    Violation { trigger: OpRecord, failed: OpRecord },
+    /// Update on a key outside of the keys pool.
+    UnexpectedKey(OpRecord),
+}
+
 /// Trimmed history of per-client acknowledged operations.
 #[derive(Debug)]
-pub(crate) struct History {
+pub struct History {
     /// Queue of pending responses to check (naturally ordered by response
     /// timestamp).
     queue: VecDeque<QueuedSpan>,
 
-    /// Per-key per-client trimmed history of acknowledged update operations.
+    /// Per-key per-client trimmed history of acknowledged update operations,
+    /// plus a last untrimmed list of updates of unknown outcome, which may
+    /// take effect at any time after their calls (hence an unbounded
+    /// response timestamp), or never.
     spans: HashMap<String, Vec<VecDeque<UpdateSpan>>>,
 
     /// Per-client max update resp timestamp seen.
     maxtr: Vec<u64>,
+
+    /// Whether scans must be explainable by a single point in time.
+    scan_atomic: bool,
 }
 
 impl History {
     /// Create a new empty history for given number of clients and keys pool.
-    pub(crate) fn new(num_clis: usize, keys: &[Vec<String>]) -> Self {
+    /// If `scan_atomic` is set, scans are additionally required to be atomic.
+    pub fn new(num_clis: usize, keys: &[Vec<String>], scan_atomic: bool) -> Self {
         let mut spans = HashMap::new();
         for cli_keys in keys {
             for key in cli_keys {
                 if !spans.contains_key(key) {
-                    spans.insert(
-                        key.clone(),
-                        vec![
-                            VecDeque::<UpdateSpan>::from([UpdateSpan {
-                                ts_call: 0,
-                                ts_resp: 0,
-                                value: None, // dummy Delete to simplify logic
-                            }]);
-                            num_clis
-                        ],
-                    );
+                    let mut key_spans = vec![
+                        VecDeque::<UpdateSpan>::from([UpdateSpan {
+                            ts_call: 0,
+                            ts_resp: 0,
+                            value: None, // dummy Delete to simplify logic
+                        }]);
+                        num_clis
+                    ];
+                    key_spans.push(VecDeque::new());
+                    spans.insert(key.clone(), key_spans);
                 }
             }
         }
@@ -60,11 +80,12 @@ impl History {
             queue: VecDeque::new(),
             spans,
             maxtr: vec![0; num_clis],
+            scan_atomic,
         }
     }
 
     /// Add a newly acknowledged response result to the check queue.
-    pub(crate) fn add_to_queue(&mut self, ts_call: u64, ts_resp: u64, resp: KvResp) {
+    pub fn add_to_queue(&mut self, ts_call: u64, ts_resp: u64, resp: KvResp) {
         debug_assert!(ts_call < ts_resp);
         debug_assert!(self.queue.is_empty() || self.queue.back().unwrap().ts_resp < ts_resp);
 
@@ -76,7 +97,7 @@ impl History {
     }
 
     /// Get the number of remaining checks in the check queue.
-    pub(crate) fn queue_len(&self) -> usize {
+    pub fn queue_len(&self) -> usize {
         self.queue.len()
     }
 
@@ -86,26 +107,61 @@ impl History {
     ///   - `Some(Some(resp))` if the check of a `resp` failed
     ///   - `Some(None)` if update key is unexpected
     ///   - `None` if everything is still alright
-    pub(crate) fn apply_update(
+    pub fn apply_update(
         &mut self,
         cidx: usize,
         ts_call: u64,
         ts_resp: u64,
         key: String,
         value: Option<String>,
+    ) -> Option<Option<KvResp>> {
+        self.apply(cidx, ts_call, ts_resp, key, value, false)
+    }
+
+    /// Add a newly answered update of unknown outcome to the history, which
+    /// may take effect at any time after its call, or never. Returns the same
+    /// as `apply_update()`.
+    pub fn apply_indeterminate(
+        &mut self,
+        cidx: usize,
+        ts_call: u64,
+        ts_resp: u64,
+        key: String,
+        value: Option<String>,
+    ) -> Option<Option<KvResp>> {
+        self.apply(cidx, ts_call, ts_resp, key, value, true)
+    }
+
+    /// Common logic of `apply_update()` and `apply_indeterminate()`.
+    fn apply(
+        &mut self,
+        cidx: usize,
+        ts_call: u64,
+        ts_resp: u64,
+        key: String,
+        value: Option<String>,
+        indeterminate: bool,
     ) -> Option<Option<KvResp>> {
         if let Some(key_spans) = self.spans.get_mut(&key) {
             debug_assert!(cidx < self.maxtr.len());
-            debug_assert!(cidx < key_spans.len());
-            debug_assert!(
-                key_spans[cidx].is_empty() || key_spans[cidx].back().unwrap().ts_resp < ts_call
-            );
+            debug_assert!(cidx < key_spans.len() - 1);
 
-            key_spans[cidx].push_back(UpdateSpan {
-                ts_call,
-                ts_resp,
-                value,
-            });
+            if indeterminate {
+                key_spans.last_mut().unwrap().push_back(UpdateSpan {
+                    ts_call,
+                    ts_resp: u64::MAX,
+                    value,
+                });
+            } else {
+                debug_assert!(
+                    key_spans[cidx].is_empty() || key_spans[cidx].back().unwrap().ts_resp < ts_call
+                );
+                key_spans[cidx].push_back(UpdateSpan {
+                    ts_call,
+                    ts_resp,
+                    value,
+                });
+            }
             self.maxtr[cidx] = ts_resp;
             let min_coming_ts = *self.maxtr.iter().min().unwrap();
             let min_queued_ts = self
@@ -148,6 +204,52 @@ impl History {
         }
     }
 
+    /// Replay a complete history through the checker in response timestamp
+    /// order, as if it were streamed in by the fuzzer. Updates must come
+    /// from clients numbered below `num_clis`.
+    pub fn replay(
+        num_clis: usize,
+        keys: &[Vec<String>],
+        records: &[OpRecord],
+        scan_atomic: bool,
+    ) -> ApproxOutcome {
+        let mut history = History::new(num_clis, keys, scan_atomic);
+        let mut order: Vec<&OpRecord> = records.iter().collect();
+        order.sort_by_key(|record| record.ts_resp);
+
+        // mirror of the check queue, to tell which operation a failed check
+        // belongs to
+        let mut queued: VecDeque<&OpRecord> = VecDeque::new();
+        for record in order {
+            history.add_to_queue(record.ts_call, record.ts_resp, record.resp.clone());
+            queued.push_back(record);
+            if let Some((key, value)) = record.update_info() {
+                let queue_len = history.queue_len();
+                let (cidx, ts_call, ts_resp) = (record.cidx, record.ts_call, record.ts_resp);
+                let applied = if record.resp.is_unknown() {
+                    history.apply_indeterminate(cidx, ts_call, ts_resp, key, value)
+                } else {
+                    history.apply_update(cidx, ts_call, ts_resp, key, value)
+                };
+                match applied {
+                    Some(Some(_)) => {
+                        let popped = queue_len - history.queue_len();
+                        return ApproxOutcome::Violation {
+                            trigger: record.clone(),
+                            failed: queued[popped - 1].clone(),
+                        };
+                    }
+                    Some(None) => return ApproxOutcome::UnexpectedKey(record.clone()),
+                    None => {
+                        let popped = queue_len - history.queue_len();
+                        queued.drain(..popped);
+                    }
+                }
+            }
+        }
+        ApproxOutcome::Passed(history.queue_len())
+    }
+
     /// Check a call popped off from the check queue, which is now decidable.
     fn check_call(&self, entry: &QueuedSpan) -> bool {
         match &entry.resp {
@@ -176,14 +278,7 @@ impl History {
                 key_start,
                 key_end,
                 entries,
-            } => Self::check_scan(
-                &self.spans,
-                entry.ts_call,
-                entry.ts_resp,
-                key_start,
-                key_end,
-                entries,
-            ),
+            } => self.check_scan(entry.ts_call, entry.ts_resp, key_start, key_end, entries),
             KvResp::Delete { key, found } => {
                 if let Some(key_spans) = self.spans.get(key) {
                     Self::check_delete(key_spans, entry.ts_call, entry.ts_resp, found)
@@ -191,6 +286,21 @@ impl History {
                     false
                 }
             }
+            KvResp::Cas { key, ok, current } => {
+                // a successful CAS observed its expected value and updated it,
+                // while a failed one only read the current value
+                if let Some(key_spans) = self.spans.get(key) {
+                    if *ok {
+                        Self::check_swap(key_spans, entry.ts_call, entry.ts_resp, current.as_ref())
+                    } else {
+                        Self::check_get(key_spans, entry.ts_call, entry.ts_resp, current.as_ref())
+                    }
+                } else {
+                    false
+                }
+            }
+            // nothing observed by failed operations or ones of unknown outcome
+            KvResp::Error { .. } | KvResp::Unknown { .. } => true,
             _ => false,
         }
     }
@@ -268,9 +378,12 @@ impl History {
     }
 
     /// Check a Scan operation result assuming given history. All possible
-    /// keys in range are searched here.
+    /// keys in range are searched here. Entries must come back in strictly
+    /// ascending key order, and every key must be from the keys pool. If
+    /// scans are required to be atomic, the values of all keys in range must
+    /// also be explainable by a single point in time.
     fn check_scan(
-        spans: &HashMap<String, Vec<VecDeque<UpdateSpan>>>,
+        &self,
         ts_call: u64,
         ts_resp: u64,
         key_start: &String,
@@ -278,18 +391,21 @@ impl History {
         entries: &[(String, String)],
     ) -> bool {
         let mut entries_map = HashMap::new();
-        for (key, value) in entries {
+        for (i, (key, value)) in entries.iter().enumerate() {
             if key < key_start || key > key_end {
                 return false; // out-of-range in scan result
             }
-            if entries_map.contains_key(key) {
-                return false; // duplicate key in scan result
+            if i > 0 && entries[i - 1].0 >= *key {
+                return false; // duplicate or out-of-order key in scan result
+            }
+            if !self.spans.contains_key(key) {
+                return false; // phantom key not in the keys pool
             }
             entries_map.insert(key, value);
         }
 
         // eprintln!("--- SCAN <{} - {}> loop", ts_call, ts_resp);
-        for (key, key_spans) in spans {
+        for (key, key_spans) in &self.spans {
             // if key >= key_start && key <= key_end {
             //     println!("... {} {:?} {:?}", key, entries_map.get(key), key_spans);
             // }
@@ -300,6 +416,22 @@ impl History {
                 return false;
             }
         }
+
+        if self.scan_atomic {
+            let per_key_windows = self
+                .spans
+                .iter()
+                .filter(|(key, _)| *key >= key_start && *key <= key_end)
+                .map(|(key, key_spans)| {
+                    let writes: Vec<WriteSpan> = key_spans
+                        .iter()
+                        .flatten()
+                        .map(|span| (span.ts_call, span.ts_resp, span.value.as_ref()))
+                        .collect();
+                    current_windows(&writes, entries_map.get(key).copied())
+                });
+            return has_common_point(ts_call, ts_resp, per_key_windows);
+        }
         true // all possible keys in range passed check
     }
 
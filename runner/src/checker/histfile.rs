@@ -0,0 +1,634 @@
+//! Line-oriented history file format for offline checking.
+//!
+//! A history file starts with a version line and a line with the fuzzing
+//! seed, followed by one `KEYS` line per client listing its pool of keys,
+//! followed by one `OP` line per acknowledged operation in response timestamp
+//! order, interleaved with one `FAULT` line per fault injected into the
+//! service:
+//!
+//! ```text
+//! MADKV-HISTORY 2
+//! SEED <seed>
+//! KEYS <cidx> <key> <key> ...
+//! OP <cidx> <ts_call> <ts_resp> <call> => <resp>
+//! FAULT <ts> <action> <target>
+//! ```
+//!
+//! where `<call>` is formatted as an input line of the stdin/out interface
+//! and `<resp>` as an output line, with scan results flattened into
+//! `SCAN <key_start> <key_end> BEGIN <key> <value> ... END` and transactions
+//! into `TXN BEGIN <op> ... COMMIT` on both sides. Keys and values, as well
+//! as the reason of an `ERROR`, are always in the escaped encoding of the
+//! interface, with the list terminators `END` and `COMMIT` escaped as well
+//! (e.g. `%45ND`), so that any content round-trips.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::str::SplitWhitespace;
+
+use super::{FaultRecord, OpRecord};
+use crate::{Encoding, KvCall, KvResp, RunnerError};
+
+/// Version line at the head of every history file.
+const VERSION_LINE: &str = "MADKV-HISTORY 2";
+
+/// Encoding of keys and values in history files.
+const ENCODING: Encoding = Encoding::Escaped;
+
+/// Segments that terminate a flattened list of scan entries or transaction
+/// operations, escaped when they appear as a key or value.
+const TERMINATORS: [&str; 2] = ["END", "COMMIT"];
+
+/// Streaming writer of a history file.
+#[derive(Debug)]
+pub struct HistoryWriter {
+    writer: BufWriter<File>,
+}
+
+impl HistoryWriter {
+    /// Create a history file at path, writing the header for the given
+    /// fuzzing seed and per-client keys pool.
+    pub fn create(path: &str, seed: u64, keys: &[Vec<String>]) -> Result<Self, RunnerError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", VERSION_LINE)?;
+        writeln!(writer, "SEED {}", seed)?;
+        for (cidx, cli_keys) in keys.iter().enumerate() {
+            let cli_keys: Vec<_> = cli_keys.iter().map(|k| encode(k)).collect();
+            writeln!(writer, "KEYS {} {}", cidx, cli_keys.join(" "))?;
+        }
+        Ok(HistoryWriter { writer })
+    }
+
+    /// Append an acknowledged operation record.
+    pub fn write_record(&mut self, record: &OpRecord) -> Result<(), RunnerError> {
+        writeln!(
+            self.writer,
+            "OP {} {} {} {} => {}",
+            record.cidx,
+            record.ts_call,
+            record.ts_resp,
+            fmt_call(&record.call),
+            fmt_resp(&record.resp)
+        )?;
+        Ok(())
+    }
+
+    /// Append an injected fault record.
+    pub fn write_fault(&mut self, fault: &FaultRecord) -> Result<(), RunnerError> {
+        writeln!(
+            self.writer,
+            "FAULT {} {} {}",
+            fault.ts, fault.action, fault.target
+        )?;
+        Ok(())
+    }
+
+    /// Flush everything written so far to the file.
+    pub fn flush(&mut self) -> Result<(), RunnerError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Complete history loaded from a history file.
+#[derive(Debug, Clone)]
+pub struct HistoryFile {
+    /// Seed of the fuzz testing round that produced the history, if known.
+    pub seed: Option<u64>,
+    /// Per-client pool of keys.
+    pub keys: Vec<Vec<String>>,
+    /// Acknowledged operation records, in file order.
+    pub records: Vec<OpRecord>,
+    /// Injected fault records, in file order.
+    pub faults: Vec<FaultRecord>,
+}
+
+impl HistoryFile {
+    /// Load a history file from path.
+    pub fn load(path: &str) -> Result<Self, RunnerError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut lines = reader.lines().enumerate();
+
+        let header = match lines.next() {
+            Some((_, line)) => line?,
+            None => String::new(),
+        };
+        if header.trim() != VERSION_LINE {
+            return Err(RunnerError::Parse(format!(
+                "missing '{}' header in {}",
+                VERSION_LINE, path
+            )));
+        }
+
+        let mut history = HistoryFile {
+            seed: None,
+            keys: vec![],
+            records: vec![],
+            faults: vec![],
+        };
+        for (lnum, line) in lines {
+            let line = line?;
+            let invalid =
+                || RunnerError::Parse(format!("invalid history line {}: {}", lnum + 1, line));
+            let mut segs = line.split_whitespace();
+
+            match segs.next() {
+                Some("SEED") => {
+                    history.seed = Some(expect_next_seg(&mut segs).ok_or_else(invalid)?.parse()?);
+                }
+
+                Some("KEYS") => {
+                    let cidx = expect_next_seg(&mut segs)
+                        .ok_or_else(invalid)?
+                        .parse::<usize>()?;
+                    if cidx != history.keys.len() {
+                        return Err(invalid());
+                    }
+                    history
+                        .keys
+                        .push(segs.map(|s| ENCODING.decode(s)).collect::<Result<_, _>>()?);
+                }
+
+                Some("OP") => {
+                    let cidx = expect_next_seg(&mut segs).ok_or_else(invalid)?.parse()?;
+                    let ts_call = expect_next_seg(&mut segs).ok_or_else(invalid)?.parse()?;
+                    let ts_resp = expect_next_seg(&mut segs).ok_or_else(invalid)?.parse()?;
+                    let call = parse_call(&mut segs, ENCODING).ok_or_else(invalid)?;
+                    if segs.next() != Some("=>") {
+                        return Err(invalid());
+                    }
+                    let resp = parse_resp(&mut segs, ENCODING).ok_or_else(invalid)?;
+                    if segs.next().is_some() {
+                        return Err(invalid());
+                    }
+                    history.records.push(OpRecord {
+                        cidx,
+                        ts_call,
+                        ts_resp,
+                        call,
+                        resp,
+                    });
+                }
+
+                Some("FAULT") => {
+                    let ts = expect_next_seg(&mut segs).ok_or_else(invalid)?.parse()?;
+                    let action = expect_next_seg(&mut segs).ok_or_else(invalid)?.parse()?;
+                    let target = expect_next_seg(&mut segs).ok_or_else(invalid)?;
+                    if segs.next().is_some() {
+                        return Err(invalid());
+                    }
+                    history.faults.push(FaultRecord { ts, action, target });
+                }
+
+                None => {} // skip empty lines
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(history)
+    }
+}
+
+/// Encode a key or value into a history segment, escaping the first byte
+/// of list terminators so that they cannot end a list early.
+fn encode(s: &str) -> Cow<'_, str> {
+    if TERMINATORS.contains(&s) {
+        Cow::Owned(format!("%{:02X}{}", s.as_bytes()[0], &s[1..]))
+    } else {
+        ENCODING.encode(s)
+    }
+}
+
+/// Encode an optional value into a history segment, `null` for `None`.
+fn encode_opt(value: Option<&str>) -> Cow<'_, str> {
+    match value {
+        Some(value) => encode(value),
+        None => Cow::Borrowed("null"),
+    }
+}
+
+/// Format a call as an input line of the stdin/out interface.
+pub(super) fn fmt_call(call: &KvCall) -> String {
+    let e = |s: &str| encode(s).into_owned();
+    match call {
+        KvCall::Put { key, value } => format!("PUT {} {}", e(key), e(value)),
+        KvCall::Swap { key, value } => format!("SWAP {} {}", e(key), e(value)),
+        KvCall::Get { key } => format!("GET {}", e(key)),
+        KvCall::Scan { key_start, key_end } => format!("SCAN {} {}", e(key_start), e(key_end)),
+        KvCall::Delete { key } => format!("DELETE {}", e(key)),
+        KvCall::Cas {
+            key,
+            expected,
+            value,
+        } => format!(
+            "CAS {} {} {}",
+            e(key),
+            encode_opt(expected.as_deref()),
+            e(value)
+        ),
+        KvCall::Txn { ops } => {
+            let mut line = "TXN BEGIN".to_string();
+            for op in ops {
+                line.push_str(&format!(" {}", fmt_call(op)));
+            }
+            line.push_str(" COMMIT");
+            line
+        }
+        KvCall::Stop => "STOP".into(),
+    }
+}
+
+/// Format a response as a single output line of the stdin/out interface.
+pub(super) fn fmt_resp(resp: &KvResp) -> String {
+    let e = |s: &str| encode(s).into_owned();
+    let found_str = |found: bool| if found { "found" } else { "not_found" };
+    let value_str = |value: &Option<String>| encode_opt(value.as_deref()).into_owned();
+    match resp {
+        KvResp::Put { key, found } => format!("PUT {} {}", e(key), found_str(*found)),
+        KvResp::Swap { key, old_value } => format!("SWAP {} {}", e(key), value_str(old_value)),
+        KvResp::Get { key, value } => format!("GET {} {}", e(key), value_str(value)),
+        KvResp::Scan {
+            key_start,
+            key_end,
+            entries,
+        } => {
+            let mut line = format!("SCAN {} {} BEGIN", e(key_start), e(key_end));
+            for (key, value) in entries {
+                line.push_str(&format!(" {} {}", e(key), e(value)));
+            }
+            line.push_str(" END");
+            line
+        }
+        KvResp::Delete { key, found } => format!("DELETE {} {}", e(key), found_str(*found)),
+        KvResp::Cas { key, ok, current } => format!(
+            "CAS {} {} {}",
+            e(key),
+            if *ok { "ok" } else { "fail" },
+            value_str(current)
+        ),
+        KvResp::Txn { results: None } => "TXN ABORT".into(),
+        KvResp::Txn {
+            results: Some(results),
+        } => {
+            let mut line = "TXN BEGIN".to_string();
+            for result in results {
+                line.push_str(&format!(" {}", fmt_resp(result)));
+            }
+            line.push_str(" COMMIT");
+            line
+        }
+        KvResp::Error { op, key, reason } => format!("ERROR {} {} {}", op, e(key), e(reason)),
+        KvResp::Unknown { op, key } => format!("UNKNOWN {} {}", op, e(key)),
+        KvResp::Stop => "STOP".into(),
+    }
+}
+
+/// Expect the next segment from an iterator of segments.
+fn expect_next_seg(segs: &mut SplitWhitespace) -> Option<String> {
+    segs.next().map(|s| s.into())
+}
+
+/// Expect the next segment as a key or value in the given encoding.
+fn expect_next_key(segs: &mut SplitWhitespace, enc: Encoding) -> Option<String> {
+    enc.decode(segs.next()?).ok()
+}
+
+/// Parse a `found` or `not_found` segment.
+fn parse_found(segs: &mut SplitWhitespace) -> Option<bool> {
+    match segs.next()? {
+        "found" => Some(true),
+        "not_found" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse a value segment, where `null` means not found.
+fn parse_value(segs: &mut SplitWhitespace, enc: Encoding) -> Option<Option<String>> {
+    enc.decode_opt(segs.next()?).ok()
+}
+
+/// Parse the flattened operations of a transaction up to its `COMMIT`
+/// segment with the given parser.
+fn parse_txn<T>(
+    segs: &mut SplitWhitespace,
+    enc: Encoding,
+    parse: fn(&mut SplitWhitespace, Encoding) -> Option<T>,
+) -> Option<Vec<T>> {
+    let mut ops = vec![];
+    while segs.clone().next()? != "COMMIT" {
+        ops.push(parse(segs, enc)?);
+    }
+    segs.next();
+    Some(ops)
+}
+
+/// Parse a call from segments of a history line.
+fn parse_call(segs: &mut SplitWhitespace, enc: Encoding) -> Option<KvCall> {
+    match segs.next()? {
+        "PUT" => Some(KvCall::Put {
+            key: expect_next_key(segs, enc)?,
+            value: expect_next_key(segs, enc)?,
+        }),
+        "SWAP" => Some(KvCall::Swap {
+            key: expect_next_key(segs, enc)?,
+            value: expect_next_key(segs, enc)?,
+        }),
+        "GET" => Some(KvCall::Get {
+            key: expect_next_key(segs, enc)?,
+        }),
+        "SCAN" => Some(KvCall::Scan {
+            key_start: expect_next_key(segs, enc)?,
+            key_end: expect_next_key(segs, enc)?,
+        }),
+        "DELETE" => Some(KvCall::Delete {
+            key: expect_next_key(segs, enc)?,
+        }),
+        "CAS" => Some(KvCall::Cas {
+            key: expect_next_key(segs, enc)?,
+            expected: parse_value(segs, enc)?,
+            value: expect_next_key(segs, enc)?,
+        }),
+        "TXN" if segs.next()? == "BEGIN" => Some(KvCall::Txn {
+            ops: parse_txn(segs, enc, parse_call)?,
+        }),
+        "STOP" => Some(KvCall::Stop),
+        _ => None,
+    }
+}
+
+/// Parse a response from segments of a history line.
+fn parse_resp(segs: &mut SplitWhitespace, enc: Encoding) -> Option<KvResp> {
+    match segs.next()? {
+        "PUT" => Some(KvResp::Put {
+            key: expect_next_key(segs, enc)?,
+            found: parse_found(segs)?,
+        }),
+        "SWAP" => Some(KvResp::Swap {
+            key: expect_next_key(segs, enc)?,
+            old_value: parse_value(segs, enc)?,
+        }),
+        "GET" => Some(KvResp::Get {
+            key: expect_next_key(segs, enc)?,
+            value: parse_value(segs, enc)?,
+        }),
+        "SCAN" => {
+            let key_start = expect_next_key(segs, enc)?;
+            let key_end = expect_next_key(segs, enc)?;
+            if segs.next()? != "BEGIN" {
+                return None;
+            }
+            let mut entries = vec![];
+            loop {
+                let key = segs.next()?;
+                if key == "END" {
+                    break;
+                }
+                entries.push((enc.decode(key).ok()?, expect_next_key(segs, enc)?));
+            }
+            Some(KvResp::Scan {
+                key_start,
+                key_end,
+                entries,
+            })
+        }
+        "DELETE" => Some(KvResp::Delete {
+            key: expect_next_key(segs, enc)?,
+            found: parse_found(segs)?,
+        }),
+        "CAS" => Some(KvResp::Cas {
+            key: expect_next_key(segs, enc)?,
+            ok: match segs.next()? {
+                "ok" => true,
+                "fail" => false,
+                _ => return None,
+            },
+            current: parse_value(segs, enc)?,
+        }),
+        "TXN" => match segs.next()? {
+            "ABORT" => Some(KvResp::Txn { results: None }),
+            "BEGIN" => Some(KvResp::Txn {
+                results: Some(parse_txn(segs, enc, parse_resp)?),
+            }),
+            _ => None,
+        },
+        "ERROR" => Some(KvResp::Error {
+            op: expect_next_seg(segs)?,
+            key: expect_next_key(segs, enc)?,
+            reason: expect_next_key(segs, enc)?,
+        }),
+        "UNKNOWN" => Some(KvResp::Unknown {
+            op: expect_next_seg(segs)?,
+            key: expect_next_key(segs, enc)?,
+        }),
+        "STOP" => Some(KvResp::Stop),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+    use crate::FaultAction;
+
+    /// Operation record with the given call and response.
+    fn record(cidx: usize, ts_call: u64, ts_resp: u64, call: KvCall, resp: KvResp) -> OpRecord {
+        OpRecord {
+            cidx,
+            ts_call,
+            ts_resp,
+            call,
+            resp,
+        }
+    }
+
+    /// Write a history file with given records, load it back, and remove it.
+    fn round_trip(name: &str, keys: &[Vec<String>], records: &[OpRecord]) -> HistoryFile {
+        let path = env::temp_dir().join(format!("madkv-{}-{}.hist", name, std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut writer = HistoryWriter::create(path, 42, keys).unwrap();
+        for record in records {
+            writer.write_record(record).unwrap();
+        }
+        writer
+            .write_fault(&FaultRecord {
+                ts: 3,
+                action: FaultAction::Kill,
+                target: "s0".into(),
+            })
+            .unwrap();
+        writer.flush().unwrap();
+        let history = HistoryFile::load(path);
+        fs::remove_file(path).unwrap();
+        history.unwrap()
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let keys = vec![vec!["k1".into(), "END".into()], vec!["a b".into()]];
+        let records = vec![
+            record(
+                0,
+                1,
+                2,
+                KvCall::Put {
+                    key: "k1".into(),
+                    value: "".into(),
+                },
+                KvResp::Put {
+                    key: "k1".into(),
+                    found: false,
+                },
+            ),
+            record(
+                1,
+                3,
+                4,
+                KvCall::Swap {
+                    key: "a b".into(),
+                    value: "null".into(),
+                },
+                KvResp::Swap {
+                    key: "a b".into(),
+                    old_value: None,
+                },
+            ),
+            record(
+                0,
+                5,
+                8,
+                KvCall::Scan {
+                    key_start: "END".into(),
+                    key_end: "k9".into(),
+                },
+                KvResp::Scan {
+                    key_start: "END".into(),
+                    key_end: "k9".into(),
+                    entries: vec![("END".into(), "COMMIT".into()), ("k1".into(), "%\n".into())],
+                },
+            ),
+            record(
+                1,
+                6,
+                7,
+                KvCall::Cas {
+                    key: "a b".into(),
+                    expected: Some("null".into()),
+                    value: "\u{4e2d}".into(),
+                },
+                KvResp::Cas {
+                    key: "a b".into(),
+                    ok: true,
+                    current: Some("null".into()),
+                },
+            ),
+            record(
+                0,
+                9,
+                10,
+                KvCall::Get { key: "k1".into() },
+                KvResp::Error {
+                    op: "GET".into(),
+                    key: "k1".into(),
+                    reason: "leader not found".into(),
+                },
+            ),
+            record(
+                1,
+                11,
+                12,
+                KvCall::Delete { key: "a b".into() },
+                KvResp::Unknown {
+                    op: "DELETE".into(),
+                    key: "a b".into(),
+                },
+            ),
+        ];
+
+        let history = round_trip("histfile-records", &keys, &records);
+        assert_eq!(history.seed, Some(42));
+        assert_eq!(history.keys, keys);
+        assert_eq!(format!("{:?}", history.records), format!("{:?}", records));
+        assert_eq!(history.faults.len(), 1);
+        assert_eq!(history.faults[0].ts, 3);
+        assert_eq!(history.faults[0].target, "s0");
+    }
+
+    #[test]
+    fn txn_round_trip() {
+        let keys = vec![vec!["COMMIT".into(), "END".into()]];
+        let records = vec![
+            record(
+                0,
+                1,
+                2,
+                KvCall::Txn {
+                    ops: vec![
+                        KvCall::Put {
+                            key: "COMMIT".into(),
+                            value: "END".into(),
+                        },
+                        KvCall::Get { key: "END".into() },
+                    ],
+                },
+                KvResp::Txn {
+                    results: Some(vec![
+                        KvResp::Put {
+                            key: "COMMIT".into(),
+                            found: false,
+                        },
+                        KvResp::Get {
+                            key: "END".into(),
+                            value: Some("COMMIT".into()),
+                        },
+                    ]),
+                },
+            ),
+            record(
+                0,
+                3,
+                4,
+                KvCall::Txn {
+                    ops: vec![KvCall::Delete {
+                        key: "COMMIT".into(),
+                    }],
+                },
+                KvResp::Txn { results: None },
+            ),
+        ];
+
+        let history = round_trip("histfile-txns", &keys, &records);
+        assert_eq!(history.keys, keys);
+        assert_eq!(format!("{:?}", history.records), format!("{:?}", records));
+    }
+
+    #[test]
+    fn terminators_escaped() {
+        let resp = KvResp::Scan {
+            key_start: "END".into(),
+            key_end: "END".into(),
+            entries: vec![("END".into(), "END".into())],
+        };
+        assert_eq!(fmt_resp(&resp), "SCAN %45ND %45ND BEGIN %45ND %45ND END");
+        let call = KvCall::Txn {
+            ops: vec![KvCall::Get {
+                key: "COMMIT".into(),
+            }],
+        };
+        assert_eq!(fmt_call(&call), "TXN BEGIN GET %43OMMIT COMMIT");
+    }
+
+    #[test]
+    fn header_required() {
+        let path =
+            env::temp_dir().join(format!("madkv-histfile-header-{}.hist", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "MADKV-HISTORY 1\nSEED 1\n").unwrap();
+        let history = HistoryFile::load(path);
+        fs::remove_file(path).unwrap();
+        assert!(history.is_err());
+    }
+}
@@ -0,0 +1,650 @@
+//! Linearizability checker over complete recorded histories.
+//!
+//! Linearizability is P-compositional, so the history is partitioned by key
+//! and each key's sub-history is checked independently against a single
+//! register model, using the Wing & Gong search with Lowe's memoization
+//! (the same approach taken by Porcupine). Scans are decomposed into one
+//! read per known key in range; scan atomicity is checked separately by
+//! `check_scans_atomic` if requested. Failed operations are left out, and
+//! updates of unknown outcome are taken as never returning (as Jepsen does
+//! with "info" operations), so they may be linearized at any point after
+//! their calls, including after everything else, i.e., never taking effect.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use bit_vec::BitVec;
+
+use super::OpRecord;
+use crate::{KvCall, KvResp};
+
+/// Effect and observed result of an operation on a single key.
+#[derive(Debug, Clone)]
+pub(super) enum KeyOpKind {
+    Put {
+        value: String,
+        found: bool,
+    },
+    Swap {
+        value: String,
+        old_value: Option<String>,
+    },
+    Get {
+        value: Option<String>,
+    },
+    Delete {
+        found: bool,
+    },
+    /// Update of unknown outcome writing the value (`None` for a delete),
+    /// observing nothing.
+    Unknown {
+        value: Option<String>,
+    },
+    /// Response does not match the call; never linearizable.
+    Mismatch,
+}
+
+impl KeyOpKind {
+    /// Apply the operation to a register state, returning the new state if
+    /// the observed result is legal from the given state, else `None`.
+    pub(super) fn step(&self, state: &Option<String>) -> Option<Option<String>> {
+        match self {
+            KeyOpKind::Put { value, found } => {
+                (state.is_some() == *found).then(|| Some(value.clone()))
+            }
+            KeyOpKind::Swap { value, old_value } => {
+                (state == old_value).then(|| Some(value.clone()))
+            }
+            KeyOpKind::Get { value } => (state == value).then(|| state.clone()),
+            KeyOpKind::Delete { found } => (state.is_some() == *found).then_some(None),
+            KeyOpKind::Unknown { value } => Some(value.clone()),
+            KeyOpKind::Mismatch => None,
+        }
+    }
+
+    /// True if the operation may change the register state.
+    pub(super) fn is_write(&self) -> bool {
+        matches!(
+            self,
+            KeyOpKind::Put { .. }
+                | KeyOpKind::Swap { .. }
+                | KeyOpKind::Delete { .. }
+                | KeyOpKind::Unknown { .. }
+        )
+    }
+
+    /// Value written by the operation, if any.
+    pub(super) fn written(&self) -> Option<&String> {
+        match self {
+            KeyOpKind::Put { value, .. } | KeyOpKind::Swap { value, .. } => Some(value),
+            KeyOpKind::Unknown { value } => value.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Value observed by the operation, if any.
+    pub(super) fn observed(&self) -> Option<&String> {
+        match self {
+            KeyOpKind::Swap { old_value, .. } => old_value.as_ref(),
+            KeyOpKind::Get { value } => value.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// True if the operation observed that some value existed.
+    pub(super) fn observed_some(&self) -> bool {
+        match self {
+            KeyOpKind::Put { found, .. } | KeyOpKind::Delete { found } => *found,
+            _ => self.observed().is_some(),
+        }
+    }
+}
+
+/// Single-key projection of a recorded operation.
+#[derive(Debug, Clone)]
+pub(super) struct KeyOp {
+    /// Index into the original records slice.
+    pub(super) ridx: usize,
+    pub(super) cidx: usize,
+    pub(super) ts_call: u64,
+    pub(super) ts_resp: u64,
+    pub(super) kind: KeyOpKind,
+}
+
+/// Precedence constraint that a witness total order must respect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Precedence {
+    /// Real-time order across all clients.
+    RealTime,
+    /// Per-client session order.
+    Session,
+    /// Per-client session order, except that a write may be ordered before
+    /// reads that precede it in the session.
+    SessionFifo,
+    /// No constraint at all.
+    Unordered,
+}
+
+/// Outcome of a witness order search.
+#[derive(Debug, Clone)]
+pub(super) enum SearchResult {
+    /// A witness order, as indices into the searched operations.
+    Found(Vec<usize>),
+    /// The search space is exhausted without finding a witness.
+    NotFound,
+    /// The search budget ran out before reaching a verdict.
+    GaveUp,
+}
+
+/// Search stack frame of the Wing & Gong algorithm.
+struct Frame {
+    /// Register state after all linearized operations so far.
+    state: Option<String>,
+    /// Remaining candidate operations to try linearizing next (in reverse).
+    cands: Vec<usize>,
+    /// Operation linearized to reach this frame.
+    applied: Option<usize>,
+    /// Index of the first not-yet-linearized operation.
+    first: usize,
+}
+
+impl Frame {
+    /// Create a frame, computing the candidates that could be linearized next
+    /// under the given precedence constraint. `ops` must be sorted by call
+    /// timestamp and `num_clis` must exceed every client index.
+    fn new(
+        ops: &[KeyOp],
+        done: &BitVec,
+        state: Option<String>,
+        applied: Option<usize>,
+        first: usize,
+        prec: Precedence,
+        num_clis: usize,
+    ) -> Self {
+        let first = (first..ops.len())
+            .find(|&i| !done.get(i).unwrap())
+            .unwrap_or(ops.len());
+        let undone = (first..ops.len()).filter(|&i| !done.get(i).unwrap());
+
+        let mut cands: Vec<usize> = match prec {
+            Precedence::RealTime => {
+                // an operation qualifies only if no other pending operation
+                // returned before it was called
+                let mut min_resp = u64::MAX;
+                let mut scanned = vec![];
+                for i in undone {
+                    if ops[i].ts_call > min_resp {
+                        break;
+                    }
+                    min_resp = min_resp.min(ops[i].ts_resp);
+                    scanned.push(i);
+                }
+                scanned
+                    .into_iter()
+                    .filter(|&i| ops[i].ts_call < min_resp)
+                    .collect()
+            }
+
+            Precedence::Session => {
+                // only the first pending operation of each client qualifies
+                let mut seen = vec![false; num_clis];
+                let mut scanned = vec![];
+                for i in undone {
+                    if !seen[ops[i].cidx] {
+                        seen[ops[i].cidx] = true;
+                        scanned.push(i);
+                        if scanned.len() == num_clis {
+                            break;
+                        }
+                    }
+                }
+                scanned
+            }
+
+            Precedence::SessionFifo => {
+                // the first pending operation of each client qualifies, and
+                // so do writes preceded by only pending reads in the session
+                let mut seen = vec![(false, false); num_clis]; // (any, write)
+                let mut scanned = vec![];
+                for i in undone {
+                    let (any, write) = &mut seen[ops[i].cidx];
+                    let is_write = ops[i].kind.is_write();
+                    if !*any || (!*write && is_write) {
+                        scanned.push(i);
+                    }
+                    *any = true;
+                    *write |= is_write;
+                }
+                scanned
+            }
+
+            Precedence::Unordered => undone.collect(),
+        };
+        cands.reverse(); // try in call order when popping
+
+        Frame {
+            state,
+            cands,
+            applied,
+            first,
+        }
+    }
+}
+
+/// Non-linearizable sub-history found by the checker.
+#[derive(Debug, Clone)]
+pub struct LinearViolation {
+    /// Key whose sub-history is not linearizable.
+    pub key: String,
+    /// Minimal non-linearizable sub-history on that key, ordered by call
+    /// timestamp.
+    pub records: Vec<OpRecord>,
+}
+
+/// Check if a complete history is linearizable. Returns `None` if it is,
+/// otherwise the minimal non-linearizable sub-history of the first failing
+/// key.
+pub fn check_linearizable(records: &[OpRecord]) -> Option<LinearViolation> {
+    for (key, ops) in partition_by_key(records) {
+        if !search(&ops) {
+            let ops = minimize(ops);
+            return Some(LinearViolation {
+                key,
+                records: ops.iter().map(|op| records[op.ridx].clone()).collect(),
+            });
+        }
+    }
+    None
+}
+
+/// Partition the history into per-key sub-histories, each sorted by call
+/// timestamp.
+pub(super) fn partition_by_key(records: &[OpRecord]) -> BTreeMap<String, Vec<KeyOp>> {
+    // gather all keys ever mentioned, so that scans can be decomposed into
+    // reads of every known key in range
+    let mut keys = BTreeSet::new();
+    for record in records {
+        match &record.call {
+            KvCall::Put { key, .. }
+            | KvCall::Swap { key, .. }
+            | KvCall::Get { key }
+            | KvCall::Delete { key }
+            | KvCall::Cas { key, .. } => {
+                keys.insert(key.clone());
+            }
+            _ => {}
+        }
+        if let KvResp::Scan { entries, .. } = &record.resp {
+            keys.extend(entries.iter().map(|(k, _)| k.clone()));
+        }
+    }
+
+    // updates of unknown outcome never return, and each gets a client of its
+    // own so as not to hold back later operations of its session
+    let mut unknown_cidx = records.iter().map(|r| r.cidx + 1).max().unwrap_or(0);
+
+    let mut parts: BTreeMap<String, Vec<KeyOp>> = BTreeMap::new();
+    for (ridx, record) in records.iter().enumerate() {
+        let (cidx, ts_resp) = if record.resp.is_unknown() {
+            unknown_cidx += 1;
+            (unknown_cidx - 1, u64::MAX)
+        } else {
+            (record.cidx, record.ts_resp)
+        };
+        let mut push = |key: &String, kind: KeyOpKind| {
+            parts.entry(key.clone()).or_default().push(KeyOp {
+                ridx,
+                cidx,
+                ts_call: record.ts_call,
+                ts_resp,
+                kind,
+            });
+        };
+
+        match (&record.call, &record.resp) {
+            // failed operations and reads of unknown outcome have no effect
+            (_, KvResp::Error { .. }) => {}
+            (
+                KvCall::Put { key, value }
+                | KvCall::Swap { key, value }
+                | KvCall::Cas { key, value, .. },
+                KvResp::Unknown { .. },
+            ) => push(
+                key,
+                KeyOpKind::Unknown {
+                    value: Some(value.clone()),
+                },
+            ),
+            (KvCall::Delete { key }, KvResp::Unknown { .. }) => {
+                push(key, KeyOpKind::Unknown { value: None })
+            }
+            (_, KvResp::Unknown { .. }) => {}
+            (KvCall::Put { key, value }, KvResp::Put { found, .. }) => push(
+                key,
+                KeyOpKind::Put {
+                    value: value.clone(),
+                    found: *found,
+                },
+            ),
+            (KvCall::Swap { key, value }, KvResp::Swap { old_value, .. }) => push(
+                key,
+                KeyOpKind::Swap {
+                    value: value.clone(),
+                    old_value: old_value.clone(),
+                },
+            ),
+            (KvCall::Get { key }, KvResp::Get { value, .. }) => push(
+                key,
+                KeyOpKind::Get {
+                    value: value.clone(),
+                },
+            ),
+            (KvCall::Scan { key_start, key_end }, KvResp::Scan { entries, .. }) => {
+                // entries not in strictly ascending key order are never legal
+                for pair in entries.windows(2) {
+                    if pair[0].0 >= pair[1].0 {
+                        push(&pair[1].0, KeyOpKind::Mismatch);
+                    }
+                }
+                let entries: BTreeMap<&String, &String> =
+                    entries.iter().map(|(k, v)| (k, v)).collect();
+                for key in keys.range(key_start.clone()..=key_end.clone()) {
+                    push(
+                        key,
+                        KeyOpKind::Get {
+                            value: entries.get(key).map(|v| (*v).clone()),
+                        },
+                    );
+                }
+                // entries outside of the requested range are never legal
+                for key in entries.keys() {
+                    if *key < key_start || *key > key_end {
+                        push(key, KeyOpKind::Mismatch);
+                    }
+                }
+            }
+            (KvCall::Delete { key }, KvResp::Delete { found, .. }) => {
+                push(key, KeyOpKind::Delete { found: *found })
+            }
+            // a successful CAS is a swap that observed the expected value, and
+            // a failed one is a read of some other value
+            (
+                KvCall::Cas {
+                    key,
+                    expected,
+                    value,
+                },
+                KvResp::Cas { ok, current, .. },
+            ) => match (*ok, current == expected) {
+                (true, true) => push(
+                    key,
+                    KeyOpKind::Swap {
+                        value: value.clone(),
+                        old_value: current.clone(),
+                    },
+                ),
+                (false, false) => push(
+                    key,
+                    KeyOpKind::Get {
+                        value: current.clone(),
+                    },
+                ),
+                _ => push(key, KeyOpKind::Mismatch),
+            },
+            (
+                KvCall::Put { key, .. }
+                | KvCall::Swap { key, .. }
+                | KvCall::Get { key }
+                | KvCall::Delete { key }
+                | KvCall::Cas { key, .. },
+                _,
+            ) => push(key, KeyOpKind::Mismatch),
+            _ => {}
+        }
+    }
+
+    for ops in parts.values_mut() {
+        ops.sort_by_key(|op| op.ts_call);
+    }
+    parts
+}
+
+/// Wing & Gong search for a linearization. Returns true if the single-key
+/// sub-history is linearizable. `ops` must be sorted by call timestamp.
+fn search(ops: &[KeyOp]) -> bool {
+    matches!(
+        search_order(ops, Precedence::RealTime, usize::MAX),
+        SearchResult::Found(_)
+    )
+}
+
+/// Wing & Gong search with memoization of (linearized set, state) pairs for
+/// a total order of a single-key sub-history that respects the precedence
+/// constraint and explains every observed result. Gives up after pushing
+/// `budget` search frames. `ops` must be sorted by call timestamp.
+pub(super) fn search_order(ops: &[KeyOp], prec: Precedence, budget: usize) -> SearchResult {
+    if ops.is_empty() {
+        return SearchResult::Found(vec![]);
+    }
+    let num_clis = ops.iter().map(|op| op.cidx + 1).max().unwrap();
+
+    let mut done = BitVec::from_elem(ops.len(), false);
+    let mut cache: HashSet<(BitVec, Option<String>)> = HashSet::new();
+    let mut stack = vec![Frame::new(ops, &done, None, None, 0, prec, num_clis)];
+    let mut pushed = 0;
+
+    while let Some(frame) = stack.last_mut() {
+        if let Some(i) = frame.cands.pop() {
+            if let Some(state) = ops[i].kind.step(&frame.state) {
+                done.set(i, true);
+                if done.all() {
+                    let mut order: Vec<usize> = stack.iter().filter_map(|f| f.applied).collect();
+                    order.push(i);
+                    return SearchResult::Found(order);
+                }
+                if cache.insert((done.clone(), state.clone())) {
+                    if pushed == budget {
+                        return SearchResult::GaveUp;
+                    }
+                    pushed += 1;
+                    let first = frame.first;
+                    stack.push(Frame::new(
+                        ops,
+                        &done,
+                        state,
+                        Some(i),
+                        first,
+                        prec,
+                        num_clis,
+                    ));
+                } else {
+                    done.set(i, false); // already explored, prune
+                }
+            }
+        } else {
+            // all candidates exhausted, backtrack
+            let frame = stack.pop().unwrap();
+            if let Some(i) = frame.applied {
+                done.set(i, false);
+            }
+        }
+    }
+    SearchResult::NotFound
+}
+
+/// Shrink a non-linearizable single-key sub-history down to a minimal one
+/// that is still non-linearizable, by first cutting to a failing prefix and
+/// then greedily removing chunks of decreasing sizes. A removal is only taken
+/// if every remaining operation keeps the operation that justifies what it
+/// observed, so that the violation is not replaced by an artificial one.
+fn minimize(ops: Vec<KeyOp>) -> Vec<KeyOp> {
+    debug_assert!(!search(&ops));
+    let justs = justifiers(&ops);
+    let still_fails = |trial: &[usize]| {
+        let kept: HashSet<usize> = trial.iter().copied().collect();
+        trial
+            .iter()
+            .all(|&i| justs[i].is_none_or(|j| kept.contains(&j)))
+            && !search(&trial.iter().map(|&i| ops[i].clone()).collect::<Vec<_>>())
+    };
+
+    // binary search for a prefix boundary where the check starts failing
+    let mut idxs: Vec<usize> = (0..ops.len()).collect();
+    let (mut lo, mut hi) = (0, idxs.len()); // prefix lo passes, prefix hi fails
+    while hi - lo > 1 {
+        let mid = (lo + hi) / 2;
+        if !still_fails(&idxs[..mid]) {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    idxs.truncate(hi);
+
+    let mut chunk = idxs.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < idxs.len() {
+            let end = (start + chunk).min(idxs.len());
+            let trial: Vec<usize> = idxs[..start].iter().chain(&idxs[end..]).copied().collect();
+            if still_fails(&trial) {
+                idxs = trial;
+            } else {
+                start = end;
+            }
+        }
+        chunk /= 2;
+    }
+    idxs.into_iter().map(|i| ops[i].clone()).collect()
+}
+
+/// For each operation, find the operation that justifies what it observed:
+/// the writer of the observed value if known, otherwise the latest-called
+/// update that could have produced the observed existence (or absence).
+pub(super) fn justifiers(ops: &[KeyOp]) -> Vec<Option<usize>> {
+    ops.iter()
+        .map(|op| {
+            if let Some(value) = op.kind.observed() {
+                if let Some(j) = ops.iter().position(|w| w.kind.written() == Some(value)) {
+                    return Some(j);
+                }
+            }
+            let want_some = op.kind.observed_some();
+            ops.iter()
+                .enumerate()
+                .filter(|(_, w)| w.ts_call < op.ts_resp && !std::ptr::eq(*w, op))
+                .filter(|(_, w)| match &w.kind {
+                    KeyOpKind::Put { .. } | KeyOpKind::Swap { .. } => want_some,
+                    KeyOpKind::Delete { .. } => !want_some,
+                    KeyOpKind::Unknown { value } => value.is_some() == want_some,
+                    _ => false,
+                })
+                .max_by_key(|(_, w)| w.ts_call)
+                .map(|(j, _)| j)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Put of `value` to key `k`, returning whether a value was found.
+    fn put(cidx: usize, ts_call: u64, ts_resp: u64, value: &str, found: bool) -> OpRecord {
+        OpRecord {
+            cidx,
+            ts_call,
+            ts_resp,
+            call: KvCall::Put {
+                key: "k".into(),
+                value: value.into(),
+            },
+            resp: KvResp::Put {
+                key: "k".into(),
+                found,
+            },
+        }
+    }
+
+    /// Get of key `k`, returning `value`.
+    fn get(cidx: usize, ts_call: u64, ts_resp: u64, value: Option<&str>) -> OpRecord {
+        OpRecord {
+            cidx,
+            ts_call,
+            ts_resp,
+            call: KvCall::Get { key: "k".into() },
+            resp: KvResp::Get {
+                key: "k".into(),
+                value: value.map(|v| v.into()),
+            },
+        }
+    }
+
+    #[test]
+    fn sequential_accepted() {
+        let records = vec![
+            get(0, 1, 2, None),
+            put(0, 3, 4, "a", false),
+            get(1, 5, 6, Some("a")),
+            put(1, 7, 8, "b", true),
+            get(0, 9, 10, Some("b")),
+        ];
+        assert!(check_linearizable(&records).is_none());
+    }
+
+    #[test]
+    fn concurrent_accepted() {
+        // the get overlaps both puts, so it may take effect between them
+        let records = vec![
+            put(0, 1, 4, "a", false),
+            get(1, 2, 7, Some("a")),
+            put(2, 3, 6, "b", true),
+            get(0, 8, 9, Some("b")),
+        ];
+        assert!(check_linearizable(&records).is_none());
+    }
+
+    #[test]
+    fn stale_read_rejected() {
+        let records = vec![
+            put(0, 1, 2, "a", false),
+            put(0, 3, 4, "b", true),
+            get(1, 5, 6, Some("a")),
+        ];
+        let violation = check_linearizable(&records).unwrap();
+        assert_eq!(violation.key, "k");
+        assert!(!violation.records.is_empty());
+    }
+
+    #[test]
+    fn unwritten_read_rejected() {
+        let records = vec![put(0, 1, 2, "a", false), get(1, 3, 4, Some("z"))];
+        assert!(check_linearizable(&records).is_some());
+    }
+
+    #[test]
+    fn unknown_write_may_apply_later() {
+        let unknown = OpRecord {
+            cidx: 0,
+            ts_call: 1,
+            ts_resp: 2,
+            call: KvCall::Put {
+                key: "k".into(),
+                value: "a".into(),
+            },
+            resp: KvResp::Unknown {
+                op: "PUT".into(),
+                key: "k".into(),
+            },
+        };
+        let records = vec![unknown, get(1, 3, 4, None), get(1, 5, 6, Some("a"))];
+        assert!(check_linearizable(&records).is_none());
+
+        // once observed, the write must have taken effect for good
+        let records = vec![
+            records[0].clone(),
+            get(1, 3, 4, Some("a")),
+            get(1, 5, 6, None),
+        ];
+        assert!(check_linearizable(&records).is_some());
+    }
+}
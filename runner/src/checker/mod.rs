@@ -0,0 +1,160 @@
+//! Consistency checkers over recorded operation histories.
+
+use std::fmt;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+
+use crate::{Direction, KvCall, KvResp, RunnerError};
+
+mod approx;
+pub use approx::{ApproxOutcome, History};
+
+mod histfile;
+pub use histfile::{HistoryFile, HistoryWriter};
+
+mod linear;
+pub use linear::{check_linearizable, LinearViolation};
+
+mod ordering;
+pub use ordering::{
+    classify_consistency, ConsistencyLevel, ConsistencyReport, ConvergenceLevel, RelationshipLevel,
+};
+
+mod scan;
+pub use scan::check_scans_atomic;
+
+mod report;
+pub use report::{
+    check_and_report, print_faults, print_record, print_timeline, report_inconsistent,
+    report_remaining, report_sessions, shrink_and_report, REMAIN_THRESH,
+};
+
+mod serial;
+pub use serial::{check_serializable, DepKind, SerialAnomaly, SerialViolation};
+
+mod session;
+pub use session::{check_sessions, SessionGuarantee, SessionReport, SessionViolation};
+
+mod shrink;
+pub use shrink::shrink_history;
+
+/// Consistency checker mode selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CheckerKind {
+    /// Approximate real-time causal consistency checker, streaming.
+    Approx,
+    /// Full linearizability checker over the complete history.
+    Linearizable,
+    /// Strongest consistency level classifier over the complete history.
+    Classify,
+    /// Serializability checker over transactions in the complete history.
+    Serializable,
+}
+
+/// Options that apply across consistency checkers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckOptions {
+    /// Require every scan to be explainable by a single point in time,
+    /// instead of each key in range independently. Applies to the
+    /// approximate and linearizability checkers.
+    pub scan_atomic: bool,
+}
+
+/// Fault action injected into the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Node crashed with SIGKILL.
+    Kill,
+    /// Node shut down with SIGTERM.
+    Term,
+    /// Node restarted on the same storage.
+    Restart,
+    /// Node or client paused with SIGSTOP.
+    Pause,
+    /// Node or client resumed with SIGCONT.
+    Resume,
+    /// Proxied link cut in the direction.
+    Cut(Direction),
+    /// Proxied link healed in both directions.
+    Heal,
+}
+
+impl fmt::Display for FaultAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaultAction::Kill => write!(f, "KILL"),
+            FaultAction::Term => write!(f, "TERM"),
+            FaultAction::Restart => write!(f, "RESTART"),
+            FaultAction::Pause => write!(f, "PAUSE"),
+            FaultAction::Resume => write!(f, "RESUME"),
+            FaultAction::Cut(Direction::Both) => write!(f, "CUT"),
+            FaultAction::Cut(Direction::Fwd) => write!(f, "CUT-FWD"),
+            FaultAction::Cut(Direction::Rev) => write!(f, "CUT-REV"),
+            FaultAction::Heal => write!(f, "HEAL"),
+        }
+    }
+}
+
+impl FromStr for FaultAction {
+    type Err = RunnerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "KILL" => Ok(FaultAction::Kill),
+            "TERM" => Ok(FaultAction::Term),
+            "RESTART" => Ok(FaultAction::Restart),
+            "PAUSE" => Ok(FaultAction::Pause),
+            "RESUME" => Ok(FaultAction::Resume),
+            "CUT" => Ok(FaultAction::Cut(Direction::Both)),
+            "CUT-FWD" => Ok(FaultAction::Cut(Direction::Fwd)),
+            "CUT-REV" => Ok(FaultAction::Cut(Direction::Rev)),
+            "HEAL" => Ok(FaultAction::Heal),
+            _ => Err(RunnerError::Parse(format!("unknown fault action: {}", s))),
+        }
+    }
+}
+
+/// Record of a fault injected into the service during a run, which took
+/// effect after all operation events up to logical timestamp `ts`.
+#[derive(Debug, Clone)]
+pub struct FaultRecord {
+    pub ts: u64,
+    pub action: FaultAction,
+    pub target: String,
+}
+
+/// Complete record of an acknowledged operation.
+#[derive(Debug, Clone)]
+pub struct OpRecord {
+    pub cidx: usize,
+    pub ts_call: u64,
+    pub ts_resp: u64,
+    pub call: KvCall,
+    pub resp: KvResp,
+}
+
+impl OpRecord {
+    /// Returns the value update made by the operation, like
+    /// `KvCall::update_info()`, except that failed operations (including
+    /// compare-and-swaps that did not match) made none. An update of unknown
+    /// outcome is still returned; it may take effect at any time after its
+    /// call, or never.
+    pub fn update_info(&self) -> Option<(String, Option<String>)> {
+        match self.resp {
+            KvResp::Error { .. } | KvResp::Cas { ok: false, .. } => None,
+            _ => self.call.update_info(),
+        }
+    }
+
+    /// True unless the response contradicts its own call: a compare-and-swap
+    /// must succeed exactly if it found the expected value.
+    pub fn is_self_consistent(&self) -> bool {
+        match (&self.call, &self.resp) {
+            (KvCall::Cas { expected, .. }, KvResp::Cas { ok, current, .. }) => {
+                *ok == (current == expected)
+            }
+            _ => true,
+        }
+    }
+}
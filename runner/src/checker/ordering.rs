@@ -0,0 +1,399 @@
+//! Consistency level classifier over ordering graphs, ported from the P
+//! model's `pmodel/PSpec/Consistency.p`.
+//!
+//! Real runs do not expose the order in which the service applied operations,
+//! so an ordering graph is inferred per key from the recorded history: first
+//! by searching for a witness total order (a chain) that respects the
+//! strongest possible relationship constraint, and failing that, by linking
+//! every operation to the update whose result it observed (a reads-from DAG).
+//! The P model's convergence and relationship conformity checks then run over
+//! the inferred graph. The level of the whole history is the weakest across
+//! keys; constraints spanning different keys are not considered.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use bit_vec::BitVec;
+
+use super::linear::{partition_by_key, search_order, KeyOp, KeyOpKind, Precedence, SearchResult};
+use super::OpRecord;
+
+/// Maximum number of search frames pushed per witness order search.
+const SEARCH_BUDGET: usize = 100_000;
+
+/// Convergence constraint level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConvergenceLevel {
+    Invalid,
+    Npo,
+    Cpo,
+    So,
+}
+
+/// Relationship constraint level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RelationshipLevel {
+    None,
+    Fifo,
+    Casl,
+    Rt,
+}
+
+/// Named consistency model, each defined as a conjunction of a convergence
+/// and a relationship constraint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsistencyLevel {
+    Linearizability,
+    Sequential,
+    Causal,
+    Pram,
+    Eventual,
+    Weak,
+    Invalid,
+}
+
+impl ConsistencyLevel {
+    /// Named models from strongest to weakest, with their requirements.
+    const MODELS: [(ConsistencyLevel, ConvergenceLevel, RelationshipLevel); 6] = [
+        (
+            ConsistencyLevel::Linearizability,
+            ConvergenceLevel::So,
+            RelationshipLevel::Rt,
+        ),
+        (
+            ConsistencyLevel::Sequential,
+            ConvergenceLevel::So,
+            RelationshipLevel::Casl,
+        ),
+        (
+            ConsistencyLevel::Causal,
+            ConvergenceLevel::Cpo,
+            RelationshipLevel::Casl,
+        ),
+        (
+            ConsistencyLevel::Pram,
+            ConvergenceLevel::Npo,
+            RelationshipLevel::Fifo,
+        ),
+        (
+            ConsistencyLevel::Eventual,
+            ConvergenceLevel::Cpo,
+            RelationshipLevel::None,
+        ),
+        (
+            ConsistencyLevel::Weak,
+            ConvergenceLevel::Npo,
+            RelationshipLevel::None,
+        ),
+    ];
+
+    /// Strongest named model satisfied by the given constraint levels.
+    fn strongest(convergence: ConvergenceLevel, relationship: RelationshipLevel) -> Self {
+        Self::MODELS
+            .iter()
+            .find(|(_, conv, rel)| convergence >= *conv && relationship >= *rel)
+            .map(|(level, _, _)| *level)
+            .unwrap_or(ConsistencyLevel::Invalid)
+    }
+}
+
+impl fmt::Display for ConsistencyLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConsistencyLevel::Linearizability => write!(f, "linearizability"),
+            ConsistencyLevel::Sequential => write!(f, "sequential consistency"),
+            ConsistencyLevel::Causal => write!(f, "causal+ consistency"),
+            ConsistencyLevel::Pram => write!(f, "PRAM consistency"),
+            ConsistencyLevel::Eventual => write!(f, "eventual consistency"),
+            ConsistencyLevel::Weak => write!(f, "weak consistency"),
+            ConsistencyLevel::Invalid => write!(f, "none"),
+        }
+    }
+}
+
+/// Ordering graph node -- records the corresponding operation, register
+/// state after this operation, and out-going neighbor nodes.
+#[derive(Debug, Clone)]
+struct OrderingNode {
+    /// Client the operation came from, `None` for the initial state node.
+    cidx: Option<usize>,
+    kind: Option<KeyOpKind>,
+    ts_call: u64,
+    ts_resp: u64,
+    state: Option<String>,
+    next: BTreeSet<usize>,
+}
+
+impl OrderingNode {
+    /// Create a node for an operation, with the state it leaves behind.
+    fn of(op: &KeyOp) -> Self {
+        let state = match &op.kind {
+            KeyOpKind::Put { value, .. } | KeyOpKind::Swap { value, .. } => Some(value.clone()),
+            KeyOpKind::Get { value } | KeyOpKind::Unknown { value } => value.clone(),
+            KeyOpKind::Delete { .. } | KeyOpKind::Mismatch => None,
+        };
+        OrderingNode {
+            cidx: Some(op.cidx),
+            kind: Some(op.kind.clone()),
+            ts_call: op.ts_call,
+            ts_resp: op.ts_resp,
+            state,
+            next: BTreeSet::new(),
+        }
+    }
+}
+
+/// Ordering graph over the operations on a single key. Node 0 is the head
+/// node holding the initial (absent) state; node `i + 1` is operation `i`.
+#[derive(Debug, Clone)]
+struct OrderingGraph {
+    nodes: Vec<OrderingNode>,
+}
+
+impl OrderingGraph {
+    /// Head node ID.
+    const HEAD: usize = 0;
+
+    /// Create a graph of unconnected nodes for the given operations.
+    fn unconnected(ops: &[KeyOp]) -> Self {
+        let head = OrderingNode {
+            cidx: None,
+            kind: None,
+            ts_call: 0,
+            ts_resp: 0,
+            state: None,
+            next: BTreeSet::new(),
+        };
+        OrderingGraph {
+            nodes: [head]
+                .into_iter()
+                .chain(ops.iter().map(OrderingNode::of))
+                .collect(),
+        }
+    }
+
+    /// Build a chain graph following a witness total order.
+    fn chain(ops: &[KeyOp], order: &[usize]) -> Self {
+        let mut graph = Self::unconnected(ops);
+        let mut prev = Self::HEAD;
+        for &i in order {
+            graph.nodes[prev].next.insert(i + 1);
+            prev = i + 1;
+        }
+        graph
+    }
+
+    /// Build a reads-from DAG where every operation hangs off the update
+    /// whose result it observed: the writer of the observed value, which
+    /// must exist, otherwise the latest update called before it that it
+    /// could have observed.
+    fn reads_from(ops: &[KeyOp]) -> Self {
+        let mut graph = Self::unconnected(ops);
+        for (i, op) in ops.iter().enumerate() {
+            let parent = if let Some(value) = op.kind.observed() {
+                ops.iter()
+                    .position(|w| w.kind.written() == Some(value))
+                    .map(|j| j + 1)
+            } else {
+                ops.iter()
+                    .enumerate()
+                    .filter(|&(j, w)| {
+                        j != i
+                            && w.kind.is_write()
+                            && w.ts_call < op.ts_call
+                            && op.kind.step(&graph.nodes[j + 1].state).is_some()
+                    })
+                    .max_by_key(|(_, w)| w.ts_call)
+                    .map(|(j, _)| j + 1)
+            };
+            // fall back to the head node, which makes the transition invalid
+            // if the operation could not have observed the initial state
+            graph.nodes[parent.unwrap_or(Self::HEAD)].next.insert(i + 1);
+        }
+        graph
+    }
+
+    /// Check convergence constraint conformity level: SO, CPO, NPO, or
+    /// invalid? Uses a colored DFS traversal algorithm. Also returns the set
+    /// of nodes reachable from each node, useful for checking relationship
+    /// constraints later.
+    fn convergence_conformity(&self) -> (ConvergenceLevel, Vec<BitVec>) {
+        let n = self.nodes.len();
+        let mut level = ConvergenceLevel::So;
+        let mut stack = vec![Self::HEAD];
+        let mut color = vec![0u8; n]; // 0: unvisited, 1: in progress, 2: done
+        let mut reachable = vec![BitVec::from_elem(n, false); n];
+        let mut parents = vec![BTreeSet::new(); n];
+        let mut done = 0;
+
+        while let Some(&curr) = stack.last() {
+            if color[curr] == 0 {
+                // first visit, mark as in-progress
+                color[curr] = 1;
+
+                if level == ConvergenceLevel::So && self.nodes[curr].next.len() > 1 {
+                    // SO must have exactly one outgoing edge to the next node
+                    level = ConvergenceLevel::Cpo;
+                }
+
+                for &next in &self.nodes[curr].next {
+                    parents[next].insert(curr);
+                    match color[next] {
+                        0 => stack.push(next),
+                        // back edge found, cycle detected, not DAG
+                        1 => return (ConvergenceLevel::Invalid, reachable),
+                        // ignore fully visited nodes
+                        _ => {}
+                    }
+                }
+            } else {
+                if color[curr] == 1 {
+                    // in-progress node popped, means its subtree is fully
+                    // visited
+                    for &next in &self.nodes[curr].next {
+                        debug_assert_eq!(color[next], 2);
+                        let below = reachable[next].clone();
+                        reachable[curr].set(next, true);
+                        reachable[curr].or(&below);
+                    }
+                    color[curr] = 2;
+                    done += 1;
+                }
+                stack.pop();
+            }
+        }
+
+        // must have visited all nodes to be a connected graph from head
+        if done < n {
+            return (ConvergenceLevel::Invalid, reachable);
+        }
+
+        // check that state transitions are valid from at least one parent
+        for (node, node_parents) in self.nodes.iter().zip(&parents) {
+            let Some(kind) = &node.kind else {
+                continue; // head node
+            };
+            let valid = node_parents
+                .iter()
+                .any(|&p| kind.step(&self.nodes[p].state).as_ref() == Some(&node.state));
+            if !valid {
+                return (ConvergenceLevel::Npo, reachable);
+            }
+        }
+
+        (level, reachable)
+    }
+
+    /// Check relationship constraint conformity level: RT, CASL, FIFO, or
+    /// less? Each client is strictly single-threaded, hence no "cluster of
+    /// reads". Unlike the P model's single pass, every level is checked
+    /// against all pairs of nodes.
+    fn relationship_conformity(&self, reachable: &[BitVec]) -> RelationshipLevel {
+        let (mut rt, mut casl, mut fifo) = (true, true, true);
+
+        for (na, a) in self.nodes.iter().enumerate().skip(1) {
+            for (nb, b) in self.nodes.iter().enumerate().skip(1) {
+                if na == nb || a.ts_resp >= b.ts_call || reachable[na].get(nb).unwrap() {
+                    continue;
+                }
+
+                // nb is not ordered after na although na returned first
+                rt = false;
+                if a.cidx == b.cidx {
+                    casl = false;
+                    let a_write = a.kind.as_ref().unwrap().is_write();
+                    let b_write = b.kind.as_ref().unwrap().is_write();
+                    if a_write || !b_write {
+                        // not a write-follow-read situation
+                        fifo = false;
+                    }
+                }
+            }
+        }
+
+        if rt {
+            RelationshipLevel::Rt
+        } else if casl {
+            RelationshipLevel::Casl
+        } else if fifo {
+            RelationshipLevel::Fifo
+        } else {
+            RelationshipLevel::None
+        }
+    }
+
+    /// Generic satisfaction check conjuncting convergence and relationship
+    /// constraints.
+    fn satisfied_constraints(&self) -> (ConvergenceLevel, RelationshipLevel) {
+        let (convergence, reachable) = self.convergence_conformity();
+        if convergence == ConvergenceLevel::Invalid {
+            // no point checking relationship if graph malformed
+            return (ConvergenceLevel::Invalid, RelationshipLevel::None);
+        }
+        (convergence, self.relationship_conformity(&reachable))
+    }
+}
+
+/// Per-key and overall consistency levels satisfied by a history.
+#[derive(Debug, Clone)]
+pub struct ConsistencyReport {
+    /// Strongest named model satisfied by the whole history.
+    pub level: ConsistencyLevel,
+    pub convergence: ConvergenceLevel,
+    pub relationship: RelationshipLevel,
+    /// Key with the weakest constraint levels, if any key was touched.
+    pub weakest_key: Option<String>,
+}
+
+/// Classify the strongest consistency level satisfied by a complete history.
+pub fn classify_consistency(records: &[OpRecord]) -> ConsistencyReport {
+    let mut convergence = ConvergenceLevel::So;
+    let mut relationship = RelationshipLevel::Rt;
+    let mut weakest_key = None;
+
+    for (key, ops) in partition_by_key(records) {
+        let (conv, rel) = classify_key(&ops);
+        if conv < convergence || rel < relationship {
+            weakest_key = Some(key);
+        }
+        convergence = convergence.min(conv);
+        relationship = relationship.min(rel);
+    }
+
+    ConsistencyReport {
+        level: ConsistencyLevel::strongest(convergence, relationship),
+        convergence,
+        relationship,
+        weakest_key,
+    }
+}
+
+/// Classify the constraint levels satisfied by a single-key sub-history,
+/// preferring a witness total order under the strongest precedence. A read
+/// of a value that no operation wrote satisfies no constraint at all.
+fn classify_key(ops: &[KeyOp]) -> (ConvergenceLevel, RelationshipLevel) {
+    if reads_unwritten(ops) {
+        return (ConvergenceLevel::Invalid, RelationshipLevel::None);
+    }
+    for prec in [
+        Precedence::RealTime,
+        Precedence::Session,
+        Precedence::SessionFifo,
+        Precedence::Unordered,
+    ] {
+        if let SearchResult::Found(order) = search_order(ops, prec, SEARCH_BUDGET) {
+            return OrderingGraph::chain(ops, &order).satisfied_constraints();
+        }
+    }
+    OrderingGraph::reads_from(ops).satisfied_constraints()
+}
+
+/// True if some operation observed a value that no operation on the key
+/// wrote (or may have written).
+fn reads_unwritten(ops: &[KeyOp]) -> bool {
+    ops.iter().any(|op| {
+        op.kind
+            .observed()
+            .is_some_and(|value| !ops.iter().any(|w| w.kind.written() == Some(value)))
+    })
+}
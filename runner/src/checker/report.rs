@@ -0,0 +1,343 @@
+//! Running checkers over complete histories and printing their results.
+
+use std::collections::BTreeSet;
+
+use color_print::cprintln;
+
+use super::histfile::{fmt_call, fmt_resp};
+use super::{
+    check_linearizable, check_scans_atomic, check_serializable, check_sessions,
+    classify_consistency, shrink_history, ApproxOutcome, CheckOptions, CheckerKind,
+    ConsistencyLevel, FaultRecord, History, OpRecord, SessionGuarantee,
+};
+
+/// Maximum number of distinct timestamps to draw timeline bars for.
+const TIMELINE_WIDTH: usize = 64;
+
+/// Threshold of remaining approximate checks above which the run is deemed
+/// unfair to some clients.
+pub const REMAIN_THRESH: usize = 1000;
+
+/// Run the chosen checker over a complete history and print its result
+/// under the given title, after making sure that every response is
+/// consistent with its own call. Returns true if the history passed.
+pub fn check_and_report(
+    kind: CheckerKind,
+    opts: &CheckOptions,
+    keys: &[Vec<String>],
+    records: &[OpRecord],
+    title: &str,
+) -> bool {
+    if let Some(record) = records.iter().find(|r| !r.is_self_consistent()) {
+        report_inconsistent(record);
+        cprintln!("<s><yellow>{}:</></> <red>FAILED</>", title);
+        return false;
+    }
+    match kind {
+        CheckerKind::Approx => report_approx(opts, keys, records, title),
+        CheckerKind::Linearizable => report_linearizable(opts, records, title),
+        CheckerKind::Classify => report_classify(records, title),
+        CheckerKind::Serializable => report_serializable(records, title),
+    }
+}
+
+/// Print one operation record on a line with given indentation.
+pub fn print_record(record: &OpRecord, indent: usize) {
+    println!(
+        "{:indent$}client {}  <<{} - {}>>  {:?}  ->  {:?}",
+        "",
+        record.cidx,
+        record.ts_call,
+        record.ts_resp,
+        record.call,
+        record.resp,
+        indent = indent
+    );
+}
+
+/// Print an operation record whose response contradicts its own call.
+pub fn report_inconsistent(record: &OpRecord) {
+    cprintln!(
+        "<s><red>Inconsistent CAS response:</></>  client {}  <<{} - {}>>",
+        record.cidx,
+        record.ts_call,
+        record.ts_resp
+    );
+    println!("  Resp: {:?}", record.resp);
+}
+
+/// Print the injected faults relevant to the given operation records: the
+/// last fault preceding the earliest call, and all faults up to the latest
+/// response.
+pub fn print_faults(faults: &[FaultRecord], records: &[OpRecord], indent: usize) {
+    let (Some(first), Some(last)) = (
+        records.iter().map(|r| r.ts_call).min(),
+        records.iter().map(|r| r.ts_resp).max(),
+    ) else {
+        return;
+    };
+    let mut order: Vec<&FaultRecord> = faults.iter().filter(|f| f.ts < last).collect();
+    order.sort_by_key(|f| f.ts);
+    let start = order.iter().rposition(|f| f.ts < first).unwrap_or(0);
+    for fault in &order[start..] {
+        println!(
+            "{:indent$}after <<{}>>  {} {}",
+            "",
+            fault.ts,
+            fault.action,
+            fault.target,
+            indent = indent
+        );
+    }
+}
+
+/// Shrink a failing history with the chosen checker and print the minimal
+/// failing history as a per-client timeline, followed by the injected faults
+/// that preceded or overlapped it.
+pub fn shrink_and_report(
+    kind: CheckerKind,
+    opts: &CheckOptions,
+    keys: &[Vec<String>],
+    records: &[OpRecord],
+    faults: &[FaultRecord],
+) {
+    cprintln!("<s><yellow>Shrinking failing history...</></>");
+    let Some(shrunk) = shrink_history(kind, opts, keys, records) else {
+        println!("  Nothing to shrink: history passes the {:?} checker", kind);
+        return;
+    };
+    let num_clis = shrunk.iter().map(|r| r.cidx).collect::<BTreeSet<_>>().len();
+    println!(
+        "  Minimal failing history  ({} ops, {} clients):",
+        shrunk.len(),
+        num_clis
+    );
+    print_timeline(&shrunk, 4);
+    let last = shrunk.iter().map(|r| r.ts_resp).max().unwrap_or(0);
+    if faults.iter().any(|f| f.ts < last) {
+        println!("  Faults injected before and during it:");
+        print_faults(faults, &shrunk, 4);
+    }
+}
+
+/// Print a history as a per-client timeline, each client's operations in call
+/// order. If there are few enough distinct timestamps, each operation is drawn
+/// as a bar spanning from its call to its response.
+pub fn print_timeline(records: &[OpRecord], indent: usize) {
+    let ticks: Vec<u64> = records
+        .iter()
+        .flat_map(|r| [r.ts_call, r.ts_resp])
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let tick_of = |ts: u64| ticks.binary_search(&ts).unwrap();
+
+    let mut order: Vec<&OpRecord> = records.iter().collect();
+    order.sort_by_key(|r| (r.cidx, r.ts_call));
+    let mut last_cidx = None;
+    for record in order {
+        let label = if last_cidx != Some(record.cidx) {
+            format!("client {}", record.cidx)
+        } else {
+            String::new()
+        };
+        last_cidx = Some(record.cidx);
+
+        let bar = if ticks.len() <= TIMELINE_WIDTH {
+            let (call, resp) = (tick_of(record.ts_call), tick_of(record.ts_resp));
+            let bar: String = (0..ticks.len())
+                .map(|t| match t {
+                    t if t == call => '[',
+                    t if t == resp => ']',
+                    t if t > call && t < resp => '-',
+                    _ => ' ',
+                })
+                .collect();
+            format!("|{}|  ", bar)
+        } else {
+            String::new()
+        };
+
+        println!(
+            "{:indent$}{:10}{}<<{} - {}>>  {}  ->  {}",
+            "",
+            label,
+            bar,
+            record.ts_call,
+            record.ts_resp,
+            fmt_call(&record.call),
+            fmt_resp(&record.resp),
+            indent = indent
+        );
+    }
+}
+
+/// Print the result of an approximate check given the number of remaining
+/// checks queued. Returns true if passed.
+pub fn report_remaining(remaining: usize, title: &str) -> bool {
+    if remaining >= REMAIN_THRESH {
+        // too many remaining checks, meaning some clients were not
+        // completing requests and were lagging too much behind others
+        cprintln!("<s><yellow>{}:</></> <red>UNFAIR</>", title);
+        println!(
+            "  Remaining checks queued:  {}  <s><red>too many!</></>",
+            remaining
+        );
+        false
+    } else {
+        // test (approximately) passed
+        cprintln!("<s><yellow>{}:</></> <green>PASSED</>", title);
+        println!("  Remaining checks queued:  {}  reasonable", remaining);
+        true
+    }
+}
+
+/// Check the session guarantees over a complete history and print which of
+/// them hold, with an example violation of each one that does not and the
+/// per-client violation counts. Returns true if all of them hold.
+pub fn report_sessions(records: &[OpRecord]) -> bool {
+    let report = check_sessions(records);
+    cprintln!("<s><yellow>Session guarantees:</></>");
+    for guarantee in SessionGuarantee::ALL {
+        let idx = guarantee as usize;
+        let Some(example) = &report.examples[idx] else {
+            cprintln!("  {:22}<green>HOLDS</>", guarantee.to_string());
+            continue;
+        };
+        let total: usize = report.counts.iter().map(|c| c[idx]).sum();
+        cprintln!(
+            "  {:22}<red>VIOLATED</>  by {} reads, e.g.:",
+            guarantee.to_string(),
+            total
+        );
+        print_record(&example.read, 4);
+        println!("    stale against:");
+        print_record(&example.required, 4);
+    }
+
+    if report.examples.iter().any(|e| e.is_some()) {
+        println!("  Violating reads per client:  (RYW  MR  MW  WFR)");
+        for (cidx, counts) in report.counts.iter().enumerate() {
+            println!(
+                "    client {:<4} {:>4} {:>4} {:>4} {:>4}",
+                cidx, counts[0], counts[1], counts[2], counts[3]
+            );
+        }
+        false
+    } else {
+        true
+    }
+}
+
+/// Replay a complete history through the approximate checker and print
+/// result.
+fn report_approx(
+    opts: &CheckOptions,
+    keys: &[Vec<String>],
+    records: &[OpRecord],
+    title: &str,
+) -> bool {
+    match History::replay(keys.len(), keys, records, opts.scan_atomic) {
+        ApproxOutcome::Passed(remaining) => report_remaining(remaining, title),
+        ApproxOutcome::Violation { trigger, failed } => {
+            cprintln!(
+                "<s><red>Consistency violation!</></>  Trigger:  client {}  <<{} - {}>>",
+                trigger.cidx,
+                trigger.ts_call,
+                trigger.ts_resp
+            );
+            println!(
+                "  Resp: {:?}  client {}  <<{} - {}>>",
+                failed.resp, failed.cidx, failed.ts_call, failed.ts_resp
+            );
+            cprintln!("<s><yellow>{}:</></> <red>FAILED</>", title);
+            false
+        }
+        ApproxOutcome::UnexpectedKey(record) => {
+            cprintln!(
+                "<s><red>Unexpected update key found:</></>  client {}  <<{} - {}>>",
+                record.cidx,
+                record.ts_call,
+                record.ts_resp
+            );
+            cprintln!("<s><yellow>{}:</></> <red>FAILED</>", title);
+            false
+        }
+    }
+}
+
+/// Run the linearizability check over a complete history and print result.
+fn report_linearizable(opts: &CheckOptions, records: &[OpRecord], title: &str) -> bool {
+    if let Some(violation) = check_linearizable(records) {
+        cprintln!("<s><yellow>{}:</></> <red>FAILED</>", title);
+        println!(
+            "  Non-linearizable sub-history on key {}  ({} ops, minimal):",
+            violation.key,
+            violation.records.len()
+        );
+        for record in &violation.records {
+            print_record(record, 4);
+        }
+        false
+    } else if let Some(scan) = opts
+        .scan_atomic
+        .then(|| check_scans_atomic(records))
+        .flatten()
+    {
+        cprintln!("<s><yellow>{}:</></> <red>FAILED</>", title);
+        println!("  Scan not explainable by a single point in time:");
+        print_record(&scan, 4);
+        false
+    } else {
+        cprintln!("<s><yellow>{}:</></> <green>PASSED</>", title);
+        println!("  Linearizable history of {} ops", records.len());
+        true
+    }
+}
+
+/// Classify the consistency level of a complete history and print result.
+fn report_classify(records: &[OpRecord], title: &str) -> bool {
+    let report = classify_consistency(records);
+    let level_str = report.level.to_string().to_uppercase();
+    if report.level == ConsistencyLevel::Invalid {
+        cprintln!("<s><yellow>{}:</></> <red>FAILED</>", title);
+    } else if report.level == ConsistencyLevel::Linearizability {
+        cprintln!("<s><yellow>{}:</></> <green>{}</>", title, level_str);
+    } else {
+        cprintln!("<s><yellow>{}:</></> <magenta>{}</>", title, level_str);
+    }
+    println!(
+        "  Constraints satisfied:  convergence {:?}  relationship {:?}",
+        report.convergence, report.relationship
+    );
+    if let Some(key) = report.weakest_key {
+        println!("  Weakest key:  {}", key);
+    }
+    report.level != ConsistencyLevel::Invalid
+}
+
+/// Run the serializability check over a complete history and print result.
+fn report_serializable(records: &[OpRecord], title: &str) -> bool {
+    let Some(violation) = check_serializable(records) else {
+        cprintln!("<s><yellow>{}:</></> <green>PASSED</>", title);
+        println!("  Serializable history of {} ops", records.len());
+        return true;
+    };
+    cprintln!("<s><yellow>{}:</></> <red>FAILED</>", title);
+    if violation.edges.is_empty() {
+        println!("  Found {}:", violation.anomaly);
+    } else {
+        println!(
+            "  Found {} among {} transactions:",
+            violation.anomaly,
+            violation.records.len()
+        );
+    }
+    for (i, record) in violation.records.iter().enumerate() {
+        print_record(record, 4);
+        if let Some(kind) = violation.edges.get(i) {
+            println!("      --{}-->", kind);
+        }
+    }
+    false
+}
@@ -0,0 +1,132 @@
+//! Scan atomicity check: whether all entries of a scan can be explained by a
+//! single point in time.
+//!
+//! Time points strictly between two consecutive logical timestamps `m` and
+//! `m+1` are represented by `m`. A write can be the current value of its key
+//! at such a point if it was called before the point and no other write on
+//! the key definitely happened after it and definitely before the point.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::OpRecord;
+use crate::{KvCall, KvResp};
+
+/// Timestamp span and written value (`None` for a delete) of a write.
+pub(super) type WriteSpan<'a> = (u64, u64, Option<&'a String>);
+
+/// Half-open ranges `[lo, hi)` of points at which the key could have held
+/// the observed value, given all writes on the key.
+pub(super) fn current_windows(writes: &[WriteSpan], observed: Option<&String>) -> Vec<(u64, u64)> {
+    writes
+        .iter()
+        .filter(|(_, _, value)| *value == observed)
+        .map(|&(ts_call, ts_resp, _)| {
+            let overwritten = writes
+                .iter()
+                .filter(|(w_call, _, _)| *w_call > ts_resp)
+                .map(|(_, w_resp, _)| *w_resp)
+                .min()
+                .unwrap_or(u64::MAX);
+            (ts_call, overwritten)
+        })
+        .filter(|(lo, hi)| lo < hi)
+        .collect()
+}
+
+/// Returns true if there is a common point within `[ts_call, ts_resp)` that
+/// falls in some window of every key.
+pub(super) fn has_common_point(
+    ts_call: u64,
+    ts_resp: u64,
+    per_key_windows: impl IntoIterator<Item = Vec<(u64, u64)>>,
+) -> bool {
+    let mut feasible = vec![(ts_call, ts_resp)];
+    for windows in per_key_windows {
+        let mut next: Vec<(u64, u64)> = vec![];
+        for &(f_lo, f_hi) in &feasible {
+            for &(w_lo, w_hi) in &windows {
+                let (lo, hi) = (f_lo.max(w_lo), f_hi.min(w_hi));
+                if lo < hi {
+                    next.push((lo, hi));
+                }
+            }
+        }
+
+        // merge overlapping ranges to keep the feasible set small
+        next.sort_unstable();
+        feasible.clear();
+        for (lo, hi) in next {
+            match feasible.last_mut() {
+                Some(last) if lo <= last.1 => last.1 = last.1.max(hi),
+                _ => feasible.push((lo, hi)),
+            }
+        }
+        if feasible.is_empty() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Check that every scan in a complete history is atomic, i.e., explainable
+/// by a single point in time across all known keys in range. Returns the
+/// first scan that is not, if any.
+pub fn check_scans_atomic(records: &[OpRecord]) -> Option<OpRecord> {
+    // gather every write per key, with a dummy initial delete
+    let mut writes: BTreeMap<&String, Vec<WriteSpan>> = BTreeMap::new();
+    let mut keys: BTreeSet<&String> = BTreeSet::new();
+    for record in records {
+        let written = match (&record.call, &record.resp) {
+            (KvCall::Put { key, value }, KvResp::Put { .. } | KvResp::Unknown { .. })
+            | (KvCall::Swap { key, value }, KvResp::Swap { .. } | KvResp::Unknown { .. })
+            | (
+                KvCall::Cas { key, value, .. },
+                KvResp::Cas { ok: true, .. } | KvResp::Unknown { .. },
+            ) => Some((key, Some(value))),
+            (KvCall::Delete { key }, KvResp::Delete { .. } | KvResp::Unknown { .. }) => {
+                Some((key, None))
+            }
+            (KvCall::Get { key } | KvCall::Cas { key, .. }, _) => {
+                keys.insert(key);
+                None
+            }
+            _ => None,
+        };
+        if let Some((key, value)) = written {
+            // an update of unknown outcome may take effect at any later time
+            let ts_resp = if record.resp.is_unknown() {
+                u64::MAX
+            } else {
+                record.ts_resp
+            };
+            keys.insert(key);
+            writes
+                .entry(key)
+                .or_insert_with(|| vec![(0, 0, None)])
+                .push((record.ts_call, ts_resp, value));
+        }
+        if let KvResp::Scan { entries, .. } = &record.resp {
+            keys.extend(entries.iter().map(|(k, _)| k));
+        }
+    }
+    let initial = vec![(0, 0, None)];
+
+    for record in records {
+        if let KvResp::Scan {
+            key_start,
+            key_end,
+            entries,
+        } = &record.resp
+        {
+            let entries: BTreeMap<&String, &String> = entries.iter().map(|(k, v)| (k, v)).collect();
+            let per_key_windows = keys.range::<&String, _>(key_start..=key_end).map(|key| {
+                let key_writes = writes.get(key).unwrap_or(&initial);
+                current_windows(key_writes, entries.get(key).copied())
+            });
+            if !has_common_point(record.ts_call, record.ts_resp, per_key_windows) {
+                return Some(record.clone());
+            }
+        }
+    }
+    None
+}
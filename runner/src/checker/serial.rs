@@ -0,0 +1,544 @@
+//! Serializability checker over transactions in complete recorded histories.
+//!
+//! Every record is taken as a transaction: a `TXN` call as its operations in
+//! order, and any other call as a transaction of a single operation. Written
+//! values are assumed unique per key (as the fuzzer makes them, almost
+//! surely), so that every read of a value traces back to the one transaction
+//! that wrote it; values written more than once are left ambiguous. Following
+//! Adya's direct serialization graph, transactions are then linked by
+//! dependency edges:
+//!
+//!   - write-read (wr): T2 read a value that T1 wrote
+//!   - write-write (ww): T2 overwrote a value that T1 wrote, known when T2
+//!     read that value before writing the key itself
+//!   - read-write (rw): T1 read a value that T2 overwrote
+//!
+//! A read of a missing key observes the initial state as long as the key is
+//! never deleted, so it precedes every write of the key. The history is
+//! serializable only if the graph is acyclic. Since the version order of a
+//! key is only partially known from reads, every cycle found is a real
+//! anomaly, but not every anomaly gets found. Reads of values written by
+//! aborted transactions or failed operations (G1a), or overwritten by their
+//! writers within themselves (G1b), are reported as well. Transactions of
+//! unknown outcome are taken as committed once some other transaction read
+//! one of their writes, and as never having taken effect otherwise.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use super::OpRecord;
+use crate::{KvCall, KvResp};
+
+/// Kind of dependency edge between two transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DepKind {
+    WriteWrite,
+    WriteRead,
+    ReadWrite,
+}
+
+impl fmt::Display for DepKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DepKind::WriteWrite => write!(f, "ww"),
+            DepKind::WriteRead => write!(f, "wr"),
+            DepKind::ReadWrite => write!(f, "rw"),
+        }
+    }
+}
+
+/// Anomaly found by the serializability checker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialAnomaly {
+    /// Read of a value that no transaction wrote.
+    GarbageRead,
+    /// Read not matching an earlier write of its own transaction, or
+    /// response not matching the call.
+    Internal,
+    /// Read of a value written by an aborted transaction or failed
+    /// operation (G1a).
+    AbortedRead,
+    /// Read of a value that its writer overwrote within itself (G1b).
+    IntermediateRead,
+    /// Cycle of write-write dependencies only (G0).
+    WriteCycle,
+    /// Cycle of write-write and write-read dependencies (G1c).
+    CircularInfoFlow,
+    /// Cycle involving read-write anti-dependencies (G2).
+    AntiDepCycle,
+}
+
+impl fmt::Display for SerialAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SerialAnomaly::GarbageRead => write!(f, "garbage read"),
+            SerialAnomaly::Internal => write!(f, "internal inconsistency"),
+            SerialAnomaly::AbortedRead => write!(f, "aborted read (G1a)"),
+            SerialAnomaly::IntermediateRead => write!(f, "intermediate read (G1b)"),
+            SerialAnomaly::WriteCycle => write!(f, "write cycle (G0)"),
+            SerialAnomaly::CircularInfoFlow => write!(f, "circular information flow (G1c)"),
+            SerialAnomaly::AntiDepCycle => write!(f, "anti-dependency cycle (G2)"),
+        }
+    }
+}
+
+/// Serializability violation found in a history.
+#[derive(Debug, Clone)]
+pub struct SerialViolation {
+    pub anomaly: SerialAnomaly,
+    /// Transactions involved: those along the cycle in order, or else the
+    /// reader followed by the writer of what it read, if any.
+    pub records: Vec<OpRecord>,
+    /// Dependency kinds along the cycle, the i-th one from the i-th record
+    /// to the next (wrapping around); empty if not a cycle.
+    pub edges: Vec<DepKind>,
+}
+
+/// Outcome of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Committed,
+    Aborted,
+    Unknown,
+}
+
+/// Single-key access made by a transaction; `None` stands for a missing
+/// key when read, and for a delete when written.
+#[derive(Debug)]
+enum Access {
+    Read(String, Option<String>),
+    Write(String, Option<String>),
+}
+
+/// Transaction view of an operation record.
+#[derive(Debug)]
+struct TxnView {
+    outcome: Outcome,
+    /// Reads of keys not written before by the transaction itself.
+    ext_reads: Vec<(String, Option<String>)>,
+    /// Last value written to each key.
+    finals: HashMap<String, Option<String>>,
+    /// Values written to keys but overwritten by the transaction itself.
+    intermediates: HashSet<(String, String)>,
+    /// True if the response does not match the call, or some read does not
+    /// match an earlier write of the transaction.
+    internal: bool,
+}
+
+/// Collect the accesses made by a call given its response, returning false
+/// if the response does not match the call. Scans read every known key in
+/// range.
+fn op_accesses(
+    call: &KvCall,
+    resp: &KvResp,
+    keys: &BTreeSet<String>,
+    out: &mut Vec<Access>,
+) -> bool {
+    match (call, resp) {
+        (KvCall::Put { key, value }, KvResp::Put { found, .. }) => {
+            if !found {
+                out.push(Access::Read(key.clone(), None));
+            }
+            out.push(Access::Write(key.clone(), Some(value.clone())));
+        }
+        (KvCall::Swap { key, value }, KvResp::Swap { old_value, .. }) => {
+            out.push(Access::Read(key.clone(), old_value.clone()));
+            out.push(Access::Write(key.clone(), Some(value.clone())));
+        }
+        (KvCall::Get { key }, KvResp::Get { value, .. }) => {
+            out.push(Access::Read(key.clone(), value.clone()));
+        }
+        (KvCall::Scan { key_start, key_end }, KvResp::Scan { entries, .. }) => {
+            let entries: BTreeMap<&String, &String> = entries.iter().map(|(k, v)| (k, v)).collect();
+            if entries.keys().any(|k| *k < key_start || *k > key_end) {
+                return false;
+            }
+            for key in keys.range(key_start.clone()..=key_end.clone()) {
+                out.push(Access::Read(
+                    key.clone(),
+                    entries.get(key).map(|v| (*v).clone()),
+                ));
+            }
+        }
+        (KvCall::Delete { key }, KvResp::Delete { found, .. }) => {
+            if !found {
+                out.push(Access::Read(key.clone(), None));
+            }
+            out.push(Access::Write(key.clone(), None));
+        }
+        (KvCall::Cas { key, value, .. }, KvResp::Cas { ok, current, .. }) => {
+            out.push(Access::Read(key.clone(), current.clone()));
+            if *ok {
+                out.push(Access::Write(key.clone(), Some(value.clone())));
+            }
+        }
+        (
+            KvCall::Txn { ops },
+            KvResp::Txn {
+                results: Some(results),
+            },
+        ) => {
+            return ops.len() == results.len()
+                && ops.iter().zip(results).all(|(op, result)| {
+                    matches!(
+                        op,
+                        KvCall::Get { .. } | KvCall::Put { .. } | KvCall::Delete { .. }
+                    ) && op_accesses(op, result, keys, out)
+                });
+        }
+        (KvCall::Stop, KvResp::Stop) => {}
+        _ => return false,
+    }
+    true
+}
+
+/// Collect the writes a call would make if it took effect.
+fn call_writes(call: &KvCall, out: &mut Vec<Access>) {
+    match call {
+        KvCall::Txn { ops } => ops.iter().for_each(|op| call_writes(op, out)),
+        _ => {
+            if let Some((key, value)) = call.update_info() {
+                out.push(Access::Write(key, value));
+            }
+        }
+    }
+}
+
+/// Gather all keys ever mentioned by calls or returned by scans.
+fn known_keys(records: &[OpRecord]) -> BTreeSet<String> {
+    fn call_keys(call: &KvCall, keys: &mut BTreeSet<String>) {
+        match call {
+            KvCall::Put { key, .. }
+            | KvCall::Swap { key, .. }
+            | KvCall::Get { key }
+            | KvCall::Delete { key }
+            | KvCall::Cas { key, .. } => {
+                keys.insert(key.clone());
+            }
+            KvCall::Txn { ops } => ops.iter().for_each(|op| call_keys(op, keys)),
+            _ => {}
+        }
+    }
+    let mut keys = BTreeSet::new();
+    for record in records {
+        call_keys(&record.call, &mut keys);
+        if let KvResp::Scan { entries, .. } = &record.resp {
+            keys.extend(entries.iter().map(|(k, _)| k.clone()));
+        }
+    }
+    keys
+}
+
+/// Build the transaction view of every record.
+fn txn_views(records: &[OpRecord]) -> Vec<TxnView> {
+    let keys = known_keys(records);
+    records
+        .iter()
+        .map(|record| {
+            let outcome = match &record.resp {
+                KvResp::Error { .. } | KvResp::Txn { results: None } => Outcome::Aborted,
+                KvResp::Unknown { .. } => Outcome::Unknown,
+                _ => Outcome::Committed,
+            };
+            let mut accesses = vec![];
+            let mut internal = false;
+            if outcome == Outcome::Committed {
+                internal = !op_accesses(&record.call, &record.resp, &keys, &mut accesses);
+            } else {
+                call_writes(&record.call, &mut accesses);
+            }
+
+            let mut view = TxnView {
+                outcome,
+                ext_reads: vec![],
+                finals: HashMap::new(),
+                intermediates: HashSet::new(),
+                internal,
+            };
+            for access in accesses {
+                match access {
+                    Access::Read(key, value) => match view.finals.get(&key) {
+                        Some(own) => view.internal |= *own != value,
+                        None => view.ext_reads.push((key, value)),
+                    },
+                    Access::Write(key, value) => {
+                        if let Some(Some(prev)) = view.finals.insert(key.clone(), value) {
+                            view.intermediates.insert((key, prev));
+                        }
+                    }
+                }
+            }
+            view
+        })
+        .collect()
+}
+
+/// Index every written value of a key to the transactions writing it.
+fn writer_index(views: &[TxnView]) -> HashMap<(&str, &str), Vec<usize>> {
+    let mut writers: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+    for (tidx, view) in views.iter().enumerate() {
+        let finals = view
+            .finals
+            .iter()
+            .filter_map(|(k, v)| v.as_ref().map(|v| (k, v)));
+        for (key, value) in finals.chain(view.intermediates.iter().map(|(k, v)| (k, v))) {
+            writers
+                .entry((key.as_str(), value.as_str()))
+                .or_default()
+                .push(tidx);
+        }
+    }
+    writers
+}
+
+/// Returns the (reader, writer) pairs of records where a committed reader
+/// read a value written by a transaction other than itself.
+pub(super) fn read_froms(records: &[OpRecord]) -> Vec<(usize, usize)> {
+    let views = txn_views(records);
+    let writers = writer_index(&views);
+    let mut pairs = vec![];
+    for (ridx, view) in views.iter().enumerate() {
+        if view.outcome != Outcome::Committed {
+            continue;
+        }
+        for (key, value) in &view.ext_reads {
+            let Some(value) = value else {
+                continue;
+            };
+            if let Some([widx]) = writers.get(&(key.as_str(), value.as_str())).map(|w| &w[..]) {
+                if *widx != ridx {
+                    pairs.push((ridx, *widx));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// Dependency graph among the transactions of a history.
+struct DepGraph {
+    /// Outgoing edges of each transaction, by target index and kind.
+    edges: Vec<BTreeSet<(usize, DepKind)>>,
+}
+
+impl DepGraph {
+    fn add(&mut self, from: usize, to: usize, kind: DepKind) {
+        if from != to {
+            self.edges[from].insert((to, kind));
+        }
+    }
+
+    /// Find a cycle using only edges of the allowed kinds, returned as the
+    /// transactions along it.
+    fn find_cycle(&self, allowed: &[DepKind]) -> Option<Vec<usize>> {
+        // iterative depth-first search: 0 unvisited, 1 on stack, 2 done
+        let mut color = vec![0u8; self.edges.len()];
+        for root in 0..self.edges.len() {
+            if color[root] != 0 {
+                continue;
+            }
+            color[root] = 1;
+            let mut stack = vec![(root, self.edges[root].iter())];
+            while let Some((u, iter)) = stack.last_mut() {
+                let u = *u;
+                let next = iter.find(|(_, kind)| allowed.contains(kind)).copied();
+                match next {
+                    Some((v, _)) if color[v] == 0 => {
+                        color[v] = 1;
+                        stack.push((v, self.edges[v].iter()));
+                    }
+                    Some((v, _)) if color[v] == 1 => {
+                        // back edge closes a cycle through v; report the
+                        // shortest one through it
+                        return self.shortest_cycle(v, allowed);
+                    }
+                    Some(_) => {}
+                    None => {
+                        color[u] = 2;
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Find a shortest cycle through the start transaction using only edges
+    /// of the allowed kinds, by breadth-first search.
+    fn shortest_cycle(&self, start: usize, allowed: &[DepKind]) -> Option<Vec<usize>> {
+        let mut prev = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(u) = queue.pop_front() {
+            for &(v, kind) in &self.edges[u] {
+                if !allowed.contains(&kind) {
+                    continue;
+                }
+                if v == start {
+                    let mut cycle = vec![u];
+                    while *cycle.last().unwrap() != start {
+                        cycle.push(prev[cycle.last().unwrap()]);
+                    }
+                    cycle.reverse();
+                    return Some(cycle);
+                }
+                if let Entry::Vacant(e) = prev.entry(v) {
+                    e.insert(u);
+                    queue.push_back(v);
+                }
+            }
+        }
+        None
+    }
+
+    /// Weakest kind of allowed edge from one transaction to another.
+    fn edge_kind(&self, from: usize, to: usize, allowed: &[DepKind]) -> DepKind {
+        self.edges[from]
+            .range((to, DepKind::WriteWrite)..=(to, DepKind::ReadWrite))
+            .map(|&(_, kind)| kind)
+            .find(|kind| allowed.contains(kind))
+            .unwrap()
+    }
+}
+
+/// Check if the history is serializable, returning the first read anomaly
+/// or the dependency cycle found if not.
+pub fn check_serializable(records: &[OpRecord]) -> Option<SerialViolation> {
+    let views = txn_views(records);
+    let writers = writer_index(&views);
+    let violation = |anomaly, idxs: &[usize]| SerialViolation {
+        anomaly,
+        records: idxs.iter().map(|&i| records[i].clone()).collect(),
+        edges: vec![],
+    };
+
+    // trace every read of a value back to its writer
+    let mut read_froms = vec![];
+    let mut observed = vec![false; views.len()];
+    for (ridx, view) in views.iter().enumerate() {
+        if view.outcome != Outcome::Committed {
+            continue;
+        }
+        if view.internal {
+            return Some(violation(SerialAnomaly::Internal, &[ridx]));
+        }
+        for (key, value) in &view.ext_reads {
+            let Some(value) = value else {
+                continue;
+            };
+            let widx = match writers.get(&(key.as_str(), value.as_str())).map(|w| &w[..]) {
+                None => return Some(violation(SerialAnomaly::GarbageRead, &[ridx])),
+                Some([widx]) => *widx,
+                Some(_) => continue, // ambiguous writer
+            };
+            if widx == ridx {
+                // read a value it only writes later
+                return Some(violation(SerialAnomaly::Internal, &[ridx]));
+            }
+            if views[widx].outcome == Outcome::Aborted {
+                return Some(violation(SerialAnomaly::AbortedRead, &[ridx, widx]));
+            }
+            if views[widx]
+                .intermediates
+                .contains(&(key.clone(), value.clone()))
+            {
+                return Some(violation(SerialAnomaly::IntermediateRead, &[ridx, widx]));
+            }
+            observed[widx] = true;
+            read_froms.push((key.as_str(), ridx, widx));
+        }
+    }
+    let included = |tidx: usize| match views[tidx].outcome {
+        Outcome::Committed => true,
+        Outcome::Unknown => observed[tidx],
+        Outcome::Aborted => false,
+    };
+
+    // write-read edges, and write-write ones for read-modify-writes
+    let mut graph = DepGraph {
+        edges: vec![BTreeSet::new(); views.len()],
+    };
+    let mut readers: HashMap<(&str, usize), Vec<usize>> = HashMap::new();
+    let mut ww = vec![];
+    for &(key, ridx, widx) in &read_froms {
+        graph.add(widx, ridx, DepKind::WriteRead);
+        readers.entry((key, widx)).or_default().push(ridx);
+        if views[ridx].finals.contains_key(key) {
+            ww.push((key, widx, ridx));
+        }
+    }
+
+    // reads of missing keys never deleted observe the initial state, before
+    // every write of the key
+    let mut deleted = HashSet::new();
+    let mut key_writers: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (tidx, view) in views.iter().enumerate() {
+        if view.outcome == Outcome::Aborted {
+            continue;
+        }
+        for (key, value) in &view.finals {
+            if value.is_none() {
+                deleted.insert(key.as_str());
+            } else if included(tidx) {
+                key_writers.entry(key.as_str()).or_default().push(tidx);
+            }
+        }
+    }
+    for (ridx, view) in views.iter().enumerate() {
+        if view.outcome != Outcome::Committed {
+            continue;
+        }
+        for (key, value) in &view.ext_reads {
+            if value.is_some() || deleted.contains(key.as_str()) {
+                continue;
+            }
+            let writes_key = view.finals.contains_key(key);
+            for &widx in key_writers.get(key.as_str()).into_iter().flatten() {
+                graph.add(ridx, widx, DepKind::ReadWrite);
+                if writes_key && widx != ridx {
+                    ww.push((key.as_str(), ridx, widx));
+                }
+            }
+        }
+    }
+
+    // anti-dependencies on every overwrite of a value read
+    for (key, w1, w2) in ww {
+        graph.add(w1, w2, DepKind::WriteWrite);
+        for &ridx in readers.get(&(key, w1)).into_iter().flatten() {
+            graph.add(ridx, w2, DepKind::ReadWrite);
+        }
+    }
+
+    // look for cycles of the weakest anomaly class first
+    const CLASSES: [(SerialAnomaly, &[DepKind]); 3] = [
+        (SerialAnomaly::WriteCycle, &[DepKind::WriteWrite]),
+        (
+            SerialAnomaly::CircularInfoFlow,
+            &[DepKind::WriteWrite, DepKind::WriteRead],
+        ),
+        (
+            SerialAnomaly::AntiDepCycle,
+            &[DepKind::WriteWrite, DepKind::WriteRead, DepKind::ReadWrite],
+        ),
+    ];
+    for (anomaly, allowed) in CLASSES {
+        let Some(mut cycle) = graph.find_cycle(allowed) else {
+            continue;
+        };
+        // start from the transaction called first
+        let first = (0..cycle.len())
+            .min_by_key(|&i| (records[cycle[i]].ts_call, cycle[i]))
+            .unwrap();
+        cycle.rotate_left(first);
+        let edges = (0..cycle.len())
+            .map(|i| graph.edge_kind(cycle[i], cycle[(i + 1) % cycle.len()], allowed))
+            .collect();
+        return Some(SerialViolation {
+            anomaly,
+            records: cycle.iter().map(|&i| records[i].clone()).collect(),
+            edges,
+        });
+    }
+    None
+}
@@ -0,0 +1,282 @@
+//! Session guarantee checkers over per-client histories.
+//!
+//! Verifies the four session guarantees of Terry et al. independently for
+//! each client session. Writes on a key are identified by their unique
+//! values. A write is known to be older than another if it returned before
+//! the other was called, or if the other is a swap that returned its value.
+//! An observation is stale with respect to a write if the observed write is
+//! known to be older than it, or if the key is observed absent while every
+//! delete on the key is known to be older than that (put or swap) write.
+//!
+//! Works over complete histories rather than the streaming `History`, whose
+//! per-client update spans get trimmed before later reads of a session that
+//! depend on them are known.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::linear::{partition_by_key, KeyOp, KeyOpKind};
+use super::OpRecord;
+
+/// Session guarantee of Terry et al.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionGuarantee {
+    /// A read observes the session's own preceding writes.
+    ReadYourWrites,
+    /// A read never observes anything older than what the session's
+    /// preceding reads observed.
+    MonotonicReads,
+    /// Once a session observed a write, it never observes anything older
+    /// than the writes that preceded it in the writer's session.
+    MonotonicWrites,
+    /// Once a session observed a write, it never observes anything older
+    /// than what the writer's session observed before making it.
+    WritesFollowReads,
+}
+
+impl SessionGuarantee {
+    /// All guarantees, in the order used for indexing.
+    pub const ALL: [SessionGuarantee; 4] = [
+        SessionGuarantee::ReadYourWrites,
+        SessionGuarantee::MonotonicReads,
+        SessionGuarantee::MonotonicWrites,
+        SessionGuarantee::WritesFollowReads,
+    ];
+}
+
+impl fmt::Display for SessionGuarantee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionGuarantee::ReadYourWrites => write!(f, "read-your-writes"),
+            SessionGuarantee::MonotonicReads => write!(f, "monotonic reads"),
+            SessionGuarantee::MonotonicWrites => write!(f, "monotonic writes"),
+            SessionGuarantee::WritesFollowReads => write!(f, "writes-follow-reads"),
+        }
+    }
+}
+
+/// Violation of a session guarantee by a read.
+#[derive(Debug, Clone)]
+pub struct SessionViolation {
+    pub guarantee: SessionGuarantee,
+    /// Operation whose observation is stale; its client is the session.
+    pub read: OpRecord,
+    /// Write that the read should have observed or superseded.
+    pub required: OpRecord,
+}
+
+/// Result of checking all session guarantees.
+#[derive(Debug, Clone)]
+pub struct SessionReport {
+    /// Number of violating reads per client session, indexed in the order of
+    /// `SessionGuarantee::ALL`.
+    pub counts: Vec<[usize; 4]>,
+    /// First violation found of each guarantee.
+    pub examples: [Option<SessionViolation>; 4],
+}
+
+impl SessionReport {
+    /// Returns true if the guarantee holds for every session.
+    pub fn holds(&self, guarantee: SessionGuarantee) -> bool {
+        self.examples[guarantee as usize].is_none()
+    }
+}
+
+/// What a read observed on a key.
+#[derive(Debug, Clone, Copy)]
+enum Obs {
+    /// Value written by the write of this index.
+    Write(usize),
+    /// No value.
+    Absent,
+}
+
+/// Single-key sub-history with writes indexed by value.
+struct KeyView {
+    ops: Vec<KeyOp>,
+    writer_of: HashMap<String, usize>,
+    deletes: Vec<usize>,
+}
+
+impl KeyView {
+    fn new(ops: Vec<KeyOp>) -> Self {
+        let writer_of = ops
+            .iter()
+            .enumerate()
+            .filter_map(|(i, op)| op.kind.written().map(|v| (v.clone(), i)))
+            .collect();
+        let deletes = (0..ops.len())
+            .filter(|&i| {
+                matches!(
+                    ops[i].kind,
+                    KeyOpKind::Delete { .. } | KeyOpKind::Unknown { value: None }
+                )
+            })
+            .collect();
+        KeyView {
+            ops,
+            writer_of,
+            deletes,
+        }
+    }
+
+    /// What the operation observed, if it is a read of a known state.
+    fn observation(&self, i: usize) -> Option<Obs> {
+        match &self.ops[i].kind {
+            KeyOpKind::Get { value: observed }
+            | KeyOpKind::Swap {
+                old_value: observed,
+                ..
+            } => match observed {
+                Some(value) => self.writer_of.get(value).map(|&w| Obs::Write(w)),
+                None => Some(Obs::Absent),
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns true if write `x` is known to be older than write `w`.
+    fn older(&self, x: usize, w: usize) -> bool {
+        x != w
+            && (self.ops[x].ts_resp < self.ops[w].ts_call
+                || matches!(&self.ops[w].kind, KeyOpKind::Swap { old_value: Some(v), .. }
+                            if self.ops[x].kind.written() == Some(v)))
+    }
+
+    /// Returns true if the observation is stale with respect to write `w`.
+    fn stale(&self, obs: Obs, w: usize) -> bool {
+        match obs {
+            Obs::Write(x) => self.older(x, w),
+            Obs::Absent => {
+                !matches!(
+                    self.ops[w].kind,
+                    KeyOpKind::Delete { .. } | KeyOpKind::Unknown { value: None }
+                ) && self.deletes.iter().all(|&d| self.older(d, w))
+            }
+        }
+    }
+}
+
+/// Latest entry of a position-sorted list strictly before the position.
+fn latest_before(list: &[(usize, usize)], pos: usize) -> Option<usize> {
+    let n = list.partition_point(|&(p, _)| p < pos);
+    (n > 0).then(|| list[n - 1].1)
+}
+
+/// Check the session guarantees over a complete history.
+pub fn check_sessions(records: &[OpRecord]) -> SessionReport {
+    let num_clis = records.iter().map(|r| r.cidx + 1).max().unwrap_or(0);
+    let views: Vec<KeyView> = partition_by_key(records)
+        .into_values()
+        .map(KeyView::new)
+        .collect();
+
+    // session order of each client, and position of each record in it
+    let mut sessions: Vec<Vec<usize>> = vec![vec![]; num_clis];
+    for (ridx, record) in records.iter().enumerate() {
+        sessions[record.cidx].push(ridx);
+    }
+    let mut pos_of = vec![0; records.len()];
+    for session in sessions.iter_mut() {
+        session.sort_by_key(|&ridx| records[ridx].ts_call);
+        for (pos, &ridx) in session.iter().enumerate() {
+            pos_of[ridx] = pos;
+        }
+    }
+
+    // single-key operations of each record, and per writer session and key,
+    // the writes made and the writes observed in session order
+    let mut by_record: Vec<Vec<(usize, usize)>> = vec![vec![]; records.len()];
+    let mut writes: HashMap<(usize, usize), Vec<(usize, usize)>> = HashMap::new();
+    let mut reads: HashMap<(usize, usize), Vec<(usize, usize)>> = HashMap::new();
+    for (k, view) in views.iter().enumerate() {
+        for (i, op) in view.ops.iter().enumerate() {
+            by_record[op.ridx].push((k, i));
+            let pos = pos_of[op.ridx];
+            if let Some(Obs::Write(x)) = view.observation(i) {
+                reads.entry((op.cidx, k)).or_default().push((pos, x));
+            }
+            if op.kind.is_write() {
+                writes.entry((op.cidx, k)).or_default().push((pos, i));
+            }
+        }
+    }
+    for list in writes.values_mut().chain(reads.values_mut()) {
+        list.sort_unstable();
+    }
+
+    let mut report = SessionReport {
+        counts: vec![[0; 4]; num_clis],
+        examples: [None, None, None, None],
+    };
+    for (cidx, session) in sessions.iter().enumerate() {
+        let mut own_writes: HashMap<usize, usize> = HashMap::new();
+        let mut floors: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut seen_pos: Vec<Option<usize>> = vec![None; num_clis];
+
+        for &ridx in session {
+            for &(k, i) in &by_record[ridx] {
+                let view = &views[k];
+                let Some(obs) = view.observation(i) else {
+                    continue;
+                };
+
+                // writes this read is required not to be stale against
+                let mut required: Vec<(SessionGuarantee, usize)> = vec![];
+                if let Some(&w) = own_writes.get(&k) {
+                    required.push((SessionGuarantee::ReadYourWrites, w));
+                }
+                for &f in floors.get(&k).into_iter().flatten() {
+                    required.push((SessionGuarantee::MonotonicReads, f));
+                }
+                for (d, pos) in seen_pos.iter().enumerate() {
+                    let Some(pos) = *pos else {
+                        continue;
+                    };
+                    if let Some(w) = writes.get(&(d, k)).and_then(|l| latest_before(l, pos)) {
+                        required.push((SessionGuarantee::MonotonicWrites, w));
+                    }
+                    if let Some(w) = reads.get(&(d, k)).and_then(|l| latest_before(l, pos)) {
+                        required.push((SessionGuarantee::WritesFollowReads, w));
+                    }
+                }
+
+                let mut violated = [false; 4];
+                for (guarantee, w) in required {
+                    if !violated[guarantee as usize] && view.stale(obs, w) {
+                        violated[guarantee as usize] = true;
+                        report.counts[cidx][guarantee as usize] += 1;
+                        report.examples[guarantee as usize].get_or_insert_with(|| {
+                            SessionViolation {
+                                guarantee,
+                                read: records[ridx].clone(),
+                                required: records[view.ops[w].ridx].clone(),
+                            }
+                        });
+                    }
+                }
+            }
+
+            // the whole operation takes effect at once, so update session
+            // state only after checking all of its keys
+            for &(k, i) in &by_record[ridx] {
+                let view = &views[k];
+                if let Some(Obs::Write(x)) = view.observation(i) {
+                    let key_floors = floors.entry(k).or_default();
+                    key_floors.retain(|&f| view.ops[f].ts_resp >= view.ops[x].ts_call);
+                    key_floors.push(x);
+                    let writer = &view.ops[x];
+                    let pos = seen_pos[records[writer.ridx].cidx].get_or_insert(0);
+                    *pos = (*pos).max(pos_of[writer.ridx]);
+                }
+                // an update of unknown outcome may never take effect
+                if view.ops[i].kind.is_write()
+                    && !matches!(view.ops[i].kind, KeyOpKind::Unknown { .. })
+                {
+                    own_writes.insert(k, i);
+                }
+            }
+        }
+    }
+    report
+}
@@ -0,0 +1,226 @@
+//! Shrinking of failing histories by delta debugging.
+//!
+//! Operations (and whole clients) are removed from a failing history as long
+//! as the chosen checker still reports a violation. Removals are closed under
+//! the justifier relation: whenever an operation is removed, so is every
+//! operation that observed its effect, so that shrinking never manufactures
+//! artificial violations such as reads of values nobody wrote.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use super::linear::{justifiers, partition_by_key};
+use super::serial::read_froms;
+use super::{
+    check_linearizable, check_scans_atomic, check_serializable, classify_consistency,
+    ApproxOutcome, CheckOptions, CheckerKind, ConsistencyLevel, History, OpRecord,
+};
+use crate::KvCall;
+
+/// Justifier relation among the records of a history.
+struct Deps {
+    /// For each record, the records that justify what it observed.
+    justs: Vec<Vec<usize>>,
+    /// For each record, the records that it justifies.
+    dependents: Vec<Vec<usize>>,
+}
+
+impl Deps {
+    fn new(records: &[OpRecord]) -> Self {
+        let mut justs = vec![vec![]; records.len()];
+        let mut dependents = vec![vec![]; records.len()];
+        for ops in partition_by_key(records).values() {
+            for (op, just) in ops.iter().zip(justifiers(ops)) {
+                if let Some(j) = just {
+                    let j = ops[j].ridx;
+                    if j != op.ridx {
+                        justs[op.ridx].push(j);
+                        dependents[j].push(op.ridx);
+                    }
+                }
+            }
+        }
+        // values read inside transactions, which are not partitioned by key
+        for (reader, writer) in read_froms(records) {
+            if matches!(records[reader].call, KvCall::Txn { .. })
+                || matches!(records[writer].call, KvCall::Txn { .. })
+            {
+                justs[reader].push(writer);
+                dependents[writer].push(reader);
+            }
+        }
+        Deps { justs, dependents }
+    }
+
+    /// Remove the records matching `pred` from `idxs`, together with every
+    /// record transitively depending on them.
+    fn drop_closed(&self, idxs: &[usize], pred: impl Fn(usize) -> bool) -> Vec<usize> {
+        let mut removed = vec![false; self.justs.len()];
+        let mut queue: VecDeque<usize> = idxs.iter().copied().filter(|&i| pred(i)).collect();
+        while let Some(i) = queue.pop_front() {
+            if !removed[i] {
+                removed[i] = true;
+                queue.extend(&self.dependents[i]);
+            }
+        }
+        idxs.iter().copied().filter(|&i| !removed[i]).collect()
+    }
+
+    /// Keep only the records of `idxs` matching `pred`, together with every
+    /// record of `idxs` they transitively depend on.
+    fn keep_closed(&self, idxs: &[usize], pred: impl Fn(usize) -> bool) -> Vec<usize> {
+        let mut kept = vec![false; self.justs.len()];
+        let mut queue: VecDeque<usize> = idxs.iter().copied().filter(|&i| pred(i)).collect();
+        while let Some(i) = queue.pop_front() {
+            if !kept[i] {
+                kept[i] = true;
+                queue.extend(&self.justs[i]);
+            }
+        }
+        idxs.iter().copied().filter(|&i| kept[i]).collect()
+    }
+}
+
+/// What a checker blames for a violation. Shrinking only accepts removals
+/// after which the checker still blames the same thing, since a checker may
+/// flag a different, artificial anomaly once some operations are gone.
+#[derive(Debug, PartialEq, Eq)]
+enum Culprit {
+    /// Operation identified by its call and response timestamps.
+    Op(u64, u64),
+    /// Key whose sub-history is at fault.
+    Key(Option<String>),
+}
+
+/// Returns what the chosen checker blames if it reports a violation on the
+/// history, or the first response that contradicts its own call. An unfair
+/// approximate check does not count as a violation.
+fn blame(
+    kind: CheckerKind,
+    opts: &CheckOptions,
+    keys: &[Vec<String>],
+    records: &[OpRecord],
+) -> Option<Culprit> {
+    if let Some(record) = records.iter().find(|r| !r.is_self_consistent()) {
+        return Some(Culprit::Op(record.ts_call, record.ts_resp));
+    }
+    match kind {
+        CheckerKind::Approx => {
+            // a client that makes no updates would block every check from
+            // becoming decidable, so renumber updating clients first and
+            // replay among them only, keeping every client's keys pool
+            let updaters: BTreeSet<usize> = records
+                .iter()
+                .filter(|r| r.update_info().is_some())
+                .map(|r| r.cidx)
+                .collect();
+            let renumber: Vec<usize> = updaters
+                .iter()
+                .copied()
+                .chain((0..keys.len()).filter(|c| !updaters.contains(c)))
+                .collect();
+            let records: Vec<OpRecord> = records
+                .iter()
+                .map(|r| OpRecord {
+                    cidx: renumber.iter().position(|&c| c == r.cidx).unwrap(),
+                    ..r.clone()
+                })
+                .collect();
+            match History::replay(updaters.len(), keys, &records, opts.scan_atomic) {
+                ApproxOutcome::Passed(_) => None,
+                ApproxOutcome::Violation { failed, .. } => {
+                    Some(Culprit::Op(failed.ts_call, failed.ts_resp))
+                }
+                ApproxOutcome::UnexpectedKey(record) => {
+                    Some(Culprit::Op(record.ts_call, record.ts_resp))
+                }
+            }
+        }
+        CheckerKind::Linearizable => {
+            if let Some(violation) = check_linearizable(records) {
+                Some(Culprit::Key(Some(violation.key)))
+            } else if opts.scan_atomic {
+                check_scans_atomic(records).map(|scan| Culprit::Op(scan.ts_call, scan.ts_resp))
+            } else {
+                None
+            }
+        }
+        CheckerKind::Classify => {
+            let report = classify_consistency(records);
+            (report.level == ConsistencyLevel::Invalid).then_some(Culprit::Key(report.weakest_key))
+        }
+        CheckerKind::Serializable => check_serializable(records).map(|violation| {
+            let first = &violation.records[0];
+            Culprit::Op(first.ts_call, first.ts_resp)
+        }),
+    }
+}
+
+/// Shrink a history on which the chosen checker reports a violation down to
+/// a minimal failing sub-history, ordered by call timestamp. Returns `None`
+/// if the history does not fail in the first place.
+pub fn shrink_history(
+    kind: CheckerKind,
+    opts: &CheckOptions,
+    keys: &[Vec<String>],
+    records: &[OpRecord],
+) -> Option<Vec<OpRecord>> {
+    let mut idxs: Vec<usize> = (0..records.len()).collect();
+    idxs.sort_by_key(|&i| (records[i].ts_call, records[i].ts_resp));
+    let pick =
+        |idxs: &[usize]| -> Vec<OpRecord> { idxs.iter().map(|&i| records[i].clone()).collect() };
+    let culprit = blame(kind, opts, keys, records)?;
+    let fails = |idxs: &[usize]| {
+        !idxs.is_empty() && blame(kind, opts, keys, &pick(idxs)).as_ref() == Some(&culprit)
+    };
+    let deps = Deps::new(records);
+
+    // focus on the operations touching a single key, if that still fails
+    for ops in partition_by_key(records).values() {
+        let touching: BTreeSet<usize> = ops.iter().map(|op| op.ridx).collect();
+        let trial = deps.keep_closed(&idxs, |i| touching.contains(&i));
+        if trial.len() < idxs.len() && fails(&trial) {
+            idxs = trial;
+            break;
+        }
+    }
+
+    // remove whole clients
+    for cidx in 0..keys.len() {
+        let trial = deps.drop_closed(&idxs, |i| records[i].cidx == cidx);
+        if trial.len() < idxs.len() && fails(&trial) {
+            idxs = trial;
+        }
+    }
+
+    // binary search for a prefix boundary where the check starts failing
+    let (mut lo, mut hi) = (0, idxs.len()); // prefix lo passes, prefix hi fails
+    while hi - lo > 1 {
+        let mid = (lo + hi) / 2;
+        let prefix: BTreeSet<usize> = idxs[..mid].iter().copied().collect();
+        if !fails(&deps.keep_closed(&idxs, |i| prefix.contains(&i))) {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    let prefix: BTreeSet<usize> = idxs[..hi].iter().copied().collect();
+    idxs = deps.keep_closed(&idxs, |i| prefix.contains(&i));
+
+    // delta debugging with decreasing chunk sizes
+    let mut chunk = idxs.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < idxs.len() {
+            let end = (start + chunk).min(idxs.len());
+            let chunk_set: BTreeSet<usize> = idxs[start..end].iter().copied().collect();
+            let trial = deps.drop_closed(&idxs, |i| chunk_set.contains(&i));
+            if fails(&trial) {
+                idxs = trial;
+            } else {
+                start = end;
+            }
+        }
+        chunk /= 2;
+    }
+    Some(pick(&idxs))
+}
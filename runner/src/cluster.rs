@@ -0,0 +1,237 @@
+//! Self-managed local cluster of service nodes.
+//!
+//! A cluster spec names the project whose `service` recipe launches each node
+//! and the topology, in the format
+//!
+//! ```text
+//! <p2|p3>[:<param>=<value>,...]
+//! ```
+//!
+//! with params `managers` (number of manager replicas), `partitions`, `rf`
+//! (server replication factor), and `port` (base port to allocate free ports
+//! upwards from; picked by the OS if 0). Project 2 only supports a single
+//! manager and no replication. Node IDs follow the `service` recipe
+//! conventions, e.g., "m", "s0", "s1" for project 2 and "m.0", "s0.0", "s0.1"
+//! for project 3.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::proc::NODE_PLACEHOLDER;
+use crate::{RunnerError, ServiceNodes};
+
+/// Interval between probes of nodes' listen addresses during startup.
+const READY_POLL: Duration = Duration::from_millis(200);
+
+/// Topology of a local cluster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterSpec {
+    pub project: u8,
+    pub managers: usize,
+    pub partitions: usize,
+    pub rf: usize,
+    pub base_port: u16,
+}
+
+impl FromStr for ClusterSpec {
+    type Err = RunnerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| RunnerError::Parse(format!("invalid cluster '{}': {}", s, why));
+        let (project, params) = s.split_once(':').unwrap_or((s, ""));
+        let project = match project {
+            "p2" => 2,
+            "p3" => 3,
+            _ => return Err(invalid("project must be 'p2' or 'p3'")),
+        };
+
+        let mut spec = ClusterSpec {
+            project,
+            managers: 1,
+            partitions: 1,
+            rf: 1,
+            base_port: 0,
+        };
+        for param in params.split(',').filter(|p| !p.is_empty()) {
+            match param.split_once('=') {
+                Some(("managers", num)) => spec.managers = num.parse()?,
+                Some(("partitions", num)) => spec.partitions = num.parse()?,
+                Some(("rf", num)) => spec.rf = num.parse()?,
+                Some(("port", num)) => spec.base_port = num.parse()?,
+                _ => return Err(invalid(&format!("unknown param '{}'", param))),
+            }
+        }
+        if spec.managers == 0 || spec.partitions == 0 || spec.rf == 0 {
+            return Err(invalid(
+                "'managers', 'partitions', and 'rf' must be positive",
+            ));
+        }
+        if project == 2 && (spec.managers != 1 || spec.rf != 1) {
+            return Err(invalid("p2 supports a single manager and no replication"));
+        }
+        Ok(spec)
+    }
+}
+
+impl fmt::Display for ClusterSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "p{}:managers={},partitions={},rf={},port={}",
+            self.project, self.managers, self.partitions, self.rf, self.base_port
+        )
+    }
+}
+
+/// Allocate a number of distinct free local ports, scanning upwards from the
+/// base port, or letting the OS pick them if the base port is 0.
+fn alloc_ports(base_port: u16, num: usize) -> Result<Vec<u16>, RunnerError> {
+    // hold all listeners until done so that no port gets picked twice
+    let mut listeners = vec![];
+    let mut port = base_port;
+    while listeners.len() < num {
+        if let Ok(listener) = TcpListener::bind(("127.0.0.1", port)) {
+            listeners.push(listener);
+        }
+        if base_port != 0 {
+            port = port.checked_add(1).ok_or_else(|| {
+                RunnerError::Io(format!("ran out of free ports above {}", base_port))
+            })?;
+        }
+    }
+    listeners
+        .iter()
+        .map(|listener| Ok(listener.local_addr()?.port()))
+        .collect()
+}
+
+/// Join local addresses of given ports into a comma-separated list.
+fn addr_list(ports: &[u16]) -> String {
+    ports
+        .iter()
+        .map(|port| format!("127.0.0.1:{}", port))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Local cluster with allocated ports and a temporary directory holding
+/// per-node backer directories, which gets removed on drop.
+#[derive(Debug)]
+pub struct Cluster {
+    pub spec: ClusterSpec,
+    /// IDs of all nodes, managers first.
+    pub node_ids: Vec<String>,
+    /// Service `just` args to launch a node, with "{node}" to be replaced
+    /// by the node ID.
+    pub service_just_args: Vec<String>,
+    /// Client `just` args to connect to the cluster.
+    pub client_just_args: Vec<String>,
+    /// Public listen address of each node, in the order of `node_ids`.
+    pub node_addrs: Vec<SocketAddr>,
+    pub tmpdir: PathBuf,
+}
+
+impl Cluster {
+    /// Allocate ports and create backer directories for a cluster following
+    /// the spec, without launching anything yet.
+    pub fn allocate(spec: &ClusterSpec) -> Result<Self, RunnerError> {
+        let num_servers = spec.partitions * spec.rf;
+        let (manager_ids, server_ids): (Vec<String>, Vec<String>) = if spec.project == 2 {
+            (
+                vec!["m".into()],
+                (0..num_servers).map(|p| format!("s{}", p)).collect(),
+            )
+        } else {
+            (
+                (0..spec.managers).map(|r| format!("m.{}", r)).collect(),
+                (0..num_servers)
+                    .map(|i| format!("s{}.{}", i / spec.rf, i % spec.rf))
+                    .collect(),
+            )
+        };
+
+        let ports = alloc_ports(spec.base_port, 2 * (spec.managers + num_servers))?;
+        let (manager_ports, ports) = ports.split_at(spec.managers);
+        let (server_ports, ports) = ports.split_at(num_servers);
+        let (manager_p2ps, server_p2ps) = ports.split_at(spec.managers);
+
+        let tmpdir = env::temp_dir().join(format!("madkv-cluster-{}", process::id()));
+        let backer_prefix = tmpdir.join("backer").to_string_lossy().into_owned();
+        let node_ids: Vec<String> = manager_ids.into_iter().chain(server_ids).collect();
+        for node in &node_ids {
+            fs::create_dir_all(format!("{}.{}", backer_prefix, node))?;
+        }
+
+        let managers = addr_list(manager_ports);
+        let servers = addr_list(server_ports);
+        let module = format!("p{}", spec.project);
+        let mut service_just_args = vec![
+            format!("{}::service", module),
+            NODE_PLACEHOLDER.into(),
+            managers.clone(),
+        ];
+        if spec.project == 2 {
+            service_just_args.extend([servers, backer_prefix]);
+        } else {
+            service_just_args.extend([
+                addr_list(manager_p2ps),
+                spec.rf.to_string(),
+                servers,
+                addr_list(server_p2ps),
+                backer_prefix,
+            ]);
+        }
+        let client_just_args = vec![format!("{}::client", module), managers];
+
+        let node_addrs = manager_ports
+            .iter()
+            .chain(server_ports)
+            .map(|&port| SocketAddr::from(([127, 0, 0, 1], port)))
+            .collect();
+
+        Ok(Cluster {
+            spec: spec.clone(),
+            node_ids,
+            service_just_args,
+            client_just_args,
+            node_addrs,
+            tmpdir,
+        })
+    }
+
+    /// Launch all nodes of the cluster.
+    pub fn launch(&self) -> Result<ServiceNodes, RunnerError> {
+        ServiceNodes::launch(&self.node_ids, &self.service_just_args)
+    }
+
+    /// Wait until every node accepts connections on its public listen
+    /// address, or until timeout.
+    pub fn wait_ready(&self, timeout: Duration) -> Result<(), RunnerError> {
+        let deadline = Instant::now() + timeout;
+        for (node, addr) in self.node_ids.iter().zip(&self.node_addrs) {
+            while TcpStream::connect_timeout(addr, READY_POLL).is_err() {
+                if Instant::now() >= deadline {
+                    return Err(RunnerError::Io(format!(
+                        "node {} not ready on {} after {:?}",
+                        node, addr, timeout
+                    )));
+                }
+                thread::sleep(READY_POLL);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.tmpdir);
+    }
+}
@@ -0,0 +1,162 @@
+//! TOML cluster description file for the service launcher.
+//!
+//! Lists every node of the cluster once, so that the layout can be checked
+//! into a repo instead of being retyped as address lists on every node:
+//!
+//! ```toml
+//! server_rf = 3                # optional, defaults to 1
+//! backer_prefix = "./backer"   # optional, for nodes without `backer`
+//! manager_just_args = ["p3::manager", "{rep}", "{api_port}", ...]  # optional
+//! server_just_args = ["p3::server", "{part}", "{rep}", ...]        # optional
+//!
+//! [[nodes]]
+//! id = "m.0"                   # role is given by the node ID
+//! addr = "127.0.0.1:3666"      # public listen address
+//! p2p = "127.0.0.1:3606"       # internal replication address, optional
+//! backer = "./backer.m.0"      # durable storage path, optional
+//! ```
+//!
+//! Nodes of a role must either all or none have `p2p` addresses, and the
+//! servers must cover every replica of partitions 0, 1, ... exactly once.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::topology::parse_addr_list;
+use crate::{NodeId, NodeRole, RunnerError, Topology};
+
+/// One node entry of a cluster description file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    pub id: String,
+    pub addr: String,
+    #[serde(default)]
+    pub p2p: Option<String>,
+    #[serde(default)]
+    pub backer: Option<String>,
+}
+
+/// Contents of a cluster description file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    #[serde(default = "default_server_rf")]
+    pub server_rf: usize,
+    #[serde(default)]
+    pub backer_prefix: Option<String>,
+    #[serde(default)]
+    pub manager_just_args: Vec<String>,
+    #[serde(default)]
+    pub server_just_args: Vec<String>,
+    pub nodes: Vec<NodeConfig>,
+}
+
+fn default_server_rf() -> usize {
+    1
+}
+
+impl ClusterConfig {
+    /// Load a cluster description from a TOML file.
+    pub fn load(path: &str) -> Result<Self, RunnerError> {
+        let text = fs::read_to_string(path)?;
+        let config: ClusterConfig = toml::from_str(&text)?;
+        Ok(config)
+    }
+
+    /// Entry of the node, failing if it is not listed.
+    pub fn node(&self, node: &NodeId) -> Result<&NodeConfig, RunnerError> {
+        self.nodes
+            .iter()
+            .find(|n| n.id.parse::<NodeId>().is_ok_and(|id| id == *node))
+            .ok_or_else(|| RunnerError::Parse(format!("node {} not listed in config", node)))
+    }
+
+    /// Durable storage path of the node, if configured either directly or
+    /// through the backer prefix.
+    pub fn backer_of(&self, node: &NodeId) -> Result<Option<String>, RunnerError> {
+        let entry = self.node(node)?;
+        Ok(entry.backer.clone().or_else(|| {
+            self.backer_prefix
+                .as_ref()
+                .map(|prefix| format!("{}.{}", prefix, node))
+        }))
+    }
+
+    /// Build the address lists of the cluster, validating that the node
+    /// entries form a complete topology.
+    pub fn topology(&self) -> Result<Topology, RunnerError> {
+        // index every node by (partition, replica) within its role
+        let mut managers: BTreeMap<(usize, usize), &NodeConfig> = BTreeMap::new();
+        let mut servers: BTreeMap<(usize, usize), &NodeConfig> = BTreeMap::new();
+        for entry in &self.nodes {
+            let id: NodeId = entry.id.parse()?;
+            parse_addr_list(&format!("{} addr", id), &entry.addr)?;
+            if let Some(p2p) = &entry.p2p {
+                parse_addr_list(&format!("{} p2p", id), p2p)?;
+            }
+            if id.role == NodeRole::Server && id.rep_id() >= self.server_rf {
+                return Err(RunnerError::Parse(format!(
+                    "node {} has a replica ID beyond 'server_rf' {}",
+                    id, self.server_rf
+                )));
+            }
+            let role_nodes = match id.role {
+                NodeRole::Manager => &mut managers,
+                NodeRole::Server => &mut servers,
+            };
+            if role_nodes
+                .insert((id.partition, id.rep_id()), entry)
+                .is_some()
+            {
+                return Err(RunnerError::Parse(format!(
+                    "node {} listed more than once in config",
+                    id
+                )));
+            }
+        }
+
+        // every position of the lists must be taken exactly once
+        let rf = self.server_rf.max(1);
+        let expected = |i: usize, role: NodeRole| match role {
+            NodeRole::Manager => (0, i),
+            NodeRole::Server => (i / rf, i % rf),
+        };
+        let mut lists = vec![];
+        for (role, role_nodes, name) in [
+            (NodeRole::Manager, &managers, "manager"),
+            (NodeRole::Server, &servers, "server"),
+        ] {
+            for (i, (&pos, entry)) in role_nodes.iter().enumerate() {
+                if pos != expected(i, role) {
+                    return Err(RunnerError::Parse(format!(
+                        "{} {} is listed without all the {}s indexed before it",
+                        name, entry.id, name
+                    )));
+                }
+            }
+            let addrs: Vec<String> = role_nodes.values().map(|e| e.addr.clone()).collect();
+            let p2ps: Vec<String> = role_nodes.values().filter_map(|e| e.p2p.clone()).collect();
+            if !p2ps.is_empty() && p2ps.len() != addrs.len() {
+                return Err(RunnerError::Parse(format!(
+                    "either all or no {}s must have 'p2p' in config",
+                    name
+                )));
+            }
+            lists.push((addrs, p2ps));
+        }
+
+        let (servers, server_p2ps) = lists.pop().unwrap();
+        let (managers, manager_p2ps) = lists.pop().unwrap();
+        Topology {
+            managers,
+            manager_p2ps,
+            servers,
+            server_p2ps,
+            server_rf: self.server_rf,
+        }
+        .validate()
+    }
+}
@@ -0,0 +1,104 @@
+//! Control commands to a supervising service launcher.
+//!
+//! A service launcher started with a control socket accepts line-oriented
+//! text commands on that Unix-domain socket, each answered by zero or more
+//! info lines followed by a final `OK` or `ERR <msg>` line, just like netem
+//! control:
+//!
+//! ```text
+//! status    # state, PID, uptime, and restart count of the process
+//! kill      # SIGKILL the process, holding it down until restarted
+//! term      # SIGTERM the process, holding it down until restarted
+//! pause     # SIGSTOP the process
+//! resume    # SIGCONT the process
+//! restart   # SIGKILL the process if running, then start it right away
+//! ```
+//!
+//! Signals reach the whole process tree under the launcher's `just` child,
+//! i.e., the actual server or manager as well.
+
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::RunnerError;
+
+/// Control command to a service launcher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceCmd {
+    Status,
+    Kill,
+    Term,
+    Pause,
+    Resume,
+    Restart,
+}
+
+impl FromStr for ServiceCmd {
+    type Err = RunnerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "status" => Ok(ServiceCmd::Status),
+            "kill" => Ok(ServiceCmd::Kill),
+            "term" => Ok(ServiceCmd::Term),
+            "pause" => Ok(ServiceCmd::Pause),
+            "resume" => Ok(ServiceCmd::Resume),
+            "restart" => Ok(ServiceCmd::Restart),
+            _ => Err(RunnerError::Parse(format!(
+                "invalid service command: {}",
+                s.trim()
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ServiceCmd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServiceCmd::Status => write!(f, "status"),
+            ServiceCmd::Kill => write!(f, "kill"),
+            ServiceCmd::Term => write!(f, "term"),
+            ServiceCmd::Pause => write!(f, "pause"),
+            ServiceCmd::Resume => write!(f, "resume"),
+            ServiceCmd::Restart => write!(f, "restart"),
+        }
+    }
+}
+
+/// Client connection to a service launcher's control socket.
+#[derive(Debug)]
+pub struct ServiceCtl {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl ServiceCtl {
+    /// Connect to the control socket at given path.
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, RunnerError> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(ServiceCtl { reader, writer })
+    }
+
+    /// Send a command, returning the info lines replied.
+    pub fn send(&mut self, cmd: ServiceCmd) -> Result<Vec<String>, RunnerError> {
+        writeln!(self.writer, "{}", cmd)?;
+        let mut infos = vec![];
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(RunnerError::Io("service control connection closed".into()));
+            }
+            let line = line.trim_end();
+            if line == "OK" {
+                return Ok(infos);
+            } else if let Some(msg) = line.strip_prefix("ERR ") {
+                return Err(RunnerError::Io(format!("service error: {}", msg)));
+            }
+            infos.push(line.into());
+        }
+    }
+}
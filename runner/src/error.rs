@@ -55,6 +55,7 @@ impl_from!(io::Error, Io);
 impl_from!(str::ParseBoolError, Parse);
 impl_from!(num::ParseIntError, Parse);
 impl_from!(num::ParseFloatError, Parse);
+impl_from!(toml::de::Error, Parse);
 impl_from!(mpsc::RecvError, Chan);
 impl_from!(mpsc::RecvTimeoutError, Chan);
 
@@ -1,46 +1,249 @@
 //! Standard input/output workload interface.
+//!
+//! Keys and values are whitespace-delimited segments of the lines. By
+//! default they are written as is, so they must be non-empty, free of
+//! whitespace, and not the literal `null` (which stands for "not found").
+//! The escaped encoding lifts these restrictions: it gets negotiated by the
+//! runner sending an `ENCODING escaped` line before any call, to which the
+//! client must answer with the same line, after which every key and value
+//! in both directions is percent-escaped UTF-8 text:
+//!
+//!   - bytes other than printable non-space ASCII, and `%` itself, are
+//!     written as `%XX` in hex, e.g., a space as `%20`
+//!   - the empty string is written as a lone `%`
+//!   - the string `null` is written as `%6Eull`, so a bare `null` still
+//!     means "not found"
+//!
+//! Keys and values are strings, so an escaped segment must decode to valid
+//! UTF-8; arbitrary binary content is not supported.
+//!
+//! Any call line may also be prefixed by a request ID tag `#<id>`, with `<id>`
+//! an unsigned integer, e.g., `#42 GET mykey`. The client must then prefix the
+//! response with the same tag, e.g., `#42 GET mykey myvalue`, but is free to
+//! answer tagged calls in any order, so that it can have many of them in
+//! flight at once. For a tagged `SCAN`, both the `BEGIN` and the `SCAN END`
+//! lines carry the tag, and the lines of one scan are written together.
+//! Untagged calls keep being answered one at a time in order.
+//!
+//! Instead of the regular response, a client may answer a call with
+//! `ERROR <op> <key> <reason>` if the operation definitely failed without
+//! taking effect, or with `UNKNOWN <op> <key>` if it cannot tell whether the
+//! operation took effect (e.g., timed out during a leader failover), where
+//! `<op>` is the call's keyword, `<key>` its (first) key, and `<reason>` the
+//! rest of the line as free text that is never escaped.
+//!
+//! Several `GET`, `PUT`, and `DELETE` calls, possibly on different keys, can
+//! be grouped into a transaction by writing them between a `TXN BEGIN` and a
+//! `TXN COMMIT` line. The client must either apply all of them atomically
+//! and answer with their responses between the same two lines, in order, or
+//! apply none of them and answer with a single `TXN ABORT` line. For a tagged
+//! transaction, the `TXN BEGIN`, `TXN COMMIT`, and `TXN ABORT` lines carry the
+//! tag, while the lines of the operations inside do not. An `ERROR TXN <key>`
+//! or `UNKNOWN TXN <key>` answer names the first key of the transaction.
 
+use std::borrow::Cow;
 use std::io;
 use std::str::SplitWhitespace;
 
+use clap::ValueEnum;
+
 use strum::EnumCount;
 
 use crate::RunnerError;
 
+/// Encoding of keys and values in the lines of the stdin/out interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Encoding {
+    /// Keys and values written as is.
+    #[default]
+    Ascii,
+    /// Keys and values percent-escaped, for arbitrary UTF-8 text.
+    Escaped,
+}
+
+impl Encoding {
+    /// Line sent by the runner, and echoed back by the client, to switch to
+    /// this encoding; `None` for the default encoding.
+    pub(crate) fn handshake_line(self) -> Option<&'static str> {
+        match self {
+            Encoding::Ascii => None,
+            Encoding::Escaped => Some("ENCODING escaped"),
+        }
+    }
+
+    /// Encode a key or value into a line segment.
+    pub fn encode(self, s: &str) -> Cow<'_, str> {
+        if self == Encoding::Ascii {
+            return Cow::Borrowed(s);
+        }
+        if s.is_empty() {
+            return Cow::Borrowed("%");
+        }
+        if s == "null" {
+            return Cow::Borrowed("%6Eull");
+        }
+        if s.bytes().all(|b| b.is_ascii_graphic() && b != b'%') {
+            return Cow::Borrowed(s);
+        }
+        let mut seg = String::with_capacity(s.len() * 3);
+        for b in s.bytes() {
+            if b.is_ascii_graphic() && b != b'%' {
+                seg.push(b as char);
+            } else {
+                seg.push_str(&format!("%{:02X}", b));
+            }
+        }
+        Cow::Owned(seg)
+    }
+
+    /// Decode a line segment back into a key or value.
+    pub fn decode(self, seg: &str) -> Result<String, RunnerError> {
+        if self == Encoding::Ascii || !seg.contains('%') {
+            return Ok(seg.into());
+        }
+        if seg == "%" {
+            return Ok(String::new());
+        }
+        let invalid = || RunnerError::Parse(format!("invalid escaped segment: {}", seg));
+        let mut bytes = Vec::with_capacity(seg.len());
+        let mut iter = seg.bytes();
+        while let Some(b) = iter.next() {
+            if b == b'%' {
+                let hex = [
+                    iter.next().ok_or_else(invalid)?,
+                    iter.next().ok_or_else(invalid)?,
+                ];
+                let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            } else {
+                bytes.push(b);
+            }
+        }
+        String::from_utf8(bytes)
+            .map_err(|_| RunnerError::Parse(format!("escaped segment is not valid UTF-8: {}", seg)))
+    }
+
+    /// Encode an optional value, where `None` is written as `null`.
+    pub fn encode_opt(self, value: Option<&str>) -> Cow<'_, str> {
+        match value {
+            Some(value) => self.encode(value),
+            None => Cow::Borrowed("null"),
+        }
+    }
+
+    /// Decode an optional value, where `null` means `None`.
+    pub fn decode_opt(self, seg: &str) -> Result<Option<String>, RunnerError> {
+        if seg == "null" {
+            Ok(None)
+        } else {
+            self.decode(seg).map(Some)
+        }
+    }
+}
+
 /// KV operation call type.
 #[derive(Debug, Clone, EnumCount)]
 pub enum KvCall {
-    Put { key: String, value: String },
-    Swap { key: String, value: String },
-    Get { key: String },
-    Scan { key_start: String, key_end: String },
-    Delete { key: String },
+    Put {
+        key: String,
+        value: String,
+    },
+    Swap {
+        key: String,
+        value: String,
+    },
+    Get {
+        key: String,
+    },
+    Scan {
+        key_start: String,
+        key_end: String,
+    },
+    Delete {
+        key: String,
+    },
+    Cas {
+        key: String,
+        expected: Option<String>,
+        value: String,
+    },
+    Txn {
+        ops: Vec<KvCall>,
+    },
     Stop,
 }
 
 impl KvCall {
-    /// Write a KV operation call as a string line to a writer.
-    pub(crate) fn into_write(self, writer: &mut impl io::Write) -> Result<(), RunnerError> {
+    /// Write a KV operation call as a string line to a writer, with keys and
+    /// values in the given encoding, prefixed by the request ID tag if given.
+    pub(crate) fn into_write(
+        self,
+        writer: &mut impl io::Write,
+        enc: Encoding,
+        tag: Option<u64>,
+    ) -> Result<(), RunnerError> {
+        if let Some(tag) = tag {
+            write!(writer, "#{} ", tag)?;
+        }
+        let e = |s: &str| enc.encode(s).into_owned();
         match self {
-            KvCall::Put { key, value } => Ok(writeln!(writer, "PUT {} {}", key, value)?),
-            KvCall::Swap { key, value } => Ok(writeln!(writer, "SWAP {} {}", key, value)?),
-            KvCall::Get { key } => Ok(writeln!(writer, "GET {}", key)?),
+            KvCall::Put { key, value } => Ok(writeln!(writer, "PUT {} {}", e(&key), e(&value))?),
+            KvCall::Swap { key, value } => Ok(writeln!(writer, "SWAP {} {}", e(&key), e(&value))?),
+            KvCall::Get { key } => Ok(writeln!(writer, "GET {}", e(&key))?),
             KvCall::Scan { key_start, key_end } => {
-                Ok(writeln!(writer, "SCAN {} {}", key_start, key_end)?)
+                Ok(writeln!(writer, "SCAN {} {}", e(&key_start), e(&key_end))?)
+            }
+            KvCall::Delete { key } => Ok(writeln!(writer, "DELETE {}", e(&key))?),
+            KvCall::Cas {
+                key,
+                expected,
+                value,
+            } => Ok(writeln!(
+                writer,
+                "CAS {} {} {}",
+                e(&key),
+                enc.encode_opt(expected.as_deref()),
+                e(&value)
+            )?),
+            KvCall::Txn { ops } => {
+                writeln!(writer, "TXN BEGIN")?;
+                for op in ops {
+                    op.into_write(writer, enc, None)?;
+                }
+                if let Some(tag) = tag {
+                    write!(writer, "#{} ", tag)?;
+                }
+                Ok(writeln!(writer, "TXN COMMIT")?)
             }
-            KvCall::Delete { key } => Ok(writeln!(writer, "DELETE {}", key)?),
             KvCall::Stop => Ok(writeln!(writer, "STOP")?),
         }
     }
 
+    /// Keyword of the operation call.
+    pub fn op_name(&self) -> &'static str {
+        match self {
+            KvCall::Put { .. } => "PUT",
+            KvCall::Swap { .. } => "SWAP",
+            KvCall::Get { .. } => "GET",
+            KvCall::Scan { .. } => "SCAN",
+            KvCall::Delete { .. } => "DELETE",
+            KvCall::Cas { .. } => "CAS",
+            KvCall::Txn { .. } => "TXN",
+            KvCall::Stop => "STOP",
+        }
+    }
+
     /// Returns the value update made by this operation call:
-    ///   - `None` if read-only operation
+    ///   - `None` if read-only operation, or transaction (whose updates are
+    ///     those of its operations)
     ///   - `Some((key, None))` if Delete operation
-    ///   - `Some((key, Some(value)))` if Put or Swap operation
+    ///   - `Some((key, Some(value)))` if Put or Swap operation, or Cas
+    ///     operation in case it succeeds
     pub fn update_info(&self) -> Option<(String, Option<String>)> {
         match self {
             KvCall::Put { key, value, .. } => Some((key.clone(), Some(value.clone()))),
             KvCall::Swap { key, value, .. } => Some((key.clone(), Some(value.clone()))),
+            KvCall::Cas { key, value, .. } => Some((key.clone(), Some(value.clone()))),
             KvCall::Delete { key, .. } => Some((key.clone(), None)),
             _ => None,
         }
@@ -71,10 +274,39 @@ pub enum KvResp {
         key: String,
         found: bool,
     },
+    Cas {
+        key: String,
+        ok: bool,
+        current: Option<String>,
+    },
+    /// Responses to the operations of a committed transaction in order, or
+    /// `None` if it aborted.
+    Txn {
+        results: Option<Vec<KvResp>>,
+    },
+    Error {
+        op: String,
+        key: String,
+        reason: String,
+    },
+    Unknown {
+        op: String,
+        key: String,
+    },
     Stop,
 }
 
 impl KvResp {
+    /// True if the operation definitely failed without taking effect.
+    pub fn is_error(&self) -> bool {
+        matches!(self, KvResp::Error { .. })
+    }
+
+    /// True if the operation may or may not have taken effect.
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, KvResp::Unknown { .. })
+    }
+
     /// Read the next line from reader into a thread-local buffer.
     fn read_next_line(
         mut reader: impl io::BufRead,
@@ -83,6 +315,9 @@ impl KvResp {
         buffer.clear();
         let size = loop {
             let size = reader.read_line(buffer)?;
+            if size == 0 {
+                return Err(RunnerError::Io("client output closed".into()));
+            }
             // skip empty lines
             if !buffer.trim().is_empty() {
                 break size;
@@ -92,7 +327,7 @@ impl KvResp {
     }
 
     /// Return an iterator over a line's whitespace-delimited segments.
-    fn get_segs_of_line(buffer: &str) -> SplitWhitespace {
+    fn get_segs_of_line(buffer: &str) -> SplitWhitespace<'_> {
         buffer.split_whitespace()
     }
 
@@ -104,59 +339,124 @@ impl KvResp {
             .ok_or(RunnerError::Io(format!("invalid line: {}", buffer)))
     }
 
-    /// Construct a KV operation response from a reader.
+    /// Expect the next segment as a key or value in the given encoding.
+    fn expect_next_key(
+        segs: &mut SplitWhitespace,
+        buffer: &str,
+        enc: Encoding,
+    ) -> Result<String, RunnerError> {
+        enc.decode(&Self::expect_next_seg(segs, buffer)?)
+    }
+
+    /// Expect the next segment as a `found` or `not_found` field.
+    fn expect_next_found(segs: &mut SplitWhitespace, buffer: &str) -> Result<bool, RunnerError> {
+        let found = Self::expect_next_seg(segs, buffer)?;
+        if found == "found" {
+            Ok(true)
+        } else if found == "not_found" {
+            Ok(false)
+        } else {
+            Err(RunnerError::Parse(format!(
+                "invalid 'found' field: {}",
+                found
+            )))
+        }
+    }
+
+    /// Split the request ID tag off the front of a line's segments if it
+    /// has one, returning the tag and the first segment after it.
+    fn strip_tag(
+        segs: &mut SplitWhitespace,
+        buffer: &str,
+    ) -> Result<(Option<u64>, Option<String>), RunnerError> {
+        match segs.next() {
+            Some(seg) if seg.starts_with('#') => {
+                let tag = seg[1..]
+                    .parse()
+                    .map_err(|_| RunnerError::Parse(format!("invalid request tag: {}", buffer)))?;
+                Ok((Some(tag), segs.next().map(|s| s.into())))
+            }
+            first => Ok((None, first.map(|s| s.into()))),
+        }
+    }
+
+    /// Check if a line is the end of a scan with given request ID tag.
+    fn is_scan_end(buffer: &str, tag: Option<u64>) -> bool {
+        let mut segs = Self::get_segs_of_line(buffer);
+        let tagged = match tag {
+            Some(tag) => segs.next() == Some(&format!("#{}", tag)),
+            None => true,
+        };
+        tagged && segs.next() == Some("SCAN") && segs.next() == Some("END") && segs.next().is_none()
+    }
+
+    /// Check if a line is the commit of a transaction with given request ID
+    /// tag.
+    fn is_txn_commit(buffer: &str, tag: Option<u64>) -> bool {
+        let mut segs = Self::get_segs_of_line(buffer);
+        let tagged = match tag {
+            Some(tag) => segs.next() == Some(&format!("#{}", tag)),
+            None => true,
+        };
+        tagged
+            && segs.next() == Some("TXN")
+            && segs.next() == Some("COMMIT")
+            && segs.next().is_none()
+    }
+
+    /// Parse the response line of an operation inside a transaction.
+    fn parse_txn_result(buffer: &str, enc: Encoding) -> Result<KvResp, RunnerError> {
+        let mut segs = Self::get_segs_of_line(buffer);
+        match segs.next() {
+            Some("GET") => Ok(KvResp::Get {
+                key: Self::expect_next_key(&mut segs, buffer, enc)?,
+                value: enc.decode_opt(&Self::expect_next_seg(&mut segs, buffer)?)?,
+            }),
+            Some("PUT") => Ok(KvResp::Put {
+                key: Self::expect_next_key(&mut segs, buffer, enc)?,
+                found: Self::expect_next_found(&mut segs, buffer)?,
+            }),
+            Some("DELETE") => Ok(KvResp::Delete {
+                key: Self::expect_next_key(&mut segs, buffer, enc)?,
+                found: Self::expect_next_found(&mut segs, buffer)?,
+            }),
+            _ => Err(RunnerError::Io(format!(
+                "invalid line in transaction: {}",
+                buffer
+            ))),
+        }
+    }
+
+    /// Construct a KV operation response from a reader, with keys and values
+    /// in the given encoding, along with its request ID tag if it has one.
     pub(crate) fn from_read(
         reader: &mut impl io::BufRead,
         buffer: &mut String,
-    ) -> Result<KvResp, RunnerError> {
+        enc: Encoding,
+    ) -> Result<(Option<u64>, KvResp), RunnerError> {
         Self::read_next_line(&mut *reader, buffer)?;
         let mut segs = Self::get_segs_of_line(buffer);
+        let (tag, first) = Self::strip_tag(&mut segs, buffer)?;
 
-        match segs.next() {
+        let resp = match first.as_deref() {
             Some("PUT") => Ok(KvResp::Put {
-                key: Self::expect_next_seg(&mut segs, buffer)?,
-                found: {
-                    let found = Self::expect_next_seg(&mut segs, buffer)?;
-                    if found == "found" {
-                        true
-                    } else if found == "not_found" {
-                        false
-                    } else {
-                        return Err(RunnerError::Parse(format!(
-                            "invalid 'found' field: {}",
-                            found
-                        )));
-                    }
-                },
+                key: Self::expect_next_key(&mut segs, buffer, enc)?,
+                found: Self::expect_next_found(&mut segs, buffer)?,
             }),
 
             Some("SWAP") => Ok(KvResp::Swap {
-                key: Self::expect_next_seg(&mut segs, buffer)?,
-                old_value: {
-                    let old_value = Self::expect_next_seg(&mut segs, buffer)?;
-                    if old_value == "null" {
-                        None
-                    } else {
-                        Some(old_value)
-                    }
-                },
+                key: Self::expect_next_key(&mut segs, buffer, enc)?,
+                old_value: enc.decode_opt(&Self::expect_next_seg(&mut segs, buffer)?)?,
             }),
 
             Some("GET") => Ok(KvResp::Get {
-                key: Self::expect_next_seg(&mut segs, buffer)?,
-                value: {
-                    let value = Self::expect_next_seg(&mut segs, buffer)?;
-                    if value == "null" {
-                        None
-                    } else {
-                        Some(value)
-                    }
-                },
+                key: Self::expect_next_key(&mut segs, buffer, enc)?,
+                value: enc.decode_opt(&Self::expect_next_seg(&mut segs, buffer)?)?,
             }),
 
             Some("SCAN") => {
-                let key_start = Self::expect_next_seg(&mut segs, buffer)?;
-                let key_end = Self::expect_next_seg(&mut segs, buffer)?;
+                let key_start = Self::expect_next_key(&mut segs, buffer, enc)?;
+                let key_end = Self::expect_next_key(&mut segs, buffer, enc)?;
                 let begin = Self::expect_next_seg(&mut segs, buffer)?;
                 if begin != "BEGIN" {
                     return Err(RunnerError::Io(format!("invalid line: {}", buffer)));
@@ -166,13 +466,13 @@ impl KvResp {
                 let mut entries = vec![];
                 loop {
                     Self::read_next_line(&mut *reader, buffer)?;
-                    if buffer.trim() == "SCAN END" {
+                    if Self::is_scan_end(buffer, tag) {
                         break;
                     }
                     let mut entry_segs = Self::get_segs_of_line(buffer);
 
-                    let key = Self::expect_next_seg(&mut entry_segs, buffer)?;
-                    let value = Self::expect_next_seg(&mut entry_segs, buffer)?;
+                    let key = Self::expect_next_key(&mut entry_segs, buffer, enc)?;
+                    let value = Self::expect_next_key(&mut entry_segs, buffer, enc)?;
                     entries.push((key, value));
                 }
 
@@ -184,25 +484,63 @@ impl KvResp {
             }
 
             Some("DELETE") => Ok(KvResp::Delete {
-                key: Self::expect_next_seg(&mut segs, buffer)?,
-                found: {
-                    let found = Self::expect_next_seg(&mut segs, buffer)?;
-                    if found == "found" {
+                key: Self::expect_next_key(&mut segs, buffer, enc)?,
+                found: Self::expect_next_found(&mut segs, buffer)?,
+            }),
+
+            Some("CAS") => Ok(KvResp::Cas {
+                key: Self::expect_next_key(&mut segs, buffer, enc)?,
+                ok: {
+                    let ok = Self::expect_next_seg(&mut segs, buffer)?;
+                    if ok == "ok" {
                         true
-                    } else if found == "not_found" {
+                    } else if ok == "fail" {
                         false
                     } else {
-                        return Err(RunnerError::Parse(format!(
-                            "invalid 'found' field: {}",
-                            found
-                        )));
+                        return Err(RunnerError::Parse(format!("invalid 'ok' field: {}", ok)));
                     }
                 },
+                current: enc.decode_opt(&Self::expect_next_seg(&mut segs, buffer)?)?,
+            }),
+
+            Some("TXN") => match Self::expect_next_seg(&mut segs, buffer)?.as_str() {
+                "ABORT" => Ok(KvResp::Txn { results: None }),
+                "BEGIN" => {
+                    // loop through per-operation results
+                    let mut results = vec![];
+                    loop {
+                        Self::read_next_line(&mut *reader, buffer)?;
+                        if Self::is_txn_commit(buffer, tag) {
+                            break;
+                        }
+                        results.push(Self::parse_txn_result(buffer, enc)?);
+                    }
+                    Ok(KvResp::Txn {
+                        results: Some(results),
+                    })
+                }
+                _ => Err(RunnerError::Io(format!("invalid line: {}", buffer))),
+            },
+
+            Some("ERROR") => {
+                let op = Self::expect_next_seg(&mut segs, buffer)?;
+                let key = Self::expect_next_key(&mut segs, buffer, enc)?;
+                Ok(KvResp::Error {
+                    op,
+                    key,
+                    reason: segs.collect::<Vec<_>>().join(" "),
+                })
+            }
+
+            Some("UNKNOWN") => Ok(KvResp::Unknown {
+                op: Self::expect_next_seg(&mut segs, buffer)?,
+                key: Self::expect_next_key(&mut segs, buffer, enc)?,
             }),
 
             Some("STOP") => Ok(KvResp::Stop),
 
             _ => Err(RunnerError::Io(format!("invalid line: {}", buffer))),
-        }
+        }?;
+        Ok((tag, resp))
     }
 }
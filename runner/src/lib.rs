@@ -1,10 +1,39 @@
 //! Driver library that runs a key-value client with automated workloads.
 
+mod checker;
+pub use checker::{
+    check_and_report, check_linearizable, check_scans_atomic, check_serializable, check_sessions,
+    classify_consistency, print_faults, print_record, print_timeline, report_inconsistent,
+    report_remaining, report_sessions, shrink_and_report, shrink_history, ApproxOutcome,
+    CheckOptions, CheckerKind, ConsistencyLevel, ConsistencyReport, ConvergenceLevel, DepKind,
+    FaultAction, FaultRecord, History, HistoryFile, HistoryWriter, LinearViolation, OpRecord,
+    RelationshipLevel, SerialAnomaly, SerialViolation, SessionGuarantee, SessionReport,
+    SessionViolation, REMAIN_THRESH,
+};
+
+mod cluster;
+pub use cluster::{Cluster, ClusterSpec};
+
+mod config;
+pub use config::{ClusterConfig, NodeConfig};
+
+mod control;
+pub use control::{ServiceCmd, ServiceCtl};
+
 mod error;
 pub use error::RunnerError;
 
 mod ioapi;
-pub use ioapi::{KvCall, KvResp};
+pub use ioapi::{Encoding, KvCall, KvResp};
+
+mod netem;
+pub use netem::{Direction, Netem, NetemCmd, NetemCtl};
 
 mod proc;
-pub use proc::{ClientProc, ServerProc};
+pub use proc::{
+    kill_children_on_exit, ClientProc, ProcGroup, ProcTree, ServerProc, ServiceNodes, Signal,
+    STOP_GRACE,
+};
+
+mod topology;
+pub use topology::{NodeId, NodeRole, Topology};
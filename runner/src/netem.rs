@@ -0,0 +1,567 @@
+//! Local fault-injecting TCP proxy for emulating network faults.
+//!
+//! Each named link listens on a local address and forwards every connection
+//! accepted to a target address. Faults are applied per link: latency with
+//! jitter on every chunk of bytes forwarded, dropping (resetting) newly
+//! accepted connections with some probability, resetting all current
+//! connections, and cutting the link in the forward (towards the target)
+//! and/or reverse direction. Bytes sent over a cut direction are held back
+//! until the link is healed, just like a TCP connection stalls across a
+//! network partition.
+//!
+//! Links are controlled with line-oriented text commands, each answered by
+//! zero or more info lines followed by a final `OK` or `ERR <msg>` line:
+//!
+//! ```text
+//! delay <link> <ms> [<jitter_ms>]
+//! drop <link> <prob>
+//! cut <link> <fwd|rev|both>
+//! heal <link>
+//! reset <link>
+//! clear <link>
+//! status
+//! ```
+//!
+//! where `<link>` is a link name, or `*` for all links.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::mem;
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::fd::AsRawFd;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::RunnerError;
+
+/// Size of buffer for each read of forwarded bytes.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Polling interval of a stalled direction waiting for the link to heal.
+const CUT_POLL: Duration = Duration::from_millis(10);
+
+/// Direction of traffic over a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the connecting side towards the target.
+    Fwd,
+    /// From the target back to the connecting side.
+    Rev,
+    /// Both directions.
+    Both,
+}
+
+impl FromStr for Direction {
+    type Err = RunnerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fwd" => Ok(Direction::Fwd),
+            "rev" => Ok(Direction::Rev),
+            "both" => Ok(Direction::Both),
+            _ => Err(RunnerError::Parse(format!("invalid direction: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Direction::Fwd => write!(f, "fwd"),
+            Direction::Rev => write!(f, "rev"),
+            Direction::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// Control command to a netem proxy.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetemCmd {
+    Delay {
+        link: String,
+        delay: Duration,
+        jitter: Duration,
+    },
+    Drop {
+        link: String,
+        prob: f64,
+    },
+    Cut {
+        link: String,
+        dir: Direction,
+    },
+    Heal {
+        link: String,
+    },
+    Reset {
+        link: String,
+    },
+    Clear {
+        link: String,
+    },
+    Status,
+}
+
+impl FromStr for NetemCmd {
+    type Err = RunnerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RunnerError::Parse(format!("invalid netem command: {}", s.trim()));
+        let segs: Vec<&str> = s.split_whitespace().collect();
+        let link = || segs.get(1).map(|l| l.to_string()).ok_or_else(invalid);
+        let millis = |seg: &str| -> Result<Duration, RunnerError> {
+            Ok(Duration::from_millis(seg.parse()?))
+        };
+
+        let cmd = match segs.first().copied() {
+            Some("delay") if segs.len() == 3 || segs.len() == 4 => NetemCmd::Delay {
+                link: link()?,
+                delay: millis(segs[2])?,
+                jitter: segs
+                    .get(3)
+                    .map(|j| millis(j))
+                    .transpose()?
+                    .unwrap_or_default(),
+            },
+            Some("drop") if segs.len() == 3 => {
+                let prob: f64 = segs[2].parse()?;
+                if !(0.0..=1.0).contains(&prob) {
+                    return Err(invalid());
+                }
+                NetemCmd::Drop {
+                    link: link()?,
+                    prob,
+                }
+            }
+            Some("cut") if segs.len() == 3 => NetemCmd::Cut {
+                link: link()?,
+                dir: segs[2].parse()?,
+            },
+            Some("heal") if segs.len() == 2 => NetemCmd::Heal { link: link()? },
+            Some("reset") if segs.len() == 2 => NetemCmd::Reset { link: link()? },
+            Some("clear") if segs.len() == 2 => NetemCmd::Clear { link: link()? },
+            Some("status") if segs.len() == 1 => NetemCmd::Status,
+            _ => return Err(invalid()),
+        };
+        Ok(cmd)
+    }
+}
+
+impl fmt::Display for NetemCmd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetemCmd::Delay {
+                link,
+                delay,
+                jitter,
+            } => write!(
+                f,
+                "delay {} {} {}",
+                link,
+                delay.as_millis(),
+                jitter.as_millis()
+            ),
+            NetemCmd::Drop { link, prob } => write!(f, "drop {} {}", link, prob),
+            NetemCmd::Cut { link, dir } => write!(f, "cut {} {}", link, dir),
+            NetemCmd::Heal { link } => write!(f, "heal {}", link),
+            NetemCmd::Reset { link } => write!(f, "reset {}", link),
+            NetemCmd::Clear { link } => write!(f, "clear {}", link),
+            NetemCmd::Status => write!(f, "status"),
+        }
+    }
+}
+
+/// Faults currently applied on a link.
+#[derive(Debug, Clone, Default)]
+struct LinkFaults {
+    delay: Duration,
+    jitter: Duration,
+    drop_prob: f64,
+    cut_fwd: bool,
+    cut_rev: bool,
+}
+
+impl LinkFaults {
+    fn is_cut(&self, dir: Direction) -> bool {
+        match dir {
+            Direction::Fwd => self.cut_fwd,
+            Direction::Rev => self.cut_rev,
+            Direction::Both => self.cut_fwd && self.cut_rev,
+        }
+    }
+}
+
+/// Both sides of a proxied connection.
+#[derive(Debug)]
+struct Conn {
+    id: u64,
+    near: TcpStream,
+    far: TcpStream,
+    reset: AtomicBool,
+}
+
+impl Conn {
+    /// Abort the connection so that both sides see a reset.
+    fn reset(&self) {
+        self.reset.store(true, Ordering::Release);
+        abort_stream(&self.near);
+        abort_stream(&self.far);
+    }
+}
+
+/// Make a stream send a reset instead of a graceful close once its last
+/// handle is dropped, and wake up any thread blocked reading it.
+fn abort_stream(stream: &TcpStream) {
+    let linger = libc::linger {
+        l_onoff: 1,
+        l_linger: 0,
+    };
+    // SAFETY: the option value points to a properly sized linger struct
+    unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_LINGER,
+            &linger as *const libc::linger as *const libc::c_void,
+            mem::size_of::<libc::linger>() as libc::socklen_t,
+        );
+    }
+    let _ = stream.shutdown(Shutdown::Read);
+}
+
+/// Shared state of a named link.
+#[derive(Debug)]
+struct Link {
+    listen: String,
+    target: String,
+    faults: Mutex<LinkFaults>,
+    conns: Mutex<Vec<Arc<Conn>>>,
+}
+
+impl Link {
+    fn faults(&self) -> LinkFaults {
+        self.faults.lock().unwrap().clone()
+    }
+
+    fn status(&self, name: &str) -> String {
+        let faults = self.faults();
+        let cut = match (faults.cut_fwd, faults.cut_rev) {
+            (false, false) => "none",
+            (true, false) => "fwd",
+            (false, true) => "rev",
+            (true, true) => "both",
+        };
+        format!(
+            "{} {} -> {}  conns {}  delay {}ms  jitter {}ms  drop {}  cut {}",
+            name,
+            self.listen,
+            self.target,
+            self.conns.lock().unwrap().len(),
+            faults.delay.as_millis(),
+            faults.jitter.as_millis(),
+            faults.drop_prob,
+            cut
+        )
+    }
+}
+
+/// Fault-injecting TCP proxy over a set of named links.
+#[derive(Debug, Clone, Default)]
+pub struct Netem {
+    links: Arc<Mutex<BTreeMap<String, Arc<Link>>>>,
+}
+
+impl Netem {
+    /// Create a proxy with no links.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a named link listening on `listen` and forwarding to `target`,
+    /// starting to accept connections on it in the background.
+    pub fn add_link(&self, name: &str, listen: &str, target: &str) -> Result<(), RunnerError> {
+        let mut links = self.links.lock().unwrap();
+        if name == "*" || links.contains_key(name) {
+            return Err(RunnerError::Parse(format!("invalid link name: {}", name)));
+        }
+        let listener = TcpListener::bind(listen)?;
+        let link = Arc::new(Link {
+            listen: listen.into(),
+            target: target.into(),
+            faults: Mutex::new(LinkFaults::default()),
+            conns: Mutex::new(vec![]),
+        });
+        links.insert(name.into(), link.clone());
+        thread::spawn(move || Self::acceptor_thread(listener, link));
+        Ok(())
+    }
+
+    /// Apply a control command, returning info lines to reply with.
+    pub fn apply(&self, cmd: &NetemCmd) -> Result<Vec<String>, RunnerError> {
+        let links = self.links.lock().unwrap();
+        let selected = |link: &str| -> Result<Vec<&Arc<Link>>, RunnerError> {
+            if link == "*" {
+                Ok(links.values().collect())
+            } else {
+                links
+                    .get(link)
+                    .map(|l| vec![l])
+                    .ok_or_else(|| RunnerError::Parse(format!("unknown link: {}", link)))
+            }
+        };
+
+        match cmd {
+            NetemCmd::Delay {
+                link,
+                delay,
+                jitter,
+            } => {
+                for l in selected(link)? {
+                    let mut faults = l.faults.lock().unwrap();
+                    faults.delay = *delay;
+                    faults.jitter = *jitter;
+                }
+            }
+            NetemCmd::Drop { link, prob } => {
+                for l in selected(link)? {
+                    l.faults.lock().unwrap().drop_prob = *prob;
+                }
+            }
+            NetemCmd::Cut { link, dir } => {
+                for l in selected(link)? {
+                    let mut faults = l.faults.lock().unwrap();
+                    faults.cut_fwd |= *dir != Direction::Rev;
+                    faults.cut_rev |= *dir != Direction::Fwd;
+                }
+            }
+            NetemCmd::Heal { link } => {
+                for l in selected(link)? {
+                    let mut faults = l.faults.lock().unwrap();
+                    faults.cut_fwd = false;
+                    faults.cut_rev = false;
+                }
+            }
+            NetemCmd::Reset { link } => {
+                for l in selected(link)? {
+                    for conn in l.conns.lock().unwrap().drain(..) {
+                        conn.reset();
+                    }
+                }
+            }
+            NetemCmd::Clear { link } => {
+                for l in selected(link)? {
+                    *l.faults.lock().unwrap() = LinkFaults::default();
+                }
+            }
+            NetemCmd::Status => {
+                return Ok(links.iter().map(|(name, link)| link.status(name)).collect());
+            }
+        }
+        Ok(vec![])
+    }
+
+    /// Serve control connections on given address in the background, each
+    /// sending one command per line.
+    pub fn serve_control(&self, addr: &str) -> Result<(), RunnerError> {
+        let listener = TcpListener::bind(addr)?;
+        let netem = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let netem = netem.clone();
+                thread::spawn(move || {
+                    if let Err(err) = netem.control_session(stream) {
+                        eprintln!("Error in netem control: {}", err);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    /// Handle commands from a reader, writing replies to a writer, until the
+    /// reader reaches EOF.
+    pub fn control_loop(
+        &self,
+        reader: impl BufRead,
+        writer: &mut impl Write,
+    ) -> Result<(), RunnerError> {
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse().and_then(|cmd| self.apply(&cmd)) {
+                Ok(infos) => {
+                    for info in infos {
+                        writeln!(writer, "{}", info)?;
+                    }
+                    writeln!(writer, "OK")?;
+                }
+                Err(err) => writeln!(writer, "ERR {}", err)?,
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// One control connection session.
+    fn control_session(&self, stream: TcpStream) -> Result<(), RunnerError> {
+        let reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        self.control_loop(reader, &mut writer)
+    }
+
+    /// Accept connections on a link, dropping some of them if requested.
+    fn acceptor_thread(listener: TcpListener, link: Arc<Link>) {
+        let mut next_id = 0;
+        for near in listener.incoming().flatten() {
+            if rand::rng().random_bool(link.faults().drop_prob) {
+                abort_stream(&near);
+                continue;
+            }
+            let far = match TcpStream::connect(&link.target) {
+                Ok(far) => far,
+                Err(_) => {
+                    abort_stream(&near);
+                    continue;
+                }
+            };
+            let _ = near.set_nodelay(true);
+            let _ = far.set_nodelay(true);
+
+            next_id += 1;
+            let conn = Arc::new(Conn {
+                id: next_id,
+                near,
+                far,
+                reset: AtomicBool::new(false),
+            });
+            link.conns.lock().unwrap().push(conn.clone());
+            if Self::spawn_pumps(&link, &conn).is_err() {
+                conn.reset();
+            }
+        }
+    }
+
+    /// Spawn the reader and writer threads of both directions of a
+    /// connection; the connection gets unregistered once both are done.
+    fn spawn_pumps(link: &Arc<Link>, conn: &Arc<Conn>) -> Result<(), RunnerError> {
+        let done = Arc::new(Mutex::new(0));
+        for dir in [Direction::Fwd, Direction::Rev] {
+            let (src, dst) = match dir {
+                Direction::Fwd => (conn.near.try_clone()?, conn.far.try_clone()?),
+                _ => (conn.far.try_clone()?, conn.near.try_clone()?),
+            };
+            let (tx, rx) = mpsc::channel();
+            let (link_r, link_w) = (link.clone(), link.clone());
+            let (conn_w, done) = (conn.clone(), done.clone());
+            thread::spawn(move || Self::reader_thread(src, &link_r, tx));
+            thread::spawn(move || {
+                Self::writer_thread(dst, &link_w, &conn_w, dir, rx);
+                let mut done = done.lock().unwrap();
+                *done += 1;
+                if *done == 2 {
+                    link_w.conns.lock().unwrap().retain(|c| c.id != conn_w.id);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Read chunks from one side, stamping each with the time it may be
+    /// delivered at. Delivery times never decrease, so that jitter does not
+    /// reorder bytes within the stream.
+    fn reader_thread(mut src: TcpStream, link: &Link, tx: mpsc::Sender<(Instant, Vec<u8>)>) {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut last_at = Instant::now();
+        while let Ok(n) = src.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let faults = link.faults();
+            let jitter = if faults.jitter.is_zero() {
+                Duration::ZERO
+            } else {
+                rand::rng().random_range(Duration::ZERO..=faults.jitter)
+            };
+            last_at = last_at.max(Instant::now() + faults.delay + jitter);
+            if tx.send((last_at, buf[..n].to_vec())).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Deliver chunks to the other side once due and while the direction is
+    /// not cut, half-closing it when the reading side reached EOF.
+    fn writer_thread(
+        mut dst: TcpStream,
+        link: &Link,
+        conn: &Conn,
+        dir: Direction,
+        rx: mpsc::Receiver<(Instant, Vec<u8>)>,
+    ) {
+        for (at, chunk) in rx {
+            thread::sleep(at.saturating_duration_since(Instant::now()));
+            while link.faults().is_cut(dir) && !conn.reset.load(Ordering::Acquire) {
+                thread::sleep(CUT_POLL);
+            }
+            if conn.reset.load(Ordering::Acquire) || dst.write_all(&chunk).is_err() {
+                conn.reset();
+                return;
+            }
+        }
+        if !conn.reset.load(Ordering::Acquire) {
+            let _ = dst.shutdown(Shutdown::Write);
+        }
+    }
+}
+
+/// Client of a netem proxy's control address.
+#[derive(Debug)]
+pub struct NetemCtl {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl NetemCtl {
+    /// Connect to the control address of a netem proxy.
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, RunnerError> {
+        let writer = TcpStream::connect(addr)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(NetemCtl { reader, writer })
+    }
+
+    /// Send a command, returning the info lines replied.
+    pub fn send(&mut self, cmd: &NetemCmd) -> Result<Vec<String>, RunnerError> {
+        writeln!(self.writer, "{}", cmd)?;
+        let mut infos = vec![];
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(RunnerError::Io("netem control connection closed".into()));
+            }
+            let line = line.trim_end();
+            if line == "OK" {
+                return Ok(infos);
+            } else if let Some(msg) = line.strip_prefix("ERR ") {
+                return Err(RunnerError::Io(format!("netem error: {}", msg)));
+            }
+            infos.push(line.into());
+        }
+    }
+
+    /// Names of all links of the proxy.
+    pub fn link_names(&mut self) -> Result<Vec<String>, RunnerError> {
+        Ok(self
+            .send(&NetemCmd::Status)?
+            .iter()
+            .filter_map(|info| info.split_whitespace().next().map(|n| n.into()))
+            .collect())
+    }
+}
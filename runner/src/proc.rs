@@ -1,37 +1,414 @@
 //! Process running and management.
 
 use std::cell::RefCell;
-use std::io::BufReader;
-use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
-use std::sync::mpsc;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::os::unix::process::CommandExt;
+use std::panic;
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::{KvCall, KvResp, RunnerError};
+use crate::{Encoding, KvCall, KvResp, RunnerError};
 
 thread_local! {
     /// Thread-local buffer for reading lines of client output.
     static READBUF: RefCell<String> = const { RefCell::new(String::new()) };
 }
 
-/// Wrapper handle to a KV server (or manager) process.
+/// Signal that can be delivered to a process group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGKILL, crashing the process without any chance of cleanup.
+    Kill,
+    /// SIGTERM, letting the process shut down gracefully.
+    Term,
+    /// SIGSTOP, pausing the process as in a long GC pause.
+    Stop,
+    /// SIGCONT, resuming a paused process.
+    Cont,
+}
+
+impl Signal {
+    /// Corresponding raw signal number.
+    fn signum(self) -> libc::c_int {
+        match self {
+            Signal::Kill => libc::SIGKILL,
+            Signal::Term => libc::SIGTERM,
+            Signal::Stop => libc::SIGSTOP,
+            Signal::Cont => libc::SIGCONT,
+        }
+    }
+}
+
+/// Copyable handle to the process group of a spawned process, for signalling
+/// every process in it, possibly from other threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcGroup {
+    pgid: u32,
+}
+
+impl ProcGroup {
+    /// Deliver a signal to every process in the group.
+    pub fn signal(&self, sig: Signal) -> Result<(), RunnerError> {
+        // SAFETY: plain syscall with no memory arguments
+        if unsafe { libc::kill(-(self.pgid as libc::pid_t), sig.signum()) } != 0 {
+            let err = io::Error::last_os_error();
+            // group already gone entirely is fine
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Pause every process in the group.
+    pub fn pause(&self) -> Result<(), RunnerError> {
+        self.signal(Signal::Stop)
+    }
+
+    /// Resume every process in the group.
+    pub fn resume(&self) -> Result<(), RunnerError> {
+        self.signal(Signal::Cont)
+    }
+}
+
+/// Copyable handle to a spawned process together with all its descendants,
+/// for signalling them when they share the caller's process group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcTree {
+    pid: u32,
+}
+
+impl ProcTree {
+    /// PID of the root process.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// PIDs of the root process and all its live descendants, parents before
+    /// children, found by scanning the parent PIDs in `/proc`.
+    fn pids(&self) -> Result<Vec<u32>, RunnerError> {
+        let mut parents = vec![];
+        for entry in fs::read_dir("/proc")? {
+            let Ok(pid) = entry?.file_name().to_string_lossy().parse::<u32>() else {
+                continue;
+            };
+            // process may have exited in between
+            let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+                continue;
+            };
+            // the command name in parentheses may contain anything
+            let ppid = stat
+                .rsplit_once(')')
+                .and_then(|(_, rest)| rest.split_whitespace().nth(1))
+                .and_then(|ppid| ppid.parse::<u32>().ok());
+            if let Some(ppid) = ppid {
+                parents.push((pid, ppid));
+            }
+        }
+
+        let mut tree = vec![self.pid];
+        let mut i = 0;
+        while i < tree.len() {
+            let parent = tree[i];
+            tree.extend(
+                parents
+                    .iter()
+                    .filter(|&&(_, ppid)| ppid == parent)
+                    .map(|&(pid, _)| pid),
+            );
+            i += 1;
+        }
+        Ok(tree)
+    }
+
+    /// Deliver a signal to the root process and all its descendants.
+    pub fn signal(&self, sig: Signal) -> Result<(), RunnerError> {
+        for pid in self.pids()? {
+            // SAFETY: plain syscall with no memory arguments
+            if unsafe { libc::kill(pid as libc::pid_t, sig.signum()) } != 0 {
+                let err = io::Error::last_os_error();
+                // process already gone is fine
+                if err.raw_os_error() != Some(libc::ESRCH) {
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pause the root process and all its descendants.
+    pub fn pause(&self) -> Result<(), RunnerError> {
+        self.signal(Signal::Stop)
+    }
+
+    /// Resume the root process and all its descendants.
+    pub fn resume(&self) -> Result<(), RunnerError> {
+        self.signal(Signal::Cont)
+    }
+}
+
+/// Grace period given to a process group between SIGTERM and SIGKILL when
+/// stopping it.
+pub const STOP_GRACE: Duration = Duration::from_secs(3);
+
+/// Polling interval of waiting for a process group leader to exit.
+const STOP_POLL: Duration = Duration::from_millis(10);
+
+/// Maximum number of live process groups tracked for killing upon exit.
+const MAX_LIVE_GROUPS: usize = 4096;
+
+/// Process groups of live children spawned into their own groups, as slots
+/// holding their PGIDs (0 if free). Atomics can be safely scanned from a
+/// signal handler, unlike a locked collection.
+static LIVE_GROUPS: [AtomicU32; MAX_LIVE_GROUPS] = [const { AtomicU32::new(0) }; MAX_LIVE_GROUPS];
+
+/// Track a live process group. If all slots are taken, the group is only
+/// covered by its handle being dropped.
+fn register_group(pgid: u32) {
+    for slot in &LIVE_GROUPS {
+        if slot
+            .compare_exchange(0, pgid, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            return;
+        }
+    }
+}
+
+/// Stop tracking a process group about to be reaped.
+fn unregister_group(pgid: u32) {
+    for slot in &LIVE_GROUPS {
+        if slot
+            .compare_exchange(pgid, 0, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            return;
+        }
+    }
+}
+
+/// SIGKILL every tracked process group. Only uses async-signal-safe calls.
+fn kill_live_groups() {
+    for slot in &LIVE_GROUPS {
+        let pgid = slot.load(Ordering::Acquire);
+        if pgid != 0 {
+            // SAFETY: plain syscall with no memory arguments
+            unsafe {
+                libc::kill(-(pgid as libc::pid_t), libc::SIGKILL);
+            }
+        }
+    }
+}
+
+extern "C" fn on_exit_signal(sig: libc::c_int) {
+    kill_live_groups();
+    // SAFETY: restoring the default disposition and re-raising are both
+    // async-signal-safe, and terminate us as the signal would have
+    unsafe {
+        libc::signal(sig, libc::SIG_DFL);
+        libc::raise(sig);
+    }
+}
+
+/// Kill all server and client process groups spawned into their own groups
+/// (which do not get a Ctrl-C themselves) when the runner gets terminated by
+/// SIGINT, SIGTERM, or SIGHUP, or when any of its threads panics. Handles
+/// dropped normally already stop their processes. Signal handlers installed
+/// after this call take precedence.
+pub fn kill_children_on_exit() {
+    let handler = on_exit_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only makes async-signal-safe calls
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGHUP, handler);
+    }
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        kill_live_groups();
+    }));
+}
+
+/// Returns true if the child has exited, without reaping it, so that its PID
+/// (and PGID, if it leads a group) cannot get reused yet.
+fn has_exited(child: &Child) -> bool {
+    // SAFETY: siginfo_t is plain old data, valid when zeroed
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    // SAFETY: info is a valid out-pointer for the duration of the call
+    let ret = unsafe {
+        libc::waitid(
+            libc::P_PID,
+            child.id(),
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    };
+    // reaped already if failed; otherwise, si_pid is only set upon exit
+    // SAFETY: info was filled in by a successful waitid()
+    ret != 0 || unsafe { info.si_pid() } != 0
+}
+
+/// Wait until the deadline for the child to exit, without reaping it,
+/// returning true if it did.
+fn wait_exited(child: &Child, deadline: Instant) -> bool {
+    while !has_exited(child) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(STOP_POLL);
+    }
+    true
+}
+
+/// Shut down the process group led by the child: SIGTERM the group (resuming
+/// it if paused), wait until the deadline for the leader to exit, then
+/// SIGKILL whatever is left of the group and reap the leader.
+fn shutdown_group(child: &mut Child, deadline: Instant) -> Result<ExitStatus, RunnerError> {
+    let group = ProcGroup { pgid: child.id() };
+    if !has_exited(child) {
+        group.signal(Signal::Term)?;
+        group.resume()?;
+        wait_exited(child, deadline);
+    }
+    // the leader is at most a zombie here, so the PGID is still ours
+    group.signal(Signal::Kill)?;
+    unregister_group(group.pgid);
+    Ok(child.wait()?)
+}
+
+/// Wrapper handle to a KV server (or manager) process. A process that has
+/// not been stopped or waited for is shut down when the handle is dropped.
 #[derive(Debug)]
 pub struct ServerProc {
     handle: Child,
+    /// Whether the process leads its own process group.
+    own_group: bool,
+    reaped: bool,
 }
 
 impl ServerProc {
     /// Run a server or manager process using provided `just` recipe args,
-    /// returning a handle to it.
+    /// returning a handle to it. The process is started in its own process
+    /// group so that signals reach the actual server under `just` as well.
     pub fn new(just_args: Vec<&str>) -> Result<ServerProc, RunnerError> {
+        let handle = Command::new("just")
+            .args(just_args)
+            .process_group(0)
+            .spawn()?;
+        register_group(handle.id());
+        Ok(ServerProc {
+            handle,
+            own_group: true,
+            reaped: false,
+        })
+    }
+
+    /// Run a server or manager process like `new()`, but in the caller's own
+    /// process group, so that signals delivered to the caller's group (e.g.,
+    /// by a runner that owns this process as a service node) reach it too.
+    pub fn new_in_caller_group(just_args: Vec<&str>) -> Result<ServerProc, RunnerError> {
         let handle = Command::new("just").args(just_args).spawn()?;
-        Ok(ServerProc { handle })
+        Ok(ServerProc {
+            handle,
+            own_group: false,
+            reaped: false,
+        })
+    }
+
+    /// Run a server or manager process like `new_in_caller_group()`, but with
+    /// its stdout and stderr piped back to the caller, returning them along
+    /// with the handle.
+    pub fn new_in_caller_group_piped(
+        just_args: Vec<&str>,
+    ) -> Result<(ServerProc, ChildStdout, ChildStderr), RunnerError> {
+        let mut handle = Command::new("just")
+            .args(just_args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = handle.stdout.take().unwrap();
+        let stderr = handle.stderr.take().unwrap();
+        let server = ServerProc {
+            handle,
+            own_group: false,
+            reaped: false,
+        };
+        Ok((server, stdout, stderr))
+    }
+
+    /// OS process ID of the server or manager process (i.e., the `just`
+    /// process invoking it).
+    pub fn pid(&self) -> u32 {
+        self.handle.id()
+    }
+
+    /// Process group of the server or manager process. Only meaningful for
+    /// processes run with `new()`.
+    pub fn group(&self) -> ProcGroup {
+        ProcGroup {
+            pgid: self.handle.id(),
+        }
+    }
+
+    /// Process tree of the server or manager process, reaching the actual
+    /// server under `just` even for processes run in the caller's group.
+    pub fn tree(&self) -> ProcTree {
+        ProcTree {
+            pid: self.handle.id(),
+        }
+    }
+
+    /// Pause the whole process group of the server or manager process.
+    pub fn pause(&self) -> Result<(), RunnerError> {
+        self.group().pause()
+    }
+
+    /// Resume the whole process group of the server or manager process.
+    pub fn resume(&self) -> Result<(), RunnerError> {
+        self.group().resume()
+    }
+
+    /// Reap the process after it exited or got signalled.
+    fn reap(&mut self) -> Result<ExitStatus, RunnerError> {
+        if self.own_group {
+            unregister_group(self.handle.id());
+        }
+        let status = self.handle.wait()?;
+        self.reaped = true;
+        Ok(status)
+    }
+
+    /// Crash the server or manager process with a signal, consuming self:
+    /// SIGKILL its whole process group and reap it, or SIGTERM it and give it
+    /// `STOP_GRACE` to exit before SIGKILL of whatever is left, as in
+    /// `stop()`. Pausing or resuming signals are rejected, since the process
+    /// would never exit to be reaped.
+    pub fn crash(mut self, sig: Signal) -> Result<(), RunnerError> {
+        match sig {
+            Signal::Kill => {
+                self.group().signal(sig)?;
+                self.reap()?;
+                Ok(())
+            }
+            Signal::Term => self.shutdown(Instant::now() + STOP_GRACE),
+            Signal::Stop | Signal::Cont => Err(RunnerError::Parse(format!(
+                "cannot crash a process with {:?}",
+                sig
+            ))),
+        }
     }
 
     /// Wait for the server or manager process to exit (usually only happens
     /// on errors), returning `Ok` only upon successful termination.
-    pub fn wait(mut self) -> Result<(), RunnerError> {
-        let status = self.handle.wait()?;
+    pub fn wait(self) -> Result<(), RunnerError> {
+        let status = self.wait_status()?;
         if status.success() {
             Ok(())
         } else {
@@ -42,64 +419,277 @@ impl ServerProc {
         }
     }
 
-    /// Kill the server or manager process, consuming self.
-    pub fn stop(mut self) -> Result<(), RunnerError> {
-        self.handle.kill()?;
+    /// Wait for the server or manager process to exit, returning its exit
+    /// status whatever it is.
+    pub fn wait_status(mut self) -> Result<ExitStatus, RunnerError> {
+        self.reap()
+    }
+
+    /// Shut down the server or manager process, giving it until the deadline
+    /// to exit gracefully upon SIGTERM before SIGKILL. Processes in the
+    /// caller's group get their process tree signalled instead.
+    fn shutdown(&mut self, deadline: Instant) -> Result<(), RunnerError> {
+        if self.own_group {
+            shutdown_group(&mut self.handle, deadline)?;
+        } else {
+            self.tree().signal(Signal::Term)?;
+            self.tree().resume()?;
+            wait_exited(&self.handle, deadline);
+            self.tree().signal(Signal::Kill)?;
+            self.handle.wait()?;
+        }
+        self.reaped = true;
+        Ok(())
+    }
+
+    /// Stop the server or manager process, giving its whole process group
+    /// `STOP_GRACE` to exit gracefully upon SIGTERM before SIGKILL, consuming
+    /// self.
+    pub fn stop(self) -> Result<(), RunnerError> {
+        self.stop_within(STOP_GRACE)
+    }
+
+    /// Stop the server or manager process like `stop()`, with a given grace
+    /// period.
+    pub fn stop_within(mut self, grace: Duration) -> Result<(), RunnerError> {
+        self.shutdown(Instant::now() + grace)
+    }
+}
+
+impl Drop for ServerProc {
+    fn drop(&mut self) {
+        if !self.reaped {
+            let _ = self.shutdown(Instant::now() + STOP_GRACE);
+        }
+    }
+}
+
+/// Placeholder in service `just` args that gets replaced by the node ID.
+pub(crate) const NODE_PLACEHOLDER: &str = "{node}";
+
+/// Set of service nodes launched and owned by the runner, each run through a
+/// `ServerProc` from the same `just` args template with every "{node}" in it
+/// replaced by the node ID, so that a restarted node reuses its storage.
+#[derive(Debug)]
+pub struct ServiceNodes {
+    just_args: Vec<String>,
+    nodes: Vec<(String, Option<ServerProc>)>,
+}
+
+impl ServiceNodes {
+    /// Launch every node of given IDs using the `just` args template.
+    pub fn launch(node_ids: &[String], just_args: &[String]) -> Result<Self, RunnerError> {
+        let mut nodes = ServiceNodes {
+            just_args: just_args.to_vec(),
+            nodes: node_ids.iter().map(|id| (id.clone(), None)).collect(),
+        };
+        for i in 0..nodes.len() {
+            nodes.start(i)?;
+        }
+        Ok(nodes)
+    }
+
+    /// Number of nodes, up or down.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns true if there are no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// ID of node of given index.
+    pub fn node_id(&self, i: usize) -> &str {
+        &self.nodes[i].0
+    }
+
+    /// Returns true if node of given index is currently up.
+    pub fn is_up(&self, i: usize) -> bool {
+        self.nodes[i].1.is_some()
+    }
+
+    /// Process group of node of given index if it is up.
+    pub fn group(&self, i: usize) -> Option<ProcGroup> {
+        self.nodes[i].1.as_ref().map(|server| server.group())
+    }
+
+    /// Start (or restart) node of given index if it is down.
+    pub fn start(&mut self, i: usize) -> Result<(), RunnerError> {
+        if self.is_up(i) {
+            return Ok(());
+        }
+        let just_args: Vec<String> = self
+            .just_args
+            .iter()
+            .map(|arg| arg.replace(NODE_PLACEHOLDER, &self.nodes[i].0))
+            .collect();
+        let server = ServerProc::new(just_args.iter().map(|s| s.as_str()).collect())?;
+        self.nodes[i].1 = Some(server);
+        Ok(())
+    }
+
+    /// Crash node of given index with the signal if it is up.
+    pub fn crash(&mut self, i: usize, sig: Signal) -> Result<(), RunnerError> {
+        if let Some(server) = self.nodes[i].1.take() {
+            server.crash(sig)?;
+        }
+        Ok(())
+    }
+
+    /// Stop all nodes that are currently up, giving them `STOP_GRACE` in
+    /// total to exit gracefully upon SIGTERM before SIGKILL, consuming self.
+    pub fn stop(self) -> Result<(), RunnerError> {
+        let deadline = Instant::now() + STOP_GRACE;
+        let mut servers: Vec<ServerProc> = self.nodes.into_iter().filter_map(|(_, s)| s).collect();
+        // terminate all nodes first so that they shut down in parallel
+        for server in &servers {
+            server.group().signal(Signal::Term)?;
+            server.resume()?;
+        }
+        for server in &mut servers {
+            server.shutdown(deadline)?;
+        }
         Ok(())
     }
 }
 
-/// Wrapper handle to a KV client process.
+/// Time to wait for a client process to answer the encoding handshake, which
+/// may include building the client.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Wrapper handle to a KV client process. A process that has not been
+/// stopped is shut down when the handle is dropped.
 #[derive(Debug)]
 pub struct ClientProc {
     handle: Child,
+    reaped: bool,
+    encoding: Encoding,
+    stopped: Arc<AtomicBool>,
     _driver: JoinHandle<()>,
-    call_tx: mpsc::Sender<KvCall>,
-    resp_rx: mpsc::Receiver<KvResp>,
+    call_tx: mpsc::Sender<(Option<u64>, KvCall)>,
+    resp_rx: mpsc::Receiver<(Option<u64>, KvResp)>,
 }
 
 impl ClientProc {
     /// Run a client process using provided `just` recipe args, returning a
-    /// handle to it.
+    /// handle to it. The process is started in its own process group so that
+    /// signals reach the actual client under `just` as well.
     pub fn new(just_args: Vec<&str>) -> Result<ClientProc, RunnerError> {
+        Self::new_with_encoding(just_args, Encoding::Ascii)
+    }
+
+    /// Run a client process like `new()`, negotiating the given encoding of
+    /// keys and values with it first, which fails if the client does not
+    /// answer the handshake in time.
+    pub fn new_with_encoding(
+        just_args: Vec<&str>,
+        encoding: Encoding,
+    ) -> Result<ClientProc, RunnerError> {
         let mut handle = Command::new("just")
             .args(just_args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
+            .process_group(0)
             .spawn()?;
+        register_group(handle.id());
         let stdin = handle.stdin.take().unwrap();
         let stdout = handle.stdout.take().unwrap();
 
         // for each ClientProc initialized, there's a spawned thread for
-        // handling the stdin/out workload API to and from the client process
+        // handling the stdin/out workload API to and from the client process,
+        // which writes calls and leaves reading responses to another thread
+        // so that tagged calls can be pipelined
         let (call_tx, call_rx) = mpsc::channel();
         let (resp_tx, resp_rx) = mpsc::channel();
-        let driver = thread::spawn(move || Self::driver_thread(stdin, stdout, call_rx, resp_tx));
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let driver = {
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                Self::driver_thread(stdin, stdout, encoding, call_rx, resp_tx, ready_tx, stopped)
+            })
+        };
 
-        Ok(ClientProc {
+        // shut down by drop if the handshake fails
+        let client = ClientProc {
             handle,
+            reaped: false,
+            encoding,
+            stopped,
             _driver: driver,
             call_tx,
             resp_rx,
-        })
+        };
+        ready_rx.recv_timeout(HANDSHAKE_TIMEOUT).map_err(|_| {
+            RunnerError::Io(format!(
+                "client did not answer the '{:?}' encoding handshake",
+                encoding
+            ))
+        })??;
+        Ok(client)
+    }
+
+    /// Encoding of keys and values negotiated with the client process.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Process group of the client process.
+    pub fn group(&self) -> ProcGroup {
+        ProcGroup {
+            pgid: self.handle.id(),
+        }
+    }
+
+    /// Pause the whole process group of the client process; calls sent in the
+    /// meantime are only handled after it gets resumed.
+    pub fn pause(&self) -> Result<(), RunnerError> {
+        self.group().pause()
+    }
+
+    /// Resume the whole process group of the client process.
+    pub fn resume(&self) -> Result<(), RunnerError> {
+        self.group().resume()
     }
 
     /// Send a KV operation call to the client process.
     pub fn send_call(&self, call: KvCall) -> Result<(), RunnerError> {
-        self.call_tx.send(call)?;
+        self.call_tx.send((None, call))?;
+        Ok(())
+    }
+
+    /// Send a KV operation call tagged with a request ID to the client
+    /// process, without waiting for earlier tagged calls to be answered.
+    /// Tags of calls in flight at the same time should be distinct.
+    pub fn send_call_tagged(&self, tag: u64, call: KvCall) -> Result<(), RunnerError> {
+        self.call_tx.send((Some(tag), call))?;
         Ok(())
     }
 
     /// Wait for the next KV operation response from the client process.
     pub fn wait_resp(&mut self, timeout: Duration) -> Result<KvResp, RunnerError> {
-        let resp = self.resp_rx.recv_timeout(timeout)?;
+        let (_, resp) = self.resp_rx.recv_timeout(timeout)?;
         Ok(resp)
     }
 
-    /// Send stop to the client process (and kill it just to be sure),
-    /// consuming self.
+    /// Wait for the response to any of the tagged calls in flight, which may
+    /// come in a different order than the calls were sent, returning it along
+    /// with its tag.
+    pub fn wait_any_resp(&mut self, timeout: Duration) -> Result<(u64, KvResp), RunnerError> {
+        match self.resp_rx.recv_timeout(timeout)? {
+            (Some(tag), resp) => Ok((tag, resp)),
+            (None, _) => Err(RunnerError::Io(
+                "untagged response, expecting a request tag".into(),
+            )),
+        }
+    }
+
+    /// Send stop to the client process and give it `STOP_GRACE` to exit by
+    /// itself, then shut down its whole process group (with SIGTERM and
+    /// another grace period before SIGKILL, just to be sure), consuming self.
     pub fn stop(mut self) -> Result<(), RunnerError> {
-        self.call_tx.send(KvCall::Stop)?;
+        self.send_call(KvCall::Stop)?;
         let resp = self.wait_resp(Duration::from_secs(10))?;
         if !matches!(resp, KvResp::Stop) {
             return Err(RunnerError::Io(
@@ -107,52 +697,42 @@ impl ClientProc {
             ));
         }
 
-        self.handle.kill()?;
+        wait_exited(&self.handle, Instant::now() + STOP_GRACE);
+        shutdown_group(&mut self.handle, Instant::now() + STOP_GRACE)?;
+        self.reaped = true;
         Ok(())
     }
 
-    /// One iteration of the driver thread loop.
+    /// One iteration of the driver thread loop, writing one call.
     fn driver_iter(
         stdin: &mut ChildStdin,
-        stdout: &mut BufReader<ChildStdout>,
-        call_rx: &mpsc::Receiver<KvCall>,
-        resp_tx: &mpsc::Sender<KvResp>,
-        line: &mut String,
-        stopped: &mut bool,
+        call_rx: &mpsc::Receiver<(Option<u64>, KvCall)>,
+        encoding: Encoding,
+        stopped: &AtomicBool,
     ) -> Result<(), RunnerError> {
-        let call = call_rx.recv()?;
+        let (tag, call) = call_rx.recv()?;
         if let KvCall::Stop = call {
-            *stopped = true;
+            stopped.store(true, Ordering::SeqCst);
         }
-        call.into_write(stdin)?;
-
-        let resp = KvResp::from_read(stdout, line)?;
-        resp_tx.send(resp)?;
+        call.into_write(stdin, encoding, tag)?;
         Ok(())
     }
 
-    /// Dedicated stdin/out API thread function.
-    fn driver_thread(
-        mut stdin: ChildStdin,
-        stdout: ChildStdout,
-        call_rx: mpsc::Receiver<KvCall>,
-        resp_tx: mpsc::Sender<KvResp>,
+    /// Response reader thread function, forwarding responses in the order
+    /// they come out of the client process.
+    fn reader_thread(
+        mut stdout: BufReader<ChildStdout>,
+        encoding: Encoding,
+        resp_tx: mpsc::Sender<(Option<u64>, KvResp)>,
+        stopped: Arc<AtomicBool>,
     ) {
         READBUF.with(|buf| {
             let line = &mut buf.borrow_mut();
-            let mut stdout = BufReader::new(stdout);
-            let mut stopped = false;
-
             loop {
-                if let Err(err) = Self::driver_iter(
-                    &mut stdin,
-                    &mut stdout,
-                    &call_rx,
-                    &resp_tx,
-                    line,
-                    &mut stopped,
-                ) {
-                    if !stopped && !matches!(err, RunnerError::Chan(_)) {
+                let result = KvResp::from_read(&mut stdout, line, encoding)
+                    .and_then(|resp| Ok(resp_tx.send(resp)?));
+                if let Err(err) = result {
+                    if !stopped.load(Ordering::SeqCst) && !matches!(err, RunnerError::Chan(_)) {
                         eprintln!("Error in driver: {}", err);
                     }
                     break;
@@ -160,4 +740,73 @@ impl ClientProc {
             }
         })
     }
+
+    /// Send the encoding handshake line if the encoding needs one, and
+    /// expect the client to echo it back.
+    fn handshake(
+        stdin: &mut ChildStdin,
+        stdout: &mut BufReader<ChildStdout>,
+        line: &mut String,
+        encoding: Encoding,
+    ) -> Result<(), RunnerError> {
+        if let Some(handshake) = encoding.handshake_line() {
+            writeln!(stdin, "{}", handshake)?;
+            line.clear();
+            stdout.read_line(line)?;
+            if line.trim() != handshake {
+                return Err(RunnerError::Io(format!(
+                    "unexpected encoding handshake answer: {}",
+                    line.trim()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Dedicated stdin/out API thread function. The `stopped` flag is set
+    /// once the client process is expected to go away, silencing errors.
+    fn driver_thread(
+        mut stdin: ChildStdin,
+        stdout: ChildStdout,
+        encoding: Encoding,
+        call_rx: mpsc::Receiver<(Option<u64>, KvCall)>,
+        resp_tx: mpsc::Sender<(Option<u64>, KvResp)>,
+        ready_tx: mpsc::Sender<Result<(), RunnerError>>,
+        stopped: Arc<AtomicBool>,
+    ) {
+        let mut stdout = BufReader::new(stdout);
+        let ready = READBUF
+            .with(|buf| Self::handshake(&mut stdin, &mut stdout, &mut buf.borrow_mut(), encoding));
+        let failed = ready.is_err();
+        let _ = ready_tx.send(ready);
+        if failed {
+            return;
+        }
+
+        let reader = {
+            let stopped = stopped.clone();
+            thread::spawn(move || Self::reader_thread(stdout, encoding, resp_tx, stopped))
+        };
+        loop {
+            if let Err(err) = Self::driver_iter(&mut stdin, &call_rx, encoding, &stopped) {
+                if !stopped.load(Ordering::SeqCst) && !matches!(err, RunnerError::Chan(_)) {
+                    eprintln!("Error in driver: {}", err);
+                }
+                break;
+            }
+        }
+
+        // close stdin of the client process before waiting for its output
+        drop(stdin);
+        let _ = reader.join();
+    }
+}
+
+impl Drop for ClientProc {
+    fn drop(&mut self) {
+        if !self.reaped {
+            self.stopped.store(true, Ordering::SeqCst);
+            let _ = shutdown_group(&mut self.handle, Instant::now() + STOP_GRACE);
+        }
+    }
 }
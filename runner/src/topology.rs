@@ -0,0 +1,243 @@
+//! Typed node IDs and cluster topology address lists.
+//!
+//! Node IDs follow the `service` recipe conventions: the manager is named
+//! "m", or "m.x" for manager replica x, and servers are named "sx" for
+//! partition x, or "sx.y" for replica y of partition x. Address lists are
+//! comma-separated "<host>:<port>" entries; server lists are indexed first by
+//! partition then by replica, and manager lists by replica.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::RunnerError;
+
+/// Role of a service node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeRole {
+    Manager,
+    Server,
+}
+
+/// Typed ID of a service node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeId {
+    pub role: NodeRole,
+    /// Partition ID; always 0 for managers.
+    pub partition: usize,
+    /// Replica ID, if the ID has one.
+    pub replica: Option<usize>,
+}
+
+impl NodeId {
+    /// Replica ID, taken as 0 if the ID has none.
+    pub fn rep_id(&self) -> usize {
+        self.replica.unwrap_or(0)
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = RunnerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            RunnerError::Parse(format!(
+                "invalid node ID '{}', expecting 'm', 'm.<rep>', 's<part>', or 's<part>.<rep>'",
+                s
+            ))
+        };
+        let parse_id = |id: &str| -> Result<usize, RunnerError> {
+            if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            id.parse().map_err(|_| invalid())
+        };
+
+        let (base, replica) = match s.split_once('.') {
+            Some((base, rep)) => (base, Some(parse_id(rep)?)),
+            None => (s, None),
+        };
+        let (role, partition) = match base.split_at_checked(1) {
+            Some(("m", "")) => (NodeRole::Manager, 0),
+            Some(("s", part)) => (NodeRole::Server, parse_id(part)?),
+            _ => return Err(invalid()),
+        };
+        Ok(NodeId {
+            role,
+            partition,
+            replica,
+        })
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.role {
+            NodeRole::Manager => write!(f, "m")?,
+            NodeRole::Server => write!(f, "s{}", self.partition)?,
+        }
+        if let Some(replica) = self.replica {
+            write!(f, ".{}", replica)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a comma-separated address list, which may be empty.
+pub(crate) fn parse_addr_list(name: &str, list: &str) -> Result<Vec<String>, RunnerError> {
+    if list.is_empty() {
+        return Ok(vec![]);
+    }
+    list.split(',')
+        .map(|addr| {
+            let addr = addr.trim();
+            match addr.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                    Ok(addr.to_string())
+                }
+                _ => Err(RunnerError::Parse(format!(
+                    "invalid address '{}' in '{}', expecting '<host>:<port>'",
+                    addr, name
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// Address lists of all service nodes in a cluster.
+#[derive(Debug, Clone)]
+pub struct Topology {
+    pub managers: Vec<String>,
+    pub manager_p2ps: Vec<String>,
+    pub servers: Vec<String>,
+    pub server_p2ps: Vec<String>,
+    pub server_rf: usize,
+}
+
+impl Topology {
+    /// Parse and validate the comma-separated address lists, any of which
+    /// may be empty if not used. Non-empty internal replication lists must
+    /// match the public lists in length, and the servers list must hold
+    /// whole partitions.
+    pub fn new(
+        managers: &str,
+        manager_p2ps: &str,
+        servers: &str,
+        server_p2ps: &str,
+        server_rf: usize,
+    ) -> Result<Self, RunnerError> {
+        Topology {
+            managers: parse_addr_list("managers", managers)?,
+            manager_p2ps: parse_addr_list("manager_p2ps", manager_p2ps)?,
+            servers: parse_addr_list("servers", servers)?,
+            server_p2ps: parse_addr_list("server_p2ps", server_p2ps)?,
+            server_rf,
+        }
+        .validate()
+    }
+
+    /// Validate the lengths of the address lists against each other.
+    pub(crate) fn validate(self) -> Result<Self, RunnerError> {
+        if self.server_rf == 0 {
+            return Err(RunnerError::Parse("'server_rf' must be positive".into()));
+        }
+        if !self.servers.len().is_multiple_of(self.server_rf) {
+            return Err(RunnerError::Parse(format!(
+                "'servers' has {} addresses, not a multiple of 'server_rf' {}",
+                self.servers.len(),
+                self.server_rf
+            )));
+        }
+        for (name, p2ps, public) in [
+            ("manager_p2ps", &self.manager_p2ps, &self.managers),
+            ("server_p2ps", &self.server_p2ps, &self.servers),
+        ] {
+            if !p2ps.is_empty() && p2ps.len() != public.len() {
+                return Err(RunnerError::Parse(format!(
+                    "'{}' has {} addresses, but there are {} nodes",
+                    name,
+                    p2ps.len(),
+                    public.len()
+                )));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Number of server partitions.
+    pub fn num_partitions(&self) -> usize {
+        self.servers.len() / self.server_rf
+    }
+
+    /// Check that the node is part of the topology.
+    pub fn check_node(&self, node: &NodeId) -> Result<(), RunnerError> {
+        let (fits, size) = match node.role {
+            NodeRole::Manager => (node.rep_id() < self.managers.len(), self.managers.len()),
+            NodeRole::Server => (
+                node.partition < self.num_partitions() && node.rep_id() < self.server_rf,
+                self.servers.len(),
+            ),
+        };
+        if !fits {
+            return Err(RunnerError::Parse(format!(
+                "node {} is out of bounds of its {} listed addresses (server_rf {})",
+                node, size, self.server_rf
+            )));
+        }
+        Ok(())
+    }
+
+    /// Index of the node into its role's lists, and the range of indexes of
+    /// its replica group.
+    fn index(&self, node: &NodeId) -> Result<(usize, usize, usize), RunnerError> {
+        self.check_node(node)?;
+        Ok(match node.role {
+            NodeRole::Manager => (node.rep_id(), 0, self.managers.len()),
+            NodeRole::Server => {
+                let first = node.partition * self.server_rf;
+                (first + node.rep_id(), first, first + self.server_rf)
+            }
+        })
+    }
+
+    /// Role's public or internal replication list, which must not be empty.
+    fn list(&self, node: &NodeId, p2p: bool) -> Result<&[String], RunnerError> {
+        let (name, list) = match (node.role, p2p) {
+            (NodeRole::Manager, false) => ("managers", &self.managers),
+            (NodeRole::Manager, true) => ("manager_p2ps", &self.manager_p2ps),
+            (NodeRole::Server, false) => ("servers", &self.servers),
+            (NodeRole::Server, true) => ("server_p2ps", &self.server_p2ps),
+        };
+        if list.is_empty() {
+            return Err(RunnerError::Parse(format!(
+                "'{}' not given, needed by node {}",
+                name, node
+            )));
+        }
+        Ok(list)
+    }
+
+    /// Public listen address of the node.
+    pub fn api_addr(&self, node: &NodeId) -> Result<&str, RunnerError> {
+        let list = self.list(node, false)?;
+        let (idx, _, _) = self.index(node)?;
+        Ok(&list[idx])
+    }
+
+    /// Internal replication listen address of the node.
+    pub fn p2p_addr(&self, node: &NodeId) -> Result<&str, RunnerError> {
+        let list = self.list(node, true)?;
+        let (idx, _, _) = self.index(node)?;
+        Ok(&list[idx])
+    }
+
+    /// Internal replication addresses of the other replicas in the node's
+    /// replica group.
+    pub fn peers(&self, node: &NodeId) -> Result<Vec<&str>, RunnerError> {
+        let list = self.list(node, true)?;
+        let (idx, first, end) = self.index(node)?;
+        Ok((first..end)
+            .filter(|&i| i != idx)
+            .map(|i| list[i].as_str())
+            .collect())
+    }
+}